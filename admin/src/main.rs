@@ -0,0 +1,80 @@
+use bigdecimal::BigDecimal;
+use clap::{App, AppSettings, Arg};
+use futures::{SinkExt, StreamExt};
+use server::{AdminCommand, Levels, ToAdmin, TradingStatus, ADMIN_ADDR};
+use std::error::Error;
+use tokio::net::TcpStream;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+fn print_levels(title: &str, levels: Levels) {
+    println!("{}", title);
+    for ((digits, exponent), quantity) in levels {
+        println!("{:>10} @ {}", quantity, BigDecimal::new(digits, exponent));
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = App::new("admin")
+        .about("Operates a running server")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(App::new("clients").about("List connected clients"))
+        .subcommand(App::new("book").about("Dump every price level of the book"))
+        .subcommand(
+            App::new("cancel")
+                .about("Cancel all orders of a client")
+                .arg(Arg::new("client").required(true)),
+        )
+        .subcommand(App::new("halt").about("Halt trading"))
+        .subcommand(App::new("resume").about("Resume continuous trading"))
+        .subcommand(
+            App::new("status")
+                .about("Set the trading status: open, halted, auction or closed")
+                .arg(Arg::new("status").required(true)),
+        )
+        .subcommand(App::new("snapshot").about("Send a book snapshot to every client"))
+        .get_matches();
+
+    let command = match args.subcommand() {
+        Some(("clients", _)) => AdminCommand::ListClients,
+        Some(("book", _)) => AdminCommand::DumpBook,
+        Some(("cancel", cancel)) => {
+            AdminCommand::CancelAllOrders(cancel.value_of("client").unwrap().parse()?)
+        }
+        Some(("halt", _)) => AdminCommand::SetTradingStatus(TradingStatus::Halted),
+        Some(("resume", _)) => AdminCommand::SetTradingStatus(TradingStatus::Open),
+        Some(("status", status)) => {
+            AdminCommand::SetTradingStatus(status.value_of("status").unwrap().parse()?)
+        }
+        Some(("snapshot", _)) => AdminCommand::ForceSnapshot,
+        _ => unreachable!("A subcommand is required"),
+    };
+
+    let mut socket = Framed::new(
+        TcpStream::connect(ADMIN_ADDR).await?,
+        LengthDelimitedCodec::new(),
+    );
+    socket.send(bincode::serialize(&command)?.into()).await?;
+    let reply: ToAdmin = match socket.next().await {
+        Some(frame) => bincode::deserialize(&frame?)?,
+        None => return Err("Server closed the connection".into()),
+    };
+    match reply {
+        ToAdmin::Clients(clients) => {
+            println!("{} connected clients", clients.len());
+            for (client_id, orders) in clients {
+                println!("Client {}: {} resting orders", client_id, orders);
+            }
+        }
+        ToAdmin::Book(bids, asks) => {
+            print_levels("Bids", bids);
+            print_levels("Asks", asks);
+        }
+        ToAdmin::OrdersCancelled(client_id, orders) => {
+            println!("Cancelled {} orders of client {}", orders, client_id)
+        }
+        ToAdmin::TradingStatus(status) => println!("Trading status: {:?}", status),
+        ToAdmin::SnapshotSent(clients) => println!("Snapshot sent to {} clients", clients),
+    }
+    Ok(())
+}
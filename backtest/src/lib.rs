@@ -0,0 +1,768 @@
+//! Backtests of trading strategies with an `OrderBook` as the exchange
+//!
+//! Recorded market events are replayed into the book and the orders of a
+//! `Strategy` join it after a latency. Orders queue by their arrival in the
+//! book, so an order of the strategy is behind every order resting at its
+//! price when it arrived and moves up as those are cancelled or executed. A
+//! recorded order moving to another price or growing joins the back of the
+//! queue under a new id.
+//!
+//! The data is replayed as recorded, fills of the strategy come on top of it:
+//! - an execution of a recorded order also fills the orders of the strategy
+//!   ahead of it on its side, up to the executed quantity
+//! - a recorded order added across the price of orders of the strategy
+//!   trades with them
+//! - an order of the strategy crossing the book trades with the orders it
+//!   crosses, taking the quantity away from the recorded ones
+
+use bigdecimal::BigDecimal;
+use engine::{
+    analytics::rounded,
+    clock::{Clock, ManualClock},
+    Level2Query, Level2View, OrderBook, OrderId, Quantity, Side,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use source::{MarketEvent, MarketEventKind};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+pub mod source;
+
+/// Id the strategy gives its orders, the ids in the book are the backtest's
+pub type StrategyOrderId = u64;
+
+/// Time from the decision of the strategy to the arrival of its order or
+/// cancel at the exchange
+///
+/// Arrivals keep the order of the decisions, like messages on one connection.
+pub struct Latency {
+    base: Duration,
+    jitter: Duration,
+    rng: StdRng,
+}
+
+impl Latency {
+    pub fn new(base: Duration) -> Self {
+        Latency {
+            base,
+            jitter: Duration::default(),
+            rng: StdRng::seed_from_u64(0),
+        }
+    }
+
+    /// Adds up to `jitter` drawn uniformly from a generator seeded with `seed`
+    pub fn with_jitter(mut self, jitter: Duration, seed: u64) -> Self {
+        self.jitter = jitter;
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    fn sample(&mut self) -> Duration {
+        match self.jitter.as_nanos() as u64 {
+            0 => self.base,
+            jitter => self.base + Duration::from_nanos(self.rng.gen_range(0..=jitter)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// On its way to the exchange
+    Sent,
+    Open,
+    Filled,
+    Cancelled,
+}
+
+/// An order of the strategy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub side: Side,
+    pub price: BigDecimal,
+    pub quantity: Quantity,
+    pub filled: Quantity,
+    pub status: OrderStatus,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Liquidity {
+    /// The order was resting
+    Maker,
+    /// The order traded on arrival
+    Taker,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub order_id: StrategyOrderId,
+    pub time: Duration,
+    pub side: Side,
+    pub price: BigDecimal,
+    pub quantity: Quantity,
+    pub liquidity: Liquidity,
+}
+
+enum Request {
+    Place(StrategyOrderId),
+    Cancel(StrategyOrderId),
+}
+
+/// What a strategy sees and does when called
+pub struct Context<'a> {
+    now: Duration,
+    book: &'a OrderBook,
+    orders: &'a HashMap<StrategyOrderId, Order>,
+    position: i64,
+    next_order_id: &'a mut StrategyOrderId,
+    placed: &'a mut Vec<(StrategyOrderId, Order)>,
+    requests: &'a mut Vec<Request>,
+}
+
+impl<'a> Context<'a> {
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// The book at the exchange, with the orders of the strategy that arrived
+    pub fn book(&self) -> &OrderBook {
+        self.book
+    }
+
+    pub fn order(&self, order_id: StrategyOrderId) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+
+    /// Quantity bought minus quantity sold
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    /// Sends a limit order, which reaches the book after the latency
+    pub fn place(&mut self, side: Side, price: BigDecimal, quantity: Quantity) -> StrategyOrderId {
+        let order_id = *self.next_order_id;
+        *self.next_order_id += 1;
+        let order = Order {
+            side,
+            price,
+            quantity,
+            filled: 0,
+            status: OrderStatus::Sent,
+        };
+        self.placed.push((order_id, order));
+        self.requests.push(Request::Place(order_id));
+        order_id
+    }
+
+    /// Sends a cancel, which does nothing if the order is filled by the time
+    /// it arrives
+    pub fn cancel(&mut self, order_id: StrategyOrderId) {
+        self.requests.push(Request::Cancel(order_id));
+    }
+}
+
+pub trait Strategy {
+    /// Called after every market event with the book as it is after it
+    fn on_market(&mut self, context: &mut Context);
+
+    /// Called when an order of the strategy trades
+    fn on_fill(&mut self, _fill: &Fill, _context: &mut Context) {}
+}
+
+/// Replays market events and runs a strategy against them
+pub struct Backtest<S> {
+    strategy: S,
+    book: OrderBook,
+    clock: ManualClock,
+    latency: Latency,
+    /// Ids in the book of the recorded orders still in it
+    recorded: HashMap<u64, OrderId>,
+    /// Ids in the book of the open orders of the strategy and the reverse
+    book_ids: HashMap<StrategyOrderId, OrderId>,
+    strategy_ids: HashMap<OrderId, StrategyOrderId>,
+    next_book_id: OrderId,
+    orders: HashMap<StrategyOrderId, Order>,
+    next_order_id: StrategyOrderId,
+    /// Requests on their way to the exchange and their arrival time
+    in_flight: VecDeque<(Duration, Request)>,
+    fills: Vec<Fill>,
+    position: i64,
+    cash: BigDecimal,
+    events: u64,
+    cancels: u64,
+}
+
+impl<S: Strategy> Backtest<S> {
+    pub fn new(strategy: S, latency: Latency) -> Self {
+        let clock = ManualClock::default();
+        Backtest {
+            strategy,
+            book: OrderBook::with_clock(clock.clone()),
+            clock,
+            latency,
+            recorded: HashMap::new(),
+            book_ids: HashMap::new(),
+            strategy_ids: HashMap::new(),
+            next_book_id: 0,
+            orders: HashMap::new(),
+            next_order_id: 0,
+            in_flight: VecDeque::new(),
+            fills: vec![],
+            position: 0,
+            cash: BigDecimal::from(0),
+            events: 0,
+            cancels: 0,
+        }
+    }
+
+    pub fn strategy(&self) -> &S {
+        &self.strategy
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Replays events in the order of their times, requests of the strategy
+    /// arriving between two events are handled in between
+    pub fn run<I: IntoIterator<Item = io::Result<MarketEvent>>>(
+        &mut self,
+        events: I,
+    ) -> io::Result<()> {
+        for event in events {
+            let event = event?;
+            self.arrive_until(event.time);
+            self.clock.set(event.time.max(self.clock.now()));
+            self.events += 1;
+            let fills = self.apply(event.kind);
+            self.notify(fills);
+            self.call(|strategy, context| strategy.on_market(context));
+        }
+        Ok(())
+    }
+
+    /// Calls the strategy and sends the requests it made
+    fn call<F: FnOnce(&mut S, &mut Context)>(&mut self, f: F) {
+        let (mut placed, mut requests) = (vec![], vec![]);
+        let mut context = Context {
+            now: self.clock.now(),
+            book: &self.book,
+            orders: &self.orders,
+            position: self.position,
+            next_order_id: &mut self.next_order_id,
+            placed: &mut placed,
+            requests: &mut requests,
+        };
+        f(&mut self.strategy, &mut context);
+        self.orders.extend(placed);
+        for request in requests {
+            self.send(request);
+        }
+    }
+
+    fn send(&mut self, request: Request) {
+        let arrival = self.clock.now() + self.latency.sample();
+        let arrival = match self.in_flight.back() {
+            Some((last, _)) => arrival.max(*last),
+            None => arrival,
+        };
+        self.in_flight.push_back((arrival, request));
+    }
+
+    fn notify(&mut self, fills: Vec<Fill>) {
+        for fill in fills {
+            self.call(|strategy, context| strategy.on_fill(&fill, context));
+        }
+    }
+
+    /// Handles the requests arriving up to `time`, including those sent
+    /// while handling them
+    fn arrive_until(&mut self, time: Duration) {
+        while matches!(self.in_flight.front(), Some((arrival, _)) if *arrival <= time) {
+            let (arrival, request) = self.in_flight.pop_front().unwrap();
+            self.clock.set(arrival.max(self.clock.now()));
+            let fills = match request {
+                Request::Place(order_id) => self.arrive(order_id),
+                Request::Cancel(order_id) => {
+                    if let Some(book_id) = self.book_ids.remove(&order_id) {
+                        self.strategy_ids.remove(&book_id);
+                        self.book.on_cancel_order(book_id);
+                        self.orders.get_mut(&order_id).unwrap().status = OrderStatus::Cancelled;
+                        self.cancels += 1;
+                    }
+                    vec![]
+                }
+            };
+            self.notify(fills);
+        }
+    }
+
+    /// Adds an order of the strategy to the book and matches it
+    fn arrive(&mut self, order_id: StrategyOrderId) -> Vec<Fill> {
+        let book_id = self.next_book_id;
+        self.next_book_id += 1;
+        let order = self.orders.get_mut(&order_id).unwrap();
+        order.status = OrderStatus::Open;
+        self.book
+            .on_new_order(order.side, order.price.clone(), order.quantity, book_id);
+        self.book_ids.insert(order_id, book_id);
+        self.strategy_ids.insert(book_id, order_id);
+        let mut fills = vec![];
+        for trade in self.book.match_order(book_id) {
+            let resting = match trade.bid_order_id == book_id {
+                true => trade.ask_order_id,
+                false => trade.bid_order_id,
+            };
+            if let Some(&resting) = self.strategy_ids.get(&resting) {
+                fills.push(self.fill(resting, &trade.price, trade.quantity, Liquidity::Maker));
+            }
+            fills.push(self.fill(order_id, &trade.price, trade.quantity, Liquidity::Taker));
+        }
+        self.remove_recorded_filled();
+        fills
+    }
+
+    /// Records a trade of an order of the strategy the book already applied
+    fn fill(
+        &mut self,
+        order_id: StrategyOrderId,
+        price: &BigDecimal,
+        quantity: Quantity,
+        liquidity: Liquidity,
+    ) -> Fill {
+        let order = self.orders.get_mut(&order_id).unwrap();
+        order.filled += quantity;
+        if order.filled >= order.quantity {
+            order.status = OrderStatus::Filled;
+            if let Some(book_id) = self.book_ids.remove(&order_id) {
+                self.strategy_ids.remove(&book_id);
+            }
+        }
+        let notional = price * BigDecimal::from(quantity as u64);
+        match order.side {
+            Side::Bid => {
+                self.position += quantity as i64;
+                self.cash -= notional;
+            }
+            Side::Ask => {
+                self.position -= quantity as i64;
+                self.cash += notional;
+            }
+        }
+        let fill = Fill {
+            order_id,
+            time: self.clock.now(),
+            side: order.side,
+            price: price.clone(),
+            quantity,
+            liquidity,
+        };
+        self.fills.push(fill.clone());
+        fill
+    }
+
+    /// Forgets recorded orders the strategy took all the quantity of
+    fn remove_recorded_filled(&mut self) {
+        let book = &self.book;
+        self.recorded
+            .retain(|_, book_id| book.get_order(*book_id).is_some());
+    }
+
+    /// Open orders of the strategy on a side in the priority of the book
+    fn strategy_queue(&self, side: Side) -> Vec<(OrderId, BigDecimal, Quantity)> {
+        let mut queue: Vec<_> = self
+            .strategy_ids
+            .keys()
+            .filter_map(|book_id| {
+                let (order_side, price, quantity) = self.book.get_order(*book_id)?;
+                Some((*book_id, price.clone(), *quantity)).filter(|_| *order_side == side)
+            })
+            .collect();
+        queue.sort_by(|a, b| match side {
+            Side::Bid => b.1.cmp(&a.1).then(a.0.cmp(&b.0)),
+            Side::Ask => a.1.cmp(&b.1).then(a.0.cmp(&b.0)),
+        });
+        queue
+    }
+
+    fn apply(&mut self, kind: MarketEventKind) -> Vec<Fill> {
+        match kind {
+            MarketEventKind::Add {
+                order_id,
+                side,
+                price,
+                quantity,
+            } => self.add_recorded(order_id, side, price, quantity),
+            MarketEventKind::Reduce { order_id, quantity } => {
+                if let Some((_, price, remaining)) = self.recorded_order(order_id) {
+                    match remaining.saturating_sub(quantity) {
+                        0 => self.delete_recorded(order_id),
+                        remaining => {
+                            let book_id = self.recorded[&order_id];
+                            self.book.on_replace_order(price, remaining, book_id);
+                        }
+                    }
+                }
+                vec![]
+            }
+            MarketEventKind::Delete { order_id } => {
+                self.delete_recorded(order_id);
+                vec![]
+            }
+            MarketEventKind::Replace {
+                order_id,
+                new_order_id,
+                price,
+                quantity,
+            } => match self.recorded_order(order_id) {
+                // Only a smaller quantity at the same price keeps the place
+                Some((_, current, remaining))
+                    if new_order_id == order_id && price == current && quantity <= remaining =>
+                {
+                    let book_id = self.recorded[&order_id];
+                    self.book.on_replace_order(price, quantity, book_id);
+                    vec![]
+                }
+                Some((side, _, _)) => {
+                    self.delete_recorded(order_id);
+                    self.add_recorded(new_order_id, side, price, quantity)
+                }
+                None => vec![],
+            },
+            MarketEventKind::Execute { order_id, quantity } => {
+                self.execute_recorded(order_id, quantity)
+            }
+        }
+    }
+
+    fn recorded_order(&self, order_id: u64) -> Option<(Side, BigDecimal, Quantity)> {
+        self.book.get_order(*self.recorded.get(&order_id)?).cloned()
+    }
+
+    fn delete_recorded(&mut self, order_id: u64) {
+        if let Some(book_id) = self.recorded.remove(&order_id) {
+            if self.book.get_order(book_id).is_some() {
+                self.book.on_cancel_order(book_id);
+            }
+        }
+    }
+
+    /// Adds a recorded order, which trades with the orders of the strategy
+    /// it crosses
+    fn add_recorded(
+        &mut self,
+        order_id: u64,
+        side: Side,
+        price: BigDecimal,
+        quantity: Quantity,
+    ) -> Vec<Fill> {
+        let book_id = self.next_book_id;
+        self.next_book_id += 1;
+        self.book
+            .on_new_order(side, price.clone(), quantity, book_id);
+        self.recorded.insert(order_id, book_id);
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mut remaining = quantity;
+        let mut fills = vec![];
+        for (resting, resting_price, resting_quantity) in self.strategy_queue(opposite) {
+            let crosses = match side {
+                Side::Bid => resting_price <= price,
+                Side::Ask => resting_price >= price,
+            };
+            if remaining == 0 || !crosses {
+                break;
+            }
+            let quantity = resting_quantity.min(remaining);
+            self.book.on_trade(quantity, resting);
+            self.book.on_trade(quantity, book_id);
+            remaining -= quantity;
+            let resting = self.strategy_ids[&resting];
+            fills.push(self.fill(resting, &resting_price, quantity, Liquidity::Maker));
+        }
+        self.remove_recorded_filled();
+        fills
+    }
+
+    /// Executes a recorded order with what is left of the executed quantity
+    /// after filling the orders of the strategy ahead of it
+    fn execute_recorded(&mut self, order_id: u64, quantity: Quantity) -> Vec<Fill> {
+        let (side, price, remaining) = match self.recorded_order(order_id) {
+            Some(order) => order,
+            None => return vec![],
+        };
+        let book_id = self.recorded[&order_id];
+        let mut volume = quantity;
+        let mut fills = vec![];
+        for (ahead, ahead_price, ahead_quantity) in self.strategy_queue(side) {
+            let is_ahead = match side {
+                Side::Bid => ahead_price > price,
+                Side::Ask => ahead_price < price,
+            } || (ahead_price == price && ahead < book_id);
+            if volume == 0 || !is_ahead {
+                break;
+            }
+            let quantity = ahead_quantity.min(volume);
+            self.book.on_trade(quantity, ahead);
+            volume -= quantity;
+            let ahead = self.strategy_ids[&ahead];
+            fills.push(self.fill(ahead, &ahead_price, quantity, Liquidity::Maker));
+        }
+        if volume > 0 {
+            self.book.on_trade(volume.min(remaining), book_id);
+        }
+        self.remove_recorded_filled();
+        fills
+    }
+
+    /// Results so far, marked to the mid price of the book
+    pub fn report(&self) -> Report {
+        let mark = match (
+            self.book.levels(Side::Bid).next(),
+            self.book.levels(Side::Ask).next(),
+        ) {
+            (Some((bid, _)), Some((ask, _))) => Some((bid + ask) / BigDecimal::from(2)),
+            _ => None,
+        };
+        Report {
+            events: self.events,
+            orders: self.orders.len(),
+            cancels: self.cancels,
+            fills: self.fills.clone(),
+            position: self.position,
+            cash: self.cash.clone(),
+            mark,
+        }
+    }
+}
+
+/// Orders, fills and profit and loss of a backtest
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// Market events replayed
+    pub events: u64,
+    pub orders: usize,
+    /// Cancels that removed an order from the book
+    pub cancels: u64,
+    pub fills: Vec<Fill>,
+    pub position: i64,
+    /// Proceeds of sales minus the cost of purchases
+    pub cash: BigDecimal,
+    /// Mid price at the end, `None` if a side of the book was empty
+    pub mark: Option<BigDecimal>,
+}
+
+impl Report {
+    /// Cash plus the position valued at the mark
+    pub fn pnl(&self) -> Option<BigDecimal> {
+        let mark = self.mark.as_ref()?;
+        Some(&self.cash + mark * BigDecimal::from(self.position))
+    }
+
+    /// Quantity filled on a side with a liquidity
+    pub fn volume(&self, side: Side, liquidity: Liquidity) -> Quantity {
+        self.fills
+            .iter()
+            .filter(|fill| fill.side == side && fill.liquidity == liquidity)
+            .map(|fill| fill.quantity)
+            .sum()
+    }
+}
+
+/// Summary lines followed by a CSV table with a row per fill
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let optional = |value: Option<BigDecimal>| match value {
+            Some(value) => rounded(value).to_string(),
+            None => "-".to_string(),
+        };
+        writeln!(f, "events: {}", self.events)?;
+        writeln!(f, "orders: {}", self.orders)?;
+        writeln!(f, "cancels: {}", self.cancels)?;
+        writeln!(f, "fills: {}", self.fills.len())?;
+        for (side, name) in [(Side::Bid, "bought"), (Side::Ask, "sold")].iter() {
+            let maker = self.volume(*side, Liquidity::Maker);
+            let taker = self.volume(*side, Liquidity::Taker);
+            writeln!(
+                f,
+                "{}: {} ({} maker, {} taker)",
+                name,
+                maker + taker,
+                maker,
+                taker
+            )?;
+        }
+        writeln!(f, "position: {}", self.position)?;
+        writeln!(f, "cash: {}", rounded(self.cash.clone()))?;
+        writeln!(f, "mark: {}", optional(self.mark.clone()))?;
+        writeln!(f, "pnl: {}", optional(self.pnl()))?;
+        writeln!(f)?;
+        writeln!(f, "time,order_id,side,price,quantity,liquidity")?;
+        for fill in &self.fills {
+            writeln!(
+                f,
+                "{}.{:09},{},{:?},{},{},{:?}",
+                fill.time.as_secs(),
+                fill.time.subsec_nanos(),
+                fill.order_id,
+                fill.side,
+                fill.price,
+                fill.quantity,
+                fill.liquidity
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bids 4 once at a price and offers them at 99 once filled
+    struct Once {
+        price: BigDecimal,
+        sent: bool,
+    }
+
+    impl Strategy for Once {
+        fn on_market(&mut self, context: &mut Context) {
+            if !self.sent {
+                self.sent = true;
+                context.place(Side::Bid, self.price.clone(), 4);
+            }
+        }
+
+        fn on_fill(&mut self, fill: &Fill, context: &mut Context) {
+            let order = context.order(fill.order_id).unwrap();
+            if order.side == Side::Bid && order.status == OrderStatus::Filled {
+                let quantity = order.quantity;
+                context.place(Side::Ask, 99.into(), quantity);
+            }
+        }
+    }
+
+    fn event(millis: u64, kind: MarketEventKind) -> io::Result<MarketEvent> {
+        Ok(MarketEvent {
+            time: Duration::from_millis(millis),
+            kind,
+        })
+    }
+
+    fn add(order_id: u64, side: Side, price: u32, quantity: Quantity) -> MarketEventKind {
+        MarketEventKind::Add {
+            order_id,
+            side,
+            price: price.into(),
+            quantity,
+        }
+    }
+
+    fn execute(order_id: u64, quantity: Quantity) -> MarketEventKind {
+        MarketEventKind::Execute { order_id, quantity }
+    }
+
+    #[test]
+    fn queue_behind_resting_orders() {
+        let strategy = Once {
+            price: 100.into(),
+            sent: false,
+        };
+        let mut backtest = Backtest::new(strategy, Latency::new(Duration::from_millis(5)));
+        backtest
+            .run(vec![
+                event(0, add(1, Side::Bid, 100, 10)),
+                // The bid of the strategy arrives at 5, behind this order
+                event(3, add(2, Side::Bid, 100, 5)),
+                event(6, add(3, Side::Bid, 100, 5)),
+                event(8, execute(1, 10)),
+                event(9, execute(2, 5)),
+                // Trades through the bid of the strategy first, leaving 4 of this
+                event(10, execute(3, 5)),
+                // The offer sent on the fill arrives at 15 and trades with the rest
+                event(30, add(4, Side::Bid, 99, 1)),
+                event(35, add(5, Side::Ask, 102, 2)),
+            ])
+            .unwrap();
+        let report = backtest.report();
+        assert_eq!(report.fills.len(), 2);
+        assert_eq!(report.fills[0].time, Duration::from_millis(10));
+        assert_eq!(report.fills[0].quantity, 4);
+        let sale = &report.fills[1];
+        assert_eq!((sale.side, sale.quantity), (Side::Ask, 4));
+        assert_eq!(
+            (sale.price.clone(), sale.time),
+            (100.into(), Duration::from_millis(15))
+        );
+        assert_eq!(report.position, 0);
+        assert_eq!(report.cash, BigDecimal::from(0));
+        assert_eq!(report.mark, Some("100.5".parse().unwrap()));
+        assert_eq!(report.pnl(), Some(0.into()));
+    }
+
+    #[test]
+    fn moved_order_joins_the_back_of_the_queue() {
+        let strategy = Once {
+            price: 100.into(),
+            sent: false,
+        };
+        let mut backtest = Backtest::new(strategy, Latency::new(Duration::from_millis(5)));
+        backtest
+            .run(vec![
+                event(0, add(1, Side::Bid, 99, 5)),
+                // Moves behind the bid of the strategy that arrived at 5
+                event(
+                    10,
+                    MarketEventKind::Replace {
+                        order_id: 1,
+                        new_order_id: 1,
+                        price: 100.into(),
+                        quantity: 5,
+                    },
+                ),
+                event(12, execute(1, 5)),
+            ])
+            .unwrap();
+        let report = backtest.report();
+        assert_eq!(report.fills[0].time, Duration::from_millis(12));
+        assert_eq!(report.fills[0].quantity, 4);
+        assert_eq!(
+            backtest.book().top_levels(Side::Bid, 1),
+            vec![(100.into(), 4)]
+        );
+    }
+
+    #[test]
+    fn take_liquidity_on_arrival() {
+        let strategy = Once {
+            price: 102.into(),
+            sent: false,
+        };
+        let latency =
+            Latency::new(Duration::from_millis(1)).with_jitter(Duration::from_millis(1), 7);
+        let mut backtest = Backtest::new(strategy, latency);
+        backtest
+            .run(vec![
+                event(0, add(1, Side::Ask, 101, 3)),
+                // Crosses the rest of the bid
+                event(5, add(2, Side::Ask, 102, 2)),
+                // The strategy already took this order
+                event(6, execute(1, 3)),
+                event(20, add(3, Side::Bid, 100, 2)),
+            ])
+            .unwrap();
+        let report = backtest.report();
+        assert_eq!(report.volume(Side::Bid, Liquidity::Taker), 3);
+        assert_eq!(report.volume(Side::Bid, Liquidity::Maker), 1);
+        assert_eq!(report.volume(Side::Ask, Liquidity::Maker), 2);
+        assert_eq!(report.position, 2);
+        let asks: Vec<_> = backtest.book().top_levels(Side::Ask, 5);
+        assert_eq!(asks, vec![(99.into(), 2), (102.into(), 1)]);
+        assert!(report
+            .to_string()
+            .contains("bought: 4 (1 maker, 3 taker)\n"));
+    }
+}
@@ -0,0 +1,165 @@
+use backtest::{source, Backtest, Context, Latency, OrderStatus, Strategy, StrategyOrderId};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use engine::{Level2Query, Quantity, Side};
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Joins the best bid and offer with a fixed size as long as a fill would not
+/// take the position beyond a limit
+struct Quoter {
+    size: Quantity,
+    max_position: i64,
+    bid: Option<StrategyOrderId>,
+    ask: Option<StrategyOrderId>,
+}
+
+impl Quoter {
+    fn quote(&mut self, context: &mut Context, side: Side) {
+        let best = context
+            .book()
+            .levels(side)
+            .next()
+            .map(|(price, _)| price.clone());
+        let size = self.size as i64;
+        let allowed = match side {
+            Side::Bid => context.position() + size <= self.max_position,
+            Side::Ask => context.position() - size >= -self.max_position,
+        };
+        let current = match side {
+            Side::Bid => &mut self.bid,
+            Side::Ask => &mut self.ask,
+        };
+        if let Some(order_id) = *current {
+            let order = context.order(order_id).unwrap();
+            match order.status {
+                // Waits for the order to arrive before changing it
+                OrderStatus::Sent => return,
+                OrderStatus::Open if allowed && Some(&order.price) == best.as_ref() => return,
+                OrderStatus::Open => context.cancel(order_id),
+                OrderStatus::Filled | OrderStatus::Cancelled => {}
+            }
+            *current = None;
+        }
+        if let (true, Some(best)) = (allowed, best) {
+            *current = Some(context.place(side, best, self.size));
+        }
+    }
+}
+
+impl Strategy for Quoter {
+    fn on_market(&mut self, context: &mut Context) {
+        self.quote(context, Side::Bid);
+        self.quote(context, Side::Ask);
+    }
+}
+
+fn parse_arg<T: FromStr>(args: &ArgMatches, name: &str) -> io::Result<Option<T>> {
+    args.value_of(name)
+        .map(|value| {
+            value.parse::<T>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid value {} for {}", value, name),
+                )
+            })
+        })
+        .transpose()
+}
+
+fn main() -> io::Result<()> {
+    let args = App::new("backtest")
+        .about("Replays market data against a strategy quoting the best bid and offer")
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .takes_value(true)
+                .help("Journal of book events, one JSON object per line"),
+        )
+        .arg(
+            Arg::new("lobster")
+                .long("lobster")
+                .takes_value(true)
+                .help("LOBSTER message file"),
+        )
+        .arg(
+            Arg::new("itch")
+                .long("itch")
+                .takes_value(true)
+                .requires("symbol")
+                .help("NASDAQ TotalView-ITCH 5.0 file"),
+        )
+        .group(
+            ArgGroup::new("data")
+                .args(&["journal", "lobster", "itch"])
+                .required(true),
+        )
+        .arg(
+            Arg::new("symbol")
+                .long("symbol")
+                .takes_value(true)
+                .help("Stock of the ITCH file to replay"),
+        )
+        .arg(
+            Arg::new("latency")
+                .long("latency")
+                .takes_value(true)
+                .default_value("100")
+                .help("Microseconds from a decision to the arrival of the order"),
+        )
+        .arg(
+            Arg::new("jitter")
+                .long("jitter")
+                .takes_value(true)
+                .default_value("0")
+                .help("Random microseconds added to the latency, up to this many"),
+        )
+        .arg(
+            Arg::new("seed")
+                .long("seed")
+                .takes_value(true)
+                .default_value("0")
+                .help("Seed of the latency jitter"),
+        )
+        .arg(
+            Arg::new("size")
+                .long("size")
+                .takes_value(true)
+                .default_value("100")
+                .help("Quantity quoted on each side"),
+        )
+        .arg(
+            Arg::new("max-position")
+                .long("max-position")
+                .takes_value(true)
+                .default_value("1000")
+                .help("Largest position long or short"),
+        )
+        .get_matches();
+
+    let micros = |name| parse_arg::<u64>(&args, name).map(|value| value.unwrap_or_default());
+    let latency = Latency::new(Duration::from_micros(micros("latency")?)).with_jitter(
+        Duration::from_micros(micros("jitter")?),
+        parse_arg(&args, "seed")?.unwrap_or_default(),
+    );
+    let quoter = Quoter {
+        size: parse_arg(&args, "size")?.unwrap_or_default(),
+        max_position: parse_arg(&args, "max-position")?.unwrap_or_default(),
+        bid: None,
+        ask: None,
+    };
+    let mut backtest = Backtest::new(quoter, latency);
+    if let Some(path) = args.value_of("journal") {
+        backtest.run(source::journal(BufReader::new(File::open(path)?)))?;
+    } else if let Some(path) = args.value_of("lobster") {
+        backtest.run(source::lobster(BufReader::new(File::open(path)?)))?;
+    } else if let Some(path) = args.value_of("itch") {
+        let symbol = args.value_of("symbol").unwrap_or_default();
+        backtest.run(source::itch(BufReader::new(File::open(path)?), symbol))?;
+    }
+    print!("{}", backtest.report());
+    Ok(())
+}
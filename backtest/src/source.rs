@@ -0,0 +1,305 @@
+//! Market events read from recorded data
+//!
+//! Order ids are those of the data, the backtest gives every order its own id
+//! in the book. Events of orders the data never added, such as those resting
+//! before the start of a file or of other stocks, are ignored when replayed.
+
+use bigdecimal::BigDecimal;
+use engine::{
+    itch::{ItchReader, Message},
+    lobster::{EventType, LobsterMessage},
+    Event, EventKind, Quantity, Side,
+};
+use std::io::{self, BufRead, Read};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MarketEvent {
+    /// Offset from any fixed instant, e.g. midnight for LOBSTER and ITCH files
+    pub time: Duration,
+    pub kind: MarketEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MarketEventKind {
+    Add {
+        order_id: u64,
+        side: Side,
+        price: BigDecimal,
+        quantity: Quantity,
+    },
+    /// Part of an order was cancelled, it keeps its priority
+    Reduce {
+        order_id: u64,
+        quantity: Quantity,
+    },
+    Delete {
+        order_id: u64,
+    },
+    /// The order changed, keeping its priority only if it keeps its id and
+    /// price and doesn't grow
+    Replace {
+        order_id: u64,
+        new_order_id: u64,
+        price: BigDecimal,
+        quantity: Quantity,
+    },
+    /// A resting order traded with an incoming one
+    Execute {
+        order_id: u64,
+        quantity: Quantity,
+    },
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Events of a journal written one `engine::Event` as JSON per line
+///
+/// An order trading on arrival is added after the executions of the orders
+/// it traded with and only if some of it is left, like on a market data feed.
+pub fn journal<R: BufRead>(reader: R) -> Journal<R> {
+    Journal {
+        lines: reader.lines(),
+        incoming: None,
+        next: None,
+    }
+}
+
+pub struct Journal<R> {
+    lines: io::Lines<R>,
+    /// Order added by the last event, while its trades follow
+    incoming: Option<MarketEvent>,
+    /// Event read after the trades of the incoming order
+    next: Option<MarketEvent>,
+}
+
+impl<R> Journal<R> {
+    /// The incoming order, unless it was filled
+    fn take_incoming(&mut self) -> Option<MarketEvent> {
+        self.incoming
+            .take()
+            .filter(|event| !matches!(event.kind, MarketEventKind::Add { quantity: 0, .. }))
+    }
+}
+
+impl<R: BufRead> Iterator for Journal<R> {
+    type Item = io::Result<MarketEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.next.take() {
+                return Some(Ok(event));
+            }
+            let line = match self.lines.next() {
+                Some(line) => line,
+                None => return self.take_incoming().map(Ok),
+            };
+            let event: Event = match line.and_then(|line| {
+                serde_json::from_str(&line)
+                    .map_err(|e| invalid(format!("Invalid journal event: {}", e)))
+            }) {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+            let kind = match event.kind {
+                EventKind::Trade { order_id, quantity } => {
+                    if let Some(MarketEvent {
+                        kind:
+                            MarketEventKind::Add {
+                                order_id: incoming,
+                                quantity: remaining,
+                                ..
+                            },
+                        ..
+                    }) = &mut self.incoming
+                    {
+                        if *incoming == order_id as u64 {
+                            *remaining = remaining.saturating_sub(quantity);
+                            continue;
+                        }
+                    }
+                    let kind = MarketEventKind::Execute {
+                        order_id: order_id as u64,
+                        quantity,
+                    };
+                    return Some(Ok(MarketEvent {
+                        time: event.time,
+                        kind,
+                    }));
+                }
+                EventKind::NewOrder {
+                    order_id,
+                    side,
+                    price,
+                    quantity,
+                } => MarketEventKind::Add {
+                    order_id: order_id as u64,
+                    side,
+                    price,
+                    quantity,
+                },
+                EventKind::CancelOrder { order_id } | EventKind::ExpireOrder { order_id } => {
+                    MarketEventKind::Delete {
+                        order_id: order_id as u64,
+                    }
+                }
+                EventKind::ReplaceOrder {
+                    order_id,
+                    price,
+                    quantity,
+                } => MarketEventKind::Replace {
+                    order_id: order_id as u64,
+                    new_order_id: order_id as u64,
+                    price,
+                    quantity,
+                },
+            };
+            let event = MarketEvent {
+                time: event.time,
+                kind,
+            };
+            let incoming = self.take_incoming();
+            match event.kind {
+                MarketEventKind::Add { .. } => self.incoming = Some(event),
+                _ => self.next = Some(event),
+            }
+            if let Some(incoming) = incoming {
+                return Some(Ok(incoming));
+            }
+        }
+    }
+}
+
+/// Events of a LOBSTER message file, without hidden executions, cross trades
+/// and halts
+pub fn lobster<R: BufRead>(reader: R) -> impl Iterator<Item = io::Result<MarketEvent>> {
+    reader.lines().filter_map(|line| {
+        let message = match line.and_then(|line| LobsterMessage::parse(&line)) {
+            Ok(message) => message,
+            Err(e) => return Some(Err(e)),
+        };
+        let order_id = message.order_id as u64;
+        let kind = match message.event {
+            EventType::Submission => MarketEventKind::Add {
+                order_id,
+                side: message.side,
+                price: message.price,
+                quantity: message.size,
+            },
+            EventType::Cancellation => MarketEventKind::Reduce {
+                order_id,
+                quantity: message.size,
+            },
+            EventType::Deletion => MarketEventKind::Delete { order_id },
+            EventType::Execution => MarketEventKind::Execute {
+                order_id,
+                quantity: message.size,
+            },
+            _ => return None,
+        };
+        Some(Ok(MarketEvent {
+            time: Duration::from_nanos(message.time),
+            kind,
+        }))
+    })
+}
+
+/// Events of one stock of an ITCH 5.0 file
+pub fn itch<R: Read>(reader: R, symbol: &str) -> impl Iterator<Item = io::Result<MarketEvent>> {
+    let symbol = symbol.to_string();
+    ItchReader::new(reader).filter_map(move |message| {
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => return Some(Err(e)),
+        };
+        let kind = match message.message {
+            Message::AddOrder {
+                order_ref,
+                side,
+                shares,
+                stock,
+                price,
+            } if stock == symbol => MarketEventKind::Add {
+                order_id: order_ref,
+                side,
+                price,
+                quantity: shares as Quantity,
+            },
+            Message::OrderExecuted {
+                order_ref, shares, ..
+            }
+            | Message::OrderExecutedWithPrice {
+                order_ref, shares, ..
+            } => MarketEventKind::Execute {
+                order_id: order_ref,
+                quantity: shares as Quantity,
+            },
+            Message::OrderCancel { order_ref, shares } => MarketEventKind::Reduce {
+                order_id: order_ref,
+                quantity: shares as Quantity,
+            },
+            Message::OrderDelete { order_ref } => MarketEventKind::Delete {
+                order_id: order_ref,
+            },
+            Message::OrderReplace {
+                order_ref,
+                new_order_ref,
+                shares,
+                price,
+            } => MarketEventKind::Replace {
+                order_id: order_ref,
+                new_order_id: new_order_ref,
+                price,
+                quantity: shares as Quantity,
+            },
+            _ => return None,
+        };
+        Some(Ok(MarketEvent {
+            time: Duration::from_nanos(message.timestamp),
+            kind,
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::{clock::ManualClock, Level2View, OrderBook};
+    use std::io::Cursor;
+
+    #[test]
+    fn journal_of_a_book() {
+        let mut book = OrderBook::with_clock(ManualClock::default()).with_journal();
+        book.on_new_order(Side::Ask, 10.into(), 5, 1);
+        book.on_new_order(Side::Bid, 10.into(), 7, 2);
+        book.match_order(2);
+        book.on_cancel_order(2);
+        let lines: Vec<_> = book
+            .drain_journal()
+            .iter()
+            .map(|event| serde_json::to_string(event).unwrap())
+            .collect();
+        let events: Vec<_> = journal(Cursor::new(lines.join("\n")))
+            .map(|event| event.unwrap().kind)
+            .collect();
+        // The bid rests with what is left after trading with the ask
+        assert_eq!(
+            events[1..],
+            [
+                MarketEventKind::Execute {
+                    order_id: 1,
+                    quantity: 5
+                },
+                MarketEventKind::Add {
+                    order_id: 2,
+                    side: Side::Bid,
+                    price: 10.into(),
+                    quantity: 2
+                },
+                MarketEventKind::Delete { order_id: 2 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,560 @@
+//! Trading bots running against the server
+//!
+//! A `Session` keeps the book, the orders and the position of a `Bot` from
+//! the messages of the server and turns what the bot does into requests. The
+//! bot gives its orders ids of its own when placing them, requests for an
+//! order the server has not accepted yet are sent once it is.
+//!
+//! Requests carry a number the server sends back with its reply, which
+//! matches the reply to the request even when a rejection overtakes others.
+
+use bigdecimal::BigDecimal;
+use engine::{checksum::levels_checksum, DepthBook, Level2Query, Quantity, Side, TimeInForce};
+use futures::{SinkExt, StreamExt};
+use server::{OrderId, RequestId, ToClient, ToServer, TradingStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+pub mod market_maker;
+pub mod momentum;
+pub mod noise;
+pub mod replay;
+
+pub type BotOrderId = usize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderStatus {
+    /// Not accepted by the server yet
+    Sent,
+    Open,
+    Filled,
+    Cancelled,
+    Rejected,
+}
+
+/// An order of the bot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order {
+    pub side: Side,
+    pub price: BigDecimal,
+    /// Quantity left to trade
+    pub remaining: Quantity,
+    pub filled: Quantity,
+    pub status: OrderStatus,
+}
+
+impl Order {
+    /// Whether the order is or will be in the book
+    pub fn is_live(&self) -> bool {
+        matches!(self.status, OrderStatus::Sent | OrderStatus::Open)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub order_id: BotOrderId,
+    pub side: Side,
+    pub price: BigDecimal,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Request {
+    Place(BotOrderId),
+    Cancel(BotOrderId),
+    Replace(BotOrderId, BigDecimal, Quantity),
+}
+
+impl Request {
+    fn order_id(&self) -> BotOrderId {
+        match self {
+            Request::Place(order_id)
+            | Request::Cancel(order_id)
+            | Request::Replace(order_id, ..) => *order_id,
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    now: Duration,
+    book: DepthBook,
+    status: Option<TradingStatus>,
+    last_trade: Option<(BigDecimal, Quantity)>,
+    orders: BTreeMap<BotOrderId, Order>,
+    position: i64,
+    next_order_id: BotOrderId,
+    requests: Vec<Request>,
+}
+
+/// What a bot sees and does when called
+pub struct Context<'a> {
+    state: &'a mut State,
+}
+
+impl<'a> Context<'a> {
+    /// Time since the session started
+    pub fn now(&self) -> Duration {
+        self.state.now
+    }
+
+    /// The book with the orders of the bot the server accepted
+    pub fn book(&self) -> &DepthBook {
+        &self.state.book
+    }
+
+    pub fn status(&self) -> Option<TradingStatus> {
+        self.state.status
+    }
+
+    pub fn best(&self, side: Side) -> Option<&BigDecimal> {
+        self.state.book.levels(side).next().map(|(price, _)| price)
+    }
+
+    /// Halfway between the best bid and the best ask
+    pub fn mid_price(&self) -> Option<BigDecimal> {
+        Some((self.best(Side::Bid)? + self.best(Side::Ask)?) / BigDecimal::from(2))
+    }
+
+    /// Price and quantity of the latest trade of anyone
+    pub fn last_trade(&self) -> Option<&(BigDecimal, Quantity)> {
+        self.state.last_trade.as_ref()
+    }
+
+    pub fn order(&self, order_id: BotOrderId) -> Option<&Order> {
+        self.state.orders.get(&order_id)
+    }
+
+    /// Orders that are or will be in the book, oldest first
+    pub fn live_orders(&self) -> impl Iterator<Item = (BotOrderId, &Order)> {
+        self.state
+            .orders
+            .iter()
+            .filter(|(_, order)| order.is_live())
+            .map(|(order_id, order)| (*order_id, order))
+    }
+
+    /// Quantity bought minus quantity sold
+    pub fn position(&self) -> i64 {
+        self.state.position
+    }
+
+    /// Sends a limit order that rests until cancelled
+    pub fn place(&mut self, side: Side, price: BigDecimal, quantity: Quantity) -> BotOrderId {
+        let order_id = self.state.next_order_id;
+        self.state.next_order_id += 1;
+        let order = Order {
+            side,
+            price,
+            remaining: quantity,
+            filled: 0,
+            status: OrderStatus::Sent,
+        };
+        self.state.orders.insert(order_id, order);
+        self.state.requests.push(Request::Place(order_id));
+        order_id
+    }
+
+    /// Sends a cancel, which does nothing if the order is filled by the time
+    /// it arrives
+    pub fn cancel(&mut self, order_id: BotOrderId) {
+        self.state.requests.push(Request::Cancel(order_id));
+    }
+
+    /// Moves an order to a new price and quantity, at the back of the queue
+    pub fn replace(&mut self, order_id: BotOrderId, price: BigDecimal, quantity: Quantity) {
+        self.state
+            .requests
+            .push(Request::Replace(order_id, price, quantity));
+    }
+}
+
+pub trait Bot {
+    /// Called after every change to the book
+    fn on_book_update(&mut self, _context: &mut Context) {}
+
+    /// Called when an order of the bot trades
+    fn on_fill(&mut self, _fill: &Fill, _context: &mut Context) {}
+
+    /// Called at the interval the bot runs with
+    fn on_timer(&mut self, _context: &mut Context) {}
+}
+
+/// Price on the tick grid, rounded away from the other side of the book
+pub fn to_tick(price: &BigDecimal, tick: &BigDecimal, side: Side) -> BigDecimal {
+    let below = (price / tick).with_scale(0) * tick;
+    match side {
+        Side::Ask if &below < price => below + tick,
+        _ => below,
+    }
+}
+
+/// A bot with what the server told it about the book and its orders
+pub struct Session<B> {
+    bot: B,
+    state: State,
+    /// Whether the book was received, the bot is called from then on
+    synced: bool,
+    /// Requests sent and not answered yet by their number
+    awaiting: HashMap<RequestId, Request>,
+    request_counter: RequestId,
+    /// Requests waiting for the acceptance of their order
+    deferred: Vec<Request>,
+    server_ids: HashMap<BotOrderId, OrderId>,
+    bot_ids: HashMap<OrderId, BotOrderId>,
+}
+
+impl<B: Bot> Session<B> {
+    pub fn new(bot: B) -> Self {
+        Session {
+            bot,
+            state: State::default(),
+            synced: false,
+            awaiting: HashMap::new(),
+            request_counter: 0,
+            deferred: vec![],
+            server_ids: HashMap::new(),
+            bot_ids: HashMap::new(),
+        }
+    }
+
+    pub fn bot(&self) -> &B {
+        &self.bot
+    }
+
+    pub fn order(&self, order_id: BotOrderId) -> Option<&Order> {
+        self.state.orders.get(&order_id)
+    }
+
+    pub fn position(&self) -> i64 {
+        self.state.position
+    }
+
+    /// Applies a message of the server and returns the requests of the bot
+    pub fn on_message(&mut self, message: ToClient, now: Duration) -> Vec<ToServer> {
+        self.state.now = now;
+        let mut requests = vec![];
+        let (request, message) = match message {
+            ToClient::Reply(request_id, message) => (self.awaiting.remove(&request_id), *message),
+            message => (None, message),
+        };
+        match message {
+            ToClient::TradingStatus(status) => self.state.status = Some(status),
+            ToClient::Snapshot(bids, asks) => {
+                let mut book = DepthBook::default();
+                for (side, levels) in [(Side::Bid, bids), (Side::Ask, asks)] {
+                    for ((digits, scale), quantity) in levels {
+                        book.set_level(side, BigDecimal::new(digits, scale), quantity);
+                    }
+                }
+                self.state.book = book;
+                self.synced = true;
+                self.on_book_update();
+            }
+            ToClient::LatestDepth(side, quantity, (digits, scale)) => {
+                self.state
+                    .book
+                    .set_level(side, BigDecimal::new(digits, scale), quantity);
+                self.on_book_update();
+            }
+            ToClient::BookChecksum(checksum) => {
+                let book = &self.state.book;
+                if self.synced
+                    && levels_checksum(book.levels(Side::Bid), book.levels(Side::Ask)) != checksum
+                {
+                    requests.push(ToServer::RequestSnapshot);
+                }
+            }
+            ToClient::Trade((digits, scale), quantity) => {
+                self.state.last_trade = Some((BigDecimal::new(digits, scale), quantity));
+            }
+            ToClient::OrderAccepted(server_id) => {
+                if let Some(Request::Place(order_id)) = request {
+                    self.server_ids.insert(order_id, server_id);
+                    self.bot_ids.insert(server_id, order_id);
+                    if let Some(order) = self.state.orders.get_mut(&order_id) {
+                        order.status = OrderStatus::Open;
+                    }
+                    let (ready, deferred) = mem::take(&mut self.deferred)
+                        .into_iter()
+                        .partition(|request| request.order_id() == order_id);
+                    self.deferred = deferred;
+                    self.state.requests.extend::<Vec<_>>(ready);
+                }
+            }
+            ToClient::Fill(server_id, (digits, scale), quantity) => {
+                self.on_fill(server_id, BigDecimal::new(digits, scale), quantity)
+            }
+            ToClient::OrderCancelled(server_id) | ToClient::OrderExpired(server_id) => {
+                if let Some(&order_id) = self.bot_ids.get(&server_id) {
+                    self.finish(order_id, OrderStatus::Cancelled);
+                }
+            }
+            ToClient::OrderReplaced(old, new) => {
+                if let Some(order_id) = self.bot_ids.remove(&old) {
+                    if let Some(Request::Replace(_, price, quantity)) = request {
+                        if let Some(order) = self.state.orders.get_mut(&order_id) {
+                            order.price = price;
+                            order.remaining = quantity;
+                        }
+                    }
+                    self.server_ids.insert(order_id, new);
+                    self.bot_ids.insert(new, order_id);
+                }
+            }
+            ToClient::Rejected(_) => {
+                if let Some(Request::Place(order_id)) = request {
+                    self.finish(order_id, OrderStatus::Rejected);
+                }
+            }
+            _ => {}
+        }
+        requests.extend(self.requests());
+        requests
+    }
+
+    /// Calls the bot on a tick of its timer and returns its requests
+    pub fn on_timer(&mut self, now: Duration) -> Vec<ToServer> {
+        self.state.now = now;
+        if self.synced {
+            self.bot.on_timer(&mut Context {
+                state: &mut self.state,
+            });
+        }
+        self.requests()
+    }
+
+    fn on_book_update(&mut self) {
+        if self.synced {
+            self.bot.on_book_update(&mut Context {
+                state: &mut self.state,
+            });
+        }
+    }
+
+    fn on_fill(&mut self, server_id: OrderId, price: BigDecimal, quantity: Quantity) {
+        let order_id = match self.bot_ids.get(&server_id) {
+            Some(order_id) => *order_id,
+            None => return,
+        };
+        let order = match self.state.orders.get_mut(&order_id) {
+            Some(order) => order,
+            None => return,
+        };
+        order.remaining = order.remaining.saturating_sub(quantity);
+        order.filled += quantity;
+        let (side, remaining) = (order.side, order.remaining);
+        self.state.position += match side {
+            Side::Bid => quantity as i64,
+            Side::Ask => -(quantity as i64),
+        };
+        if remaining == 0 {
+            self.finish(order_id, OrderStatus::Filled);
+        }
+        let fill = Fill {
+            order_id,
+            side,
+            price,
+            quantity,
+        };
+        self.bot.on_fill(
+            &fill,
+            &mut Context {
+                state: &mut self.state,
+            },
+        );
+    }
+
+    /// Marks an order as out of the book for good
+    fn finish(&mut self, order_id: BotOrderId, status: OrderStatus) {
+        if let Some(order) = self.state.orders.get_mut(&order_id) {
+            order.status = status;
+        }
+        if let Some(server_id) = self.server_ids.remove(&order_id) {
+            self.bot_ids.remove(&server_id);
+        }
+        self.deferred
+            .retain(|request| request.order_id() != order_id);
+    }
+
+    /// Turns the requests of the bot into messages to the server
+    fn requests(&mut self) -> Vec<ToServer> {
+        let mut messages = vec![];
+        for request in mem::take(&mut self.state.requests) {
+            let order = match self.state.orders.get(&request.order_id()) {
+                Some(order) => order,
+                None => continue,
+            };
+            if !order.is_live() {
+                continue;
+            }
+            let server_id = self.server_ids.get(&request.order_id()).copied();
+            let message = match (&request, server_id) {
+                (Request::Place(_), _) => Some(ToServer::PlaceOrder(
+                    order.side,
+                    order.price.as_bigint_and_exponent(),
+                    order.remaining,
+                    TimeInForce::GoodTillCancel,
+                )),
+                (Request::Cancel(_), Some(server_id)) => Some(ToServer::CancelOrder(server_id)),
+                (Request::Replace(_, price, quantity), Some(server_id)) => Some(
+                    ToServer::ReplaceOrder(server_id, price.as_bigint_and_exponent(), *quantity),
+                ),
+                (_, None) => None,
+            };
+            match message {
+                Some(message) => {
+                    let request_id = self.request_counter;
+                    self.request_counter += 1;
+                    messages.push(ToServer::Request(request_id, Box::new(message)));
+                    self.awaiting.insert(request_id, request);
+                }
+                None => self.deferred.push(request),
+            }
+        }
+        messages
+    }
+}
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// Runs a bot against the server at `addr` until it disconnects, calling its
+/// timer every `interval`. The bot logs in with an account and password if
+/// given.
+pub async fn run<B: Bot>(
+    mut session: Session<B>,
+    addr: &str,
+    login: Option<(&str, &str)>,
+    interval: Duration,
+) -> io::Result<()> {
+    let mut socket = Framed::new(TcpStream::connect(addr).await?, LengthDelimitedCodec::new());
+    if let Some((account, password)) = login {
+        let login = ToServer::Login(account.to_string(), password.to_string());
+        socket
+            .send(bincode::serialize(&login).map_err(invalid)?.into())
+            .await?;
+    }
+    let start = Instant::now();
+    let mut timer = time::interval(interval);
+    loop {
+        let requests = tokio::select! {
+            frame = socket.next() => match frame {
+                Some(frame) => {
+                    let message = bincode::deserialize(&frame?).map_err(invalid)?;
+                    session.on_message(message, start.elapsed())
+                }
+                None => return Ok(()),
+            },
+            _ = timer.tick() => session.on_timer(start.elapsed()),
+        };
+        for request in requests {
+            socket
+                .send(bincode::serialize(&request).map_err(invalid)?.into())
+                .await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Requests without their numbers
+    pub(crate) fn unnumbered(requests: Vec<ToServer>) -> Vec<ToServer> {
+        requests
+            .into_iter()
+            .map(|request| match request {
+                ToServer::Request(_, request) => *request,
+                request => request,
+            })
+            .collect()
+    }
+
+    pub(crate) fn reply(request_id: RequestId, message: ToClient) -> ToClient {
+        ToClient::Reply(request_id, Box::new(message))
+    }
+
+    /// Places a bid on the first timer tick and cancels it right away
+    struct PlaceAndCancel;
+
+    impl Bot for PlaceAndCancel {
+        fn on_timer(&mut self, context: &mut Context) {
+            if context.order(0).is_none() {
+                let order_id = context.place(Side::Bid, 99.into(), 5);
+                context.cancel(order_id);
+            }
+        }
+    }
+
+    #[test]
+    fn requests_wait_for_acceptance() {
+        let mut session = Session::new(PlaceAndCancel);
+        // Nothing happens before the book arrives
+        assert!(session.on_timer(Duration::from_secs(1)).is_empty());
+        session.on_message(ToClient::Snapshot(vec![], vec![]), Duration::from_secs(1));
+        let requests = unnumbered(session.on_timer(Duration::from_secs(2)));
+        assert!(matches!(
+            requests[..],
+            [ToServer::PlaceOrder(Side::Bid, _, 5, _)]
+        ));
+        let requests =
+            session.on_message(reply(0, ToClient::OrderAccepted(7)), Duration::from_secs(2));
+        assert!(matches!(
+            unnumbered(requests)[..],
+            [ToServer::CancelOrder(7)]
+        ));
+        session.on_message(
+            ToClient::Fill(7, BigDecimal::from(99).as_bigint_and_exponent(), 2),
+            Duration::from_secs(2),
+        );
+        assert_eq!(session.position(), 2);
+        session.on_message(
+            reply(1, ToClient::OrderCancelled(7)),
+            Duration::from_secs(2),
+        );
+        let order = session.order(0).unwrap();
+        assert_eq!((order.filled, order.remaining), (2, 3));
+        assert_eq!(order.status, OrderStatus::Cancelled);
+    }
+
+    #[test]
+    fn rejected_orders() {
+        let mut session = Session::new(PlaceAndCancel);
+        session.on_message(ToClient::Snapshot(vec![], vec![]), Duration::from_secs(1));
+        session.on_timer(Duration::from_secs(1));
+        // A reply to another request doesn't reject the order
+        session.on_message(
+            reply(5, ToClient::Rejected(server::RejectReason::Throttled)),
+            Duration::from_secs(1),
+        );
+        assert_eq!(session.order(0).unwrap().status, OrderStatus::Sent);
+        let requests = session.on_message(
+            reply(0, ToClient::Rejected(server::RejectReason::MarketClosed)),
+            Duration::from_secs(1),
+        );
+        // The cancel waiting for the order is dropped with it
+        assert!(requests.is_empty());
+        assert_eq!(session.order(0).unwrap().status, OrderStatus::Rejected);
+    }
+
+    #[test]
+    fn ticks() {
+        let tick = BigDecimal::from(5) / BigDecimal::from(100);
+        let price = BigDecimal::from(10012) / BigDecimal::from(100);
+        assert_eq!(
+            to_tick(&price, &tick, Side::Bid),
+            BigDecimal::from(1001) / BigDecimal::from(10)
+        );
+        assert_eq!(
+            to_tick(&price, &tick, Side::Ask),
+            BigDecimal::from(10015) / BigDecimal::from(100)
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use backtest::source::{self, MarketEvent};
+use bigdecimal::BigDecimal;
+use bots::{
+    market_maker::MarketMaker, momentum::Momentum, noise::NoiseTrader, replay::Replay, Bot,
+    Context, Fill, Session,
+};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use engine::Side;
+use server::CLIENT_ADDR;
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Prints the fills of a bot
+struct Logged<B>(B);
+
+impl<B: Bot> Bot for Logged<B> {
+    fn on_book_update(&mut self, context: &mut Context) {
+        self.0.on_book_update(context);
+    }
+
+    fn on_fill(&mut self, fill: &Fill, context: &mut Context) {
+        let action = match fill.side {
+            Side::Bid => "Bought",
+            Side::Ask => "Sold",
+        };
+        println!(
+            "{} {} @ {}, position {}",
+            action,
+            fill.quantity,
+            fill.price,
+            context.position()
+        );
+        self.0.on_fill(fill, context);
+    }
+
+    fn on_timer(&mut self, context: &mut Context) {
+        self.0.on_timer(context);
+    }
+}
+
+fn parse_arg<T: FromStr>(args: &ArgMatches, name: &str) -> io::Result<T> {
+    let value = args.value_of(name).unwrap_or_default();
+    value.parse::<T>().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid value {} for {}", value, name),
+        )
+    })
+}
+
+fn events(args: &ArgMatches) -> io::Result<Box<dyn Iterator<Item = io::Result<MarketEvent>>>> {
+    Ok(if let Some(path) = args.value_of("journal") {
+        Box::new(source::journal(BufReader::new(File::open(path)?)))
+    } else if let Some(path) = args.value_of("lobster") {
+        Box::new(source::lobster(BufReader::new(File::open(path)?)))
+    } else {
+        let path = args.value_of("itch").unwrap_or_default();
+        let symbol = args.value_of("symbol").unwrap_or_default();
+        Box::new(source::itch(BufReader::new(File::open(path)?), symbol))
+    })
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let size = || {
+        Arg::new("size")
+            .long("size")
+            .takes_value(true)
+            .default_value("10")
+            .help("Quantity of each order")
+    };
+    let max_position = || {
+        Arg::new("max-position")
+            .long("max-position")
+            .takes_value(true)
+            .default_value("1000")
+            .help("Largest position long or short")
+    };
+    let tick = || {
+        Arg::new("tick")
+            .long("tick")
+            .takes_value(true)
+            .default_value("0.01")
+            .help("Prices are multiples of the tick")
+    };
+    let args = App::new("bots")
+        .about("Runs a trading bot against the server")
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::new("addr")
+                .long("addr")
+                .takes_value(true)
+                .default_value(CLIENT_ADDR)
+                .help("Address of the server"),
+        )
+        .arg(
+            Arg::new("account")
+                .long("account")
+                .takes_value(true)
+                .help("Account the bot trades for"),
+        )
+        .arg(
+            Arg::new("password")
+                .long("password")
+                .takes_value(true)
+                .default_value("")
+                .help("Password of the account"),
+        )
+        .arg(
+            Arg::new("interval")
+                .long("interval")
+                .takes_value(true)
+                .default_value("100")
+                .help("Milliseconds between two timer ticks of the bot"),
+        )
+        .subcommand(
+            App::new("market-maker")
+                .about("Quotes both sides around the mid price")
+                .arg(size())
+                .arg(max_position())
+                .arg(tick())
+                .arg(
+                    Arg::new("spread")
+                        .long("spread")
+                        .takes_value(true)
+                        .default_value("0.1")
+                        .help("Distance between the best bid and ask quoted"),
+                )
+                .arg(
+                    Arg::new("levels")
+                        .long("levels")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("Orders quoted on each side, a tick apart"),
+                )
+                .arg(
+                    Arg::new("price")
+                        .long("price")
+                        .takes_value(true)
+                        .help("Mid price quoted around while a side of the book is empty"),
+                ),
+        )
+        .subcommand(
+            App::new("noise")
+                .about("Places random orders around a random walk")
+                .arg(tick())
+                .arg(
+                    Arg::new("price")
+                        .long("price")
+                        .takes_value(true)
+                        .default_value("100")
+                        .help("Start of the random walk"),
+                )
+                .arg(
+                    Arg::new("width")
+                        .long("width")
+                        .takes_value(true)
+                        .default_value("5")
+                        .help("Ticks either side of the random walk orders are placed at"),
+                )
+                .arg(
+                    Arg::new("max-size")
+                        .long("max-size")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("Largest quantity of an order"),
+                )
+                .arg(
+                    Arg::new("rate")
+                        .long("rate")
+                        .takes_value(true)
+                        .default_value("0.5")
+                        .help("Chance of placing an order on a timer tick"),
+                )
+                .arg(
+                    Arg::new("max-orders")
+                        .long("max-orders")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("Resting orders kept, the oldest are cancelled"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("Seed of the random walk and orders, random by default"),
+                ),
+        )
+        .subcommand(
+            App::new("momentum")
+                .about("Takes liquidity in the direction the mid price moved")
+                .arg(size())
+                .arg(max_position())
+                .arg(
+                    Arg::new("window")
+                        .long("window")
+                        .takes_value(true)
+                        .default_value("10")
+                        .help("Timer ticks the move of the mid price is measured over"),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .takes_value(true)
+                        .default_value("0.05")
+                        .help("Smallest move traded on"),
+                ),
+        )
+        .subcommand(
+            App::new("replay")
+                .about("Places the orders of recorded market data")
+                .arg(
+                    Arg::new("journal")
+                        .long("journal")
+                        .takes_value(true)
+                        .help("Journal of book events, one JSON object per line"),
+                )
+                .arg(
+                    Arg::new("lobster")
+                        .long("lobster")
+                        .takes_value(true)
+                        .help("LOBSTER message file"),
+                )
+                .arg(
+                    Arg::new("itch")
+                        .long("itch")
+                        .takes_value(true)
+                        .requires("symbol")
+                        .help("NASDAQ TotalView-ITCH 5.0 file"),
+                )
+                .group(
+                    ArgGroup::new("data")
+                        .args(&["journal", "lobster", "itch"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("symbol")
+                        .long("symbol")
+                        .takes_value(true)
+                        .help("Stock of the ITCH file to replay"),
+                )
+                .arg(
+                    Arg::new("speed")
+                        .long("speed")
+                        .takes_value(true)
+                        .default_value("1")
+                        .help("How many times faster than recorded to replay"),
+                ),
+        )
+        .get_matches();
+
+    let addr = args.value_of("addr").unwrap_or(CLIENT_ADDR);
+    let account = args
+        .value_of("account")
+        .map(|account| (account, args.value_of("password").unwrap_or_default()));
+    let interval = Duration::from_millis(parse_arg(&args, "interval")?);
+    match args.subcommand() {
+        Some(("market-maker", args)) => {
+            let spread: BigDecimal = parse_arg(args, "spread")?;
+            let mut market_maker = MarketMaker::new(parse_arg(args, "size")?, spread)
+                .with_tick(parse_arg(args, "tick")?)
+                .with_levels(parse_arg(args, "levels")?)
+                .with_max_position(parse_arg(args, "max-position")?);
+            if args.is_present("price") {
+                market_maker = market_maker.with_reference(parse_arg(args, "price")?);
+            }
+            let session = Session::new(Logged(market_maker));
+            bots::run(session, addr, account, interval).await
+        }
+        Some(("noise", args)) => {
+            let seed = match args.value_of("seed") {
+                Some(_) => parse_arg(args, "seed")?,
+                None => rand::random(),
+            };
+            let noise = NoiseTrader::new(parse_arg(args, "price")?, seed)
+                .with_tick(parse_arg(args, "tick")?)
+                .with_width(parse_arg(args, "width")?)
+                .with_max_size(parse_arg(args, "max-size")?)
+                .with_rate(parse_arg(args, "rate")?)
+                .with_max_orders(parse_arg(args, "max-orders")?);
+            bots::run(Session::new(Logged(noise)), addr, account, interval).await
+        }
+        Some(("momentum", args)) => {
+            let momentum = Momentum::new(
+                parse_arg(args, "size")?,
+                parse_arg(args, "window")?,
+                parse_arg(args, "threshold")?,
+            )
+            .with_max_position(parse_arg(args, "max-position")?);
+            bots::run(Session::new(Logged(momentum)), addr, account, interval).await
+        }
+        Some(("replay", args)) => {
+            let replay = Replay::new(events(args)?).with_speed(parse_arg(args, "speed")?);
+            bots::run(Session::new(Logged(replay)), addr, account, interval).await
+        }
+        _ => Ok(()),
+    }
+}
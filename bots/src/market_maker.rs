@@ -0,0 +1,206 @@
+//! Symmetric market maker quoting around the mid price
+
+use crate::{to_tick, Bot, Context, OrderStatus};
+use bigdecimal::BigDecimal;
+use engine::{Quantity, Side};
+
+/// Quotes `levels` orders on each side, the best half the spread away from
+/// the mid price and the others a tick apart behind it. Only adds an order
+/// while filling it and every other open order on its side would keep the
+/// position within the limit.
+pub struct MarketMaker {
+    size: Quantity,
+    half_spread: BigDecimal,
+    tick: BigDecimal,
+    levels: usize,
+    max_position: i64,
+    /// Mid price while the book has no bid or no ask
+    reference: Option<BigDecimal>,
+}
+
+impl MarketMaker {
+    pub fn new(size: Quantity, spread: BigDecimal) -> Self {
+        MarketMaker {
+            size,
+            half_spread: spread / BigDecimal::from(2),
+            tick: BigDecimal::new(1.into(), 2),
+            levels: 1,
+            max_position: i64::MAX,
+            reference: None,
+        }
+    }
+
+    pub fn with_tick(mut self, tick: BigDecimal) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    pub fn with_levels(mut self, levels: usize) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn with_max_position(mut self, max_position: i64) -> Self {
+        self.max_position = max_position;
+        self
+    }
+
+    pub fn with_reference(mut self, reference: BigDecimal) -> Self {
+        self.reference = Some(reference);
+        self
+    }
+
+    /// Prices to quote on each side, best first
+    fn quotes(&self, context: &Context) -> Vec<(Side, BigDecimal)> {
+        let mid = match context.mid_price().or_else(|| self.reference.clone()) {
+            Some(mid) => mid,
+            None => return vec![],
+        };
+        let mut quotes = vec![];
+        for level in 0..self.levels {
+            let distance = &self.half_spread + &self.tick * BigDecimal::from(level as u64);
+            let bid = to_tick(&(&mid - &distance), &self.tick, Side::Bid);
+            if bid > BigDecimal::from(0) {
+                quotes.push((Side::Bid, bid));
+            }
+            quotes.push((
+                Side::Ask,
+                to_tick(&(&mid + &distance), &self.tick, Side::Ask),
+            ));
+        }
+        quotes
+    }
+
+    fn requote(&mut self, context: &mut Context) {
+        // The book doesn't show orders on their way yet
+        if context
+            .live_orders()
+            .any(|(_, order)| order.status == OrderStatus::Sent)
+        {
+            return;
+        }
+        let mut quotes = self.quotes(context);
+        let mut stale = vec![];
+        for (order_id, order) in context.live_orders() {
+            match quotes
+                .iter()
+                .position(|(side, price)| *side == order.side && *price == order.price)
+            {
+                Some(index) => {
+                    quotes.remove(index);
+                }
+                None => stale.push(order_id),
+            }
+        }
+        // Orders being cancelled can still trade until the cancel arrives
+        let (mut bids, mut asks) = (context.position(), context.position());
+        for (_, order) in context.live_orders() {
+            match order.side {
+                Side::Bid => bids = bids.saturating_add(order.remaining as i64),
+                Side::Ask => asks = asks.saturating_sub(order.remaining as i64),
+            }
+        }
+        for order_id in stale {
+            context.cancel(order_id);
+        }
+        let size = self.size as i64;
+        for (side, price) in quotes {
+            let exposure = match side {
+                Side::Bid => &mut bids,
+                Side::Ask => &mut asks,
+            };
+            let after = match side {
+                Side::Bid => exposure.saturating_add(size),
+                Side::Ask => exposure.saturating_sub(size),
+            };
+            if after.saturating_abs() <= self.max_position {
+                *exposure = after;
+                context.place(side, price, self.size);
+            }
+        }
+    }
+}
+
+impl Bot for MarketMaker {
+    fn on_book_update(&mut self, context: &mut Context) {
+        self.requote(context);
+    }
+
+    fn on_timer(&mut self, context: &mut Context) {
+        self.requote(context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{reply, unnumbered};
+    use crate::Session;
+    use server::{Levels, ToClient, ToServer};
+    use std::time::Duration;
+
+    fn level(price: u32, quantity: Quantity) -> Levels {
+        vec![(BigDecimal::from(price).as_bigint_and_exponent(), quantity)]
+    }
+
+    #[test]
+    fn quotes_around_mid() {
+        let market_maker = MarketMaker::new(10, 2.into())
+            .with_tick(1.into())
+            .with_levels(2)
+            .with_max_position(20);
+        let mut session = Session::new(market_maker);
+        let requests = unnumbered(session.on_message(
+            ToClient::Snapshot(level(98, 5), level(104, 5)),
+            Duration::from_secs(1),
+        ));
+        let prices: Vec<_> = requests
+            .iter()
+            .map(|request| match request {
+                ToServer::PlaceOrder(side, (digits, scale), 10, _) => {
+                    (*side, BigDecimal::new(digits.clone(), *scale))
+                }
+                request => panic!("Unexpected request {:?}", request),
+            })
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                (Side::Bid, 100.into()),
+                (Side::Ask, 102.into()),
+                (Side::Bid, 99.into()),
+                (Side::Ask, 103.into())
+            ]
+        );
+        for order_id in 0..4 {
+            let accepted = reply(order_id as u64, ToClient::OrderAccepted(order_id));
+            session.on_message(accepted, Duration::from_secs(1));
+        }
+        let requests = session.on_message(
+            ToClient::Fill(0, BigDecimal::from(100).as_bigint_and_exponent(), 10),
+            Duration::from_secs(1),
+        );
+        assert!(requests.is_empty());
+        // The filled bid isn't replaced while the other one could take the
+        // position beyond the limit
+        assert!(session.on_timer(Duration::from_secs(2)).is_empty());
+        let requests = session.on_message(
+            ToClient::Fill(2, BigDecimal::from(99).as_bigint_and_exponent(), 10),
+            Duration::from_secs(2),
+        );
+        assert!(requests.is_empty());
+        // Filling an ask brings the position back enough for one bid
+        session.on_message(
+            ToClient::Fill(1, BigDecimal::from(102).as_bigint_and_exponent(), 10),
+            Duration::from_secs(3),
+        );
+        let requests = unnumbered(session.on_timer(Duration::from_secs(3)));
+        assert!(matches!(
+            requests[..],
+            [
+                ToServer::PlaceOrder(Side::Bid, _, 10, _),
+                ToServer::PlaceOrder(Side::Ask, _, 10, _)
+            ]
+        ));
+    }
+}
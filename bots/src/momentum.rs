@@ -0,0 +1,81 @@
+//! Momentum trader taking liquidity in the direction of the market
+
+use crate::{Bot, Context};
+use bigdecimal::BigDecimal;
+use engine::{Quantity, Side};
+use std::collections::VecDeque;
+
+/// Samples the mid price on every timer tick and, once it moved by at least
+/// `threshold` over the last `window` ticks, buys at the best ask or sells at
+/// the best bid. It never rests: what an order did not fill is cancelled on
+/// the next tick.
+pub struct Momentum {
+    size: Quantity,
+    window: usize,
+    threshold: BigDecimal,
+    max_position: i64,
+    mids: VecDeque<BigDecimal>,
+}
+
+impl Momentum {
+    pub fn new(size: Quantity, window: usize, threshold: BigDecimal) -> Self {
+        Momentum {
+            size,
+            window: window.max(1),
+            threshold,
+            max_position: i64::MAX,
+            mids: VecDeque::new(),
+        }
+    }
+
+    pub fn with_max_position(mut self, max_position: i64) -> Self {
+        self.max_position = max_position;
+        self
+    }
+}
+
+impl Bot for Momentum {
+    fn on_timer(&mut self, context: &mut Context) {
+        let open: Vec<_> = context
+            .live_orders()
+            .map(|(order_id, _)| order_id)
+            .collect();
+        for order_id in open {
+            context.cancel(order_id);
+        }
+        let mid = match context.mid_price() {
+            Some(mid) => mid,
+            None => return,
+        };
+        self.mids.push_back(mid);
+        if self.mids.len() <= self.window {
+            return;
+        }
+        self.mids.pop_front();
+        let change = match (self.mids.front(), self.mids.back()) {
+            (Some(first), Some(last)) => last - first,
+            _ => return,
+        };
+        let size = self.size as i64;
+        let side = if change >= self.threshold
+            && context.position().saturating_add(size) <= self.max_position
+        {
+            Side::Bid
+        } else if change <= -self.threshold.clone()
+            && context.position().saturating_sub(size) >= -self.max_position
+        {
+            Side::Ask
+        } else {
+            return;
+        };
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        if let Some(price) = context.best(opposite).cloned() {
+            context.place(side, price, self.size);
+            // Waits for a new move before trading again
+            self.mids.clear();
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Noise trader placing random orders around a random walk
+
+use crate::{Bot, BotOrderId, Context};
+use bigdecimal::BigDecimal;
+use engine::{Quantity, Side};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::VecDeque;
+
+/// Moves its price a tick up or down on every timer tick and sometimes places
+/// an order of a random side and size up to `width` ticks either side of it,
+/// which trades when it crosses the book. The oldest orders are cancelled
+/// beyond `max_orders`.
+pub struct NoiseTrader {
+    price: BigDecimal,
+    tick: BigDecimal,
+    width: u32,
+    max_size: Quantity,
+    /// Chance of placing an order on a timer tick
+    rate: f64,
+    max_orders: usize,
+    orders: VecDeque<BotOrderId>,
+    rng: StdRng,
+}
+
+impl NoiseTrader {
+    pub fn new(price: BigDecimal, seed: u64) -> Self {
+        NoiseTrader {
+            price,
+            tick: BigDecimal::new(1.into(), 2),
+            width: 5,
+            max_size: 10,
+            rate: 0.5,
+            max_orders: 10,
+            orders: VecDeque::new(),
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn with_tick(mut self, tick: BigDecimal) -> Self {
+        self.tick = tick;
+        self
+    }
+
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.width = width;
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: Quantity) -> Self {
+        self.max_size = max_size.max(1);
+        self
+    }
+
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        self.rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_max_orders(mut self, max_orders: usize) -> Self {
+        self.max_orders = max_orders;
+        self
+    }
+
+    /// Where the random walk is
+    pub fn price(&self) -> &BigDecimal {
+        &self.price
+    }
+}
+
+impl Bot for NoiseTrader {
+    fn on_timer(&mut self, context: &mut Context) {
+        if self.rng.gen() {
+            self.price += &self.tick;
+        } else if self.price > self.tick {
+            self.price -= &self.tick;
+        }
+        if self.rng.gen_bool(self.rate) {
+            let side = if self.rng.gen() { Side::Bid } else { Side::Ask };
+            let width = self.width as i64;
+            let offset = BigDecimal::from(self.rng.gen_range(-width..=width)) * &self.tick;
+            let price = &self.price + offset;
+            if price > BigDecimal::from(0) {
+                let quantity = self.rng.gen_range(1..=self.max_size);
+                self.orders.push_back(context.place(side, price, quantity));
+            }
+        }
+        self.orders
+            .retain(|order_id| matches!(context.order(*order_id), Some(order) if order.is_live()));
+        while self.orders.len() > self.max_orders {
+            if let Some(order_id) = self.orders.pop_front() {
+                context.cancel(order_id);
+            }
+        }
+    }
+}
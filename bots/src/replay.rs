@@ -0,0 +1,172 @@
+//! Replay of recorded market data into the server
+
+use crate::{Bot, BotOrderId, Context};
+use backtest::source::{MarketEvent, MarketEventKind};
+use std::collections::HashMap;
+use std::io;
+use std::iter::Peekable;
+use std::time::Duration;
+
+/// Places, changes and cancels the orders of recorded market data at the
+/// pace they were recorded, times `speed`. Executions are not replayed, the
+/// server matches the orders again as they arrive. A partial cancel replaces
+/// the order with what is left of it, which loses its priority.
+pub struct Replay<I: Iterator> {
+    events: Peekable<I>,
+    speed: f64,
+    /// Time of the first event
+    start: Option<Duration>,
+    /// Orders of the bot by their id in the data
+    orders: HashMap<u64, BotOrderId>,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = io::Result<MarketEvent>>> Replay<I> {
+    pub fn new(events: I) -> Self {
+        Replay {
+            events: events.peekable(),
+            speed: 1.0,
+            start: None,
+            orders: HashMap::new(),
+            failed: false,
+        }
+    }
+
+    pub fn with_speed(mut self, speed: f64) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Whether every event was replayed or the data could not be read
+    pub fn is_done(&mut self) -> bool {
+        self.failed || self.events.peek().is_none()
+    }
+
+    fn replay(&mut self, event: MarketEvent, context: &mut Context) {
+        match event.kind {
+            MarketEventKind::Add {
+                order_id,
+                side,
+                price,
+                quantity,
+            } => {
+                let bot_order_id = context.place(side, price, quantity);
+                self.orders.insert(order_id, bot_order_id);
+            }
+            MarketEventKind::Reduce { order_id, quantity } => {
+                let bot_order_id = match self.orders.get(&order_id) {
+                    Some(bot_order_id) => *bot_order_id,
+                    None => return,
+                };
+                if let Some(order) = context.order(bot_order_id) {
+                    let (price, remaining) = (
+                        order.price.clone(),
+                        order.remaining.saturating_sub(quantity),
+                    );
+                    if remaining == 0 {
+                        self.orders.remove(&order_id);
+                        context.cancel(bot_order_id);
+                    } else {
+                        context.replace(bot_order_id, price, remaining);
+                    }
+                }
+            }
+            MarketEventKind::Delete { order_id } => {
+                if let Some(bot_order_id) = self.orders.remove(&order_id) {
+                    context.cancel(bot_order_id);
+                }
+            }
+            MarketEventKind::Replace {
+                order_id,
+                new_order_id,
+                price,
+                quantity,
+            } => {
+                if let Some(bot_order_id) = self.orders.remove(&order_id) {
+                    context.replace(bot_order_id, price, quantity);
+                    self.orders.insert(new_order_id, bot_order_id);
+                }
+            }
+            MarketEventKind::Execute { .. } => {}
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MarketEvent>>> Bot for Replay<I> {
+    fn on_timer(&mut self, context: &mut Context) {
+        if self.failed {
+            return;
+        }
+        loop {
+            let time = match self.events.peek() {
+                Some(Ok(event)) => event.time,
+                Some(Err(_)) => break,
+                None => return,
+            };
+            let start = *self.start.get_or_insert(time);
+            if time.saturating_sub(start) > context.now().mul_f64(self.speed) {
+                return;
+            }
+            if let Some(Ok(event)) = self.events.next() {
+                self.replay(event, context);
+            }
+        }
+        if let Some(Err(e)) = self.events.next() {
+            eprintln!("Stopped replaying: {}", e);
+            self.failed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{reply, unnumbered};
+    use crate::Session;
+    use engine::Side;
+    use server::{ToClient, ToServer};
+
+    fn event(secs: u64, kind: MarketEventKind) -> io::Result<MarketEvent> {
+        Ok(MarketEvent {
+            time: Duration::from_secs(secs),
+            kind,
+        })
+    }
+
+    #[test]
+    fn replays_at_the_recorded_pace() {
+        let events = vec![
+            event(
+                100,
+                MarketEventKind::Add {
+                    order_id: 42,
+                    side: Side::Ask,
+                    price: 101.into(),
+                    quantity: 10,
+                },
+            ),
+            event(
+                104,
+                MarketEventKind::Reduce {
+                    order_id: 42,
+                    quantity: 4,
+                },
+            ),
+            event(105, MarketEventKind::Delete { order_id: 42 }),
+        ];
+        let replay = Replay::new(events.into_iter()).with_speed(2.0);
+        let mut session = Session::new(replay);
+        session.on_message(ToClient::Snapshot(vec![], vec![]), Duration::from_secs(0));
+        let requests = unnumbered(session.on_timer(Duration::from_secs(1)));
+        assert!(matches!(
+            requests[..],
+            [ToServer::PlaceOrder(Side::Ask, _, 10, _)]
+        ));
+        session.on_message(reply(0, ToClient::OrderAccepted(3)), Duration::from_secs(1));
+        // Four seconds of data are replayed in two
+        let requests = unnumbered(session.on_timer(Duration::from_secs(2)));
+        assert!(matches!(requests[..], [ToServer::ReplaceOrder(3, _, 6)]));
+        let requests = unnumbered(session.on_timer(Duration::from_secs(3)));
+        assert!(matches!(requests[..], [ToServer::CancelOrder(3)]));
+    }
+}
@@ -1,15 +1,20 @@
 use bigdecimal::BigDecimal;
 use clap::{App, Arg};
-use engine::Side;
-use futures::StreamExt;
-use rand::prelude::*;
-use server::{ToClient, ToServer};
+use engine::{
+    analytics::Analytics,
+    bars::{Bar, BarKind},
+    checksum::levels_checksum,
+    queue::QueuePosition,
+    Side, TimeInForce,
+};
+use futures::{SinkExt, StreamExt};
+use server::{Levels, ToClient, ToServer, TradingStatus, CLIENT_ADDR};
 use std::{collections::BTreeMap, error::Error, io, str::FromStr};
 use termion::{event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen};
 use termion_input_tokio::TermReadAsync;
-use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::time::{self, Duration};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tui::{
     backend::TermionBackend,
     layout::{Constraint, Corner, Direction, Layout},
@@ -18,32 +23,61 @@ use tui::{
     widgets::{BarChart, Block, Borders, List, ListItem, Paragraph},
     Terminal,
 };
+/// Levels of each side the imbalance and average prices are computed over
+const ANALYTICS_LEVELS: usize = 5;
+/// Distance from the mid price in basis points of the depth shown
+const ANALYTICS_BPS: u32 = 10;
+/// Bars shown until another kind is requested
+const BAR_KIND: &str = "1m";
+/// Number of bars shown
+const BAR_LIMIT: usize = 20;
 const HELP_TEXT: &str = "Welcome! Here are the commands 
 To exit the application: <ESC>
 Place a sell order at asking price 10 and 2 quantities: Ask -p 10 -q 2 
 Place a buy order at bidding price 9 and 3 quantities: Bid -p 9.9 -q 3 
+Place an order expiring at the end of the day (or gtt:<ms>, gtd:<YYYY-MM-DD>): Bid -p 9.9 -q 3 -t day
 Get book depth: Depth -s Ask 
 Get Size for price level: Size -s Ask -p 12.2
 Get top of book: Top -s Ask
-Spam a lot of orders (type loco again to stop): loco
+Show bars of another kind (1s, 5m, 100t, 500v): Bars -k 5m
 ";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let mut socket = TcpStream::connect("127.0.0.1:8080").await?;
+    let mut socket = Framed::new(
+        TcpStream::connect(CLIENT_ADDR).await?,
+        LengthDelimitedCodec::new(),
+    );
     let stdout = io::stdout().into_raw_mode()?;
     let stdout = MouseTerminal::from(stdout);
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
+    let mut bar_kind: BarKind = BAR_KIND.parse()?;
+    let mut bars: Vec<Bar> = vec![];
+    socket
+        .send(
+            bincode::serialize(&ToServer::GetBars(bar_kind, BAR_LIMIT))
+                .unwrap()
+                .into(),
+        )
+        .await?;
     let mut keys_stream = tokio::io::stdin().keys_stream();
     let mut to_client_events = vec![];
     let mut input = String::new();
-    let mut is_loco = false;
-    let mut loco_timer = time::interval(Duration::from_millis(20));
     let mut bids = BTreeMap::new();
     let mut asks = BTreeMap::new();
-    let mut rng = thread_rng();
+    let mut status = TradingStatus::Open;
+    let mut indicative_uncross: Option<(BigDecimal, usize)> = None;
+    let mut last_trade: Option<(BigDecimal, usize)> = None;
+    // Snapshots requested because the book differed from the server's checksum
+    let mut resyncs = 0;
+    // Checksums keep mismatching until the snapshot arrives
+    let mut snapshot_requested = false;
+    let mut analytics: Option<Analytics> = None;
+    let mut analytics_timer = time::interval(Duration::from_secs(1));
+    // Own resting orders, refreshed along with the analytics
+    let mut queue_positions: Vec<QueuePosition> = vec![];
 
     loop {
         terminal.draw(|f| {
@@ -54,14 +88,40 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             let left_side = Layout::default()
                 .direction(Direction::Vertical)
-                .constraints([Constraint::Percentage(80), Constraint::Percentage(20)])
+                .constraints([
+                    Constraint::Percentage(50),
+                    Constraint::Percentage(30),
+                    Constraint::Percentage(20),
+                ])
                 .split(chunks[0]);
             f.render_widget(Paragraph::new(HELP_TEXT), left_side[0]);
 
+            let orders: Vec<ListItem> = queue_positions
+                .iter()
+                .map(|position| {
+                    let time_to_fill = position
+                        .time_to_fill
+                        .map_or("-".to_string(), |time| format!("{}s", time.as_secs()));
+                    ListItem::new(format!(
+                        "#{} {:?} {} @ {}: {} ahead of {}, fill in {}",
+                        position.order_id,
+                        position.side,
+                        position.quantity,
+                        position.price,
+                        position.ahead,
+                        position.level_size,
+                        time_to_fill
+                    ))
+                })
+                .collect();
+            let orders_list =
+                List::new(orders).block(Block::default().borders(Borders::ALL).title("Orders"));
+            f.render_widget(orders_list, left_side[1]);
+
             let paragraph = Paragraph::new(input.as_ref())
                 .style(Style::default())
                 .block(Block::default().borders(Borders::ALL).title("Input"));
-            f.render_widget(paragraph, left_side[1]);
+            f.render_widget(paragraph, left_side[2]);
 
             let right_side = Layout::default()
                 .direction(Direction::Vertical)
@@ -109,6 +169,83 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
             f.render_widget(barchart_asks, bar_charts_area[2]);
 
+            let mut market = vec![Spans::from(format!("Status: {:?}", status))];
+            if let Some((price, quantity)) = &last_trade {
+                market.push(Spans::from(format!("Last trade: {} @ {}", quantity, price)));
+            }
+            if let (TradingStatus::Auction, Some((price, volume))) = (status, &indicative_uncross) {
+                market.push(Spans::from(format!("Indicative: {} @ {}", volume, price)));
+            }
+            if resyncs > 0 {
+                market.push(Spans::from(format!("Resyncs: {}", resyncs)));
+            }
+            if let Some(analytics) = &analytics {
+                let show = |value: &Option<BigDecimal>| match value {
+                    Some(value) => value.to_string(),
+                    None => "-".to_string(),
+                };
+                let show_depth =
+                    |value: Option<usize>| value.map_or("-".to_string(), |value| value.to_string());
+                market.push(Spans::from(format!("Spread: {}", show(&analytics.spread))));
+                market.push(Spans::from(format!("Mid: {}", show(&analytics.mid_price))));
+                market.push(Spans::from(format!(
+                    "Microprice: {}",
+                    show(&analytics.microprice)
+                )));
+                market.push(Spans::from(format!(
+                    "Imbalance: {}",
+                    show(&analytics.imbalance)
+                )));
+                market.push(Spans::from(format!(
+                    "Depth {}bps: {} / {}",
+                    ANALYTICS_BPS,
+                    show_depth(analytics.bid_depth),
+                    show_depth(analytics.ask_depth)
+                )));
+                market.push(Spans::from(format!(
+                    "Avg price: {} / {}",
+                    show(&analytics.bid_average_price),
+                    show(&analytics.ask_average_price)
+                )));
+            }
+            let market = Paragraph::new(market)
+                .block(Block::default().title("Market").borders(Borders::ALL));
+            f.render_widget(market, bar_charts_area[1]);
+
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(right_side[1]);
+
+            let bar_items: Vec<ListItem> = bars
+                .iter()
+                .rev()
+                .map(|bar| {
+                    let secs = bar.start.as_secs() % (24 * 3600);
+                    ListItem::new(format!(
+                        "{:02}:{:02}:{:02} O {} H {} L {} C {} V {} VWAP {} N {}",
+                        secs / 3600,
+                        secs / 60 % 60,
+                        secs % 60,
+                        bar.open,
+                        bar.high,
+                        bar.low,
+                        bar.close,
+                        bar.volume,
+                        bar.vwap,
+                        bar.trades
+                    ))
+                })
+                .collect();
+            let bars_list = List::new(bar_items)
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(format!("Bars {}", bar_kind)),
+                )
+                .start_corner(Corner::BottomLeft);
+            f.render_widget(bars_list, bottom[0]);
+
             let events: Vec<ListItem> = to_client_events
                 .iter()
                 .rev()
@@ -120,33 +257,83 @@ async fn main() -> Result<(), Box<dyn Error>> {
             let events_list = List::new(events)
                 .block(Block::default().borders(Borders::ALL).title("Events"))
                 .start_corner(Corner::BottomLeft);
-            f.render_widget(events_list, right_side[1]);
+            f.render_widget(events_list, bottom[1]);
         })?;
 
         tokio::select! {
-            _ = socket.readable() => {
-                let mut buf = [0; 1024];
-                let n = match socket.try_read(&mut buf){
-
-                    Ok(n) if n == 0 => break,
-                    Ok(n) => n,
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
-                    Err(e) => {
+            frame = socket.next() => {
+                let frame = match frame {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(e)) => {
                         println!("failed to read from socket; err = {:?}", e);
                         break;
                     }
+                    None => break,
                 };
-                let to_client_msg: ToClient = bincode::deserialize_from(&buf[0..n]).unwrap();
+                let to_client_msg: ToClient = bincode::deserialize(&frame).unwrap();
                 match to_client_msg.clone(){
                     ToClient::LatestDepth(side,quantity,(digits,exponent)) => {
                         let bhm = match side{
                             Side::Ask => &mut asks,
                             Side::Bid => &mut bids,
                         };
-                        let entry = bhm.entry(BigDecimal::new(digits, exponent)).or_insert(0);
-                        *entry = quantity;
+                        let price = BigDecimal::new(digits, exponent);
+                        if quantity == 0 {
+                            bhm.remove(&price);
+                        } else {
+                            bhm.insert(price, quantity);
+                        }
+                    },
+                    ToClient::Trade((digits,exponent),quantity) => {
+                        last_trade = Some((BigDecimal::new(digits, exponent), quantity));
+                    },
+                    ToClient::TradingStatus(new_status) => status = new_status,
+                    ToClient::IndicativeUncross(uncross) => {
+                        indicative_uncross = uncross.map(|((digits,exponent),volume)| (BigDecimal::new(digits, exponent), volume));
+                    },
+                    ToClient::Snapshot(snapshot_bids, snapshot_asks) => {
+                        let levels = |levels: Levels| levels
+                            .into_iter()
+                            .map(|((digits, exponent), quantity)| (BigDecimal::new(digits, exponent), quantity))
+                            .collect();
+                        bids = levels(snapshot_bids);
+                        asks = levels(snapshot_asks);
+                        snapshot_requested = false;
+                        continue;
+                    },
+                    ToClient::Analytics(latest) => {
+                        analytics = Some((*latest).into());
+                        continue;
+                    },
+                    ToClient::QueuePositions(positions) => {
+                        queue_positions = positions.into_iter().map(Into::into).collect();
+                        continue;
+                    },
+                    ToClient::Bars(kind, history) => {
+                        bar_kind = kind;
+                        bars = history.into_iter().map(Into::into).collect();
+                        continue;
+                    },
+                    ToClient::BarUpdate(kind, bar) => {
+                        if kind == bar_kind {
+                            let bar = Bar::from(*bar);
+                            match bars.last_mut() {
+                                Some(last) if last.number == bar.number => *last = bar,
+                                _ => bars.push(bar),
+                            }
+                            if bars.len() > BAR_LIMIT {
+                                bars.remove(0);
+                            }
+                        }
+                        continue;
+                    },
+                    ToClient::BookChecksum(checksum) => {
+                        if !snapshot_requested && levels_checksum(bids.iter().rev(), asks.iter()) != checksum {
+                            snapshot_requested = true;
+                            resyncs += 1;
+                            socket.send(bincode::serialize(&ToServer::RequestSnapshot).unwrap().into()).await.expect("Could not send to server");
+                        }
+                        continue;
                     },
                     _ => ()
                 }
@@ -160,11 +347,8 @@ async fn main() -> Result<(), Box<dyn Error>> {
                             input.pop();
                         },
                         Key::Char('\n') => {
-                            if input == "loco"{
-                                is_loco = !is_loco;
-                            }
                             if let Some(cmd) = try_parse_into_command(&input){
-                                socket.write(&bincode::serialize(&cmd).unwrap()).await.expect("Could not send to server");
+                                socket.send(bincode::serialize(&cmd).unwrap().into()).await.expect("Could not send to server");
                             }
                             input.clear();
                         },
@@ -173,22 +357,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
                     };
                 }
             }
-            _ = loco_timer.tick() => {
-                if is_loco {
-                    let side = match rng.gen() {
-                        true => Side::Ask,
-                        false => Side::Bid
-                    };
-                    let price = match side {
-                        Side::Ask => rng.gen_range(101..103),
-                        Side::Bid => rng.gen_range(98..100)
-                    };
-                    let (digits,exponents) = BigDecimal::from(price).as_bigint_and_exponent();
-                    let quantity = rng.gen_range(1..150);
-                    socket.write(&bincode::serialize(&ToServer::PlaceOrder(side,(digits,exponents),quantity)).unwrap()).await;
-                }
+            _ = analytics_timer.tick() => {
+                let bps = BigDecimal::from(ANALYTICS_BPS).as_bigint_and_exponent();
+                socket.send(bincode::serialize(&ToServer::GetAnalytics(ANALYTICS_LEVELS, bps)).unwrap().into()).await.expect("Could not send to server");
+                socket.send(bincode::serialize(&ToServer::GetQueuePositions).unwrap().into()).await.expect("Could not send to server");
             }
-
         }
     }
     Ok(())
@@ -199,8 +372,23 @@ fn try_parse_into_command(input: &str) -> Option<ToServer> {
         .arg(Arg::new("command").requires_ifs(&[("top", "side"), ("depth", "side")]))
         .arg(Arg::new("side").short('s').takes_value(true))
         .arg(Arg::new("price").short('p').takes_value(true))
-        .arg(Arg::new("quantity").short('q').takes_value(true));
+        .arg(Arg::new("quantity").short('q').takes_value(true))
+        .arg(Arg::new("kind").short('k').takes_value(true))
+        .arg(Arg::new("time_in_force").short('t').takes_value(true));
     if let Ok(parsed) = cmd_parser.try_get_matches_from(input.split(' ')) {
+        if let (Some("bars"), Some(Ok(kind))) = (
+            parsed
+                .value_of("command")
+                .map(|c| c.to_lowercase())
+                .as_deref(),
+            parsed.value_of("kind").map(str::parse::<BarKind>),
+        ) {
+            return Some(ToServer::GetBars(kind, BAR_LIMIT));
+        }
+        let time_in_force = match parsed.value_of("time_in_force") {
+            Some(time_in_force) => time_in_force.parse::<TimeInForce>().ok()?,
+            None => TimeInForce::GoodTillCancel,
+        };
         return match (
             parsed
                 .value_of("command")
@@ -208,32 +396,40 @@ fn try_parse_into_command(input: &str) -> Option<ToServer> {
                 .as_deref(),
             parsed
                 .value_of("price")
-                .map(|p| BigDecimal::from_str(p).ok())
-                .flatten(),
+                .and_then(|p| BigDecimal::from_str(p).ok()),
             parsed
                 .value_of("quantity")
-                .map(|q| q.parse::<usize>().ok())
-                .flatten(),
+                .and_then(|q| q.parse::<usize>().ok()),
             parsed
                 .value_of("side")
-                .map(|s| match s.to_lowercase().as_ref() {
+                .and_then(|s| match s.to_lowercase().as_ref() {
                     "b" | "bid" => Some(engine::Side::Bid),
                     "a" | "ask" => Some(engine::Side::Ask),
                     _ => None,
-                })
-                .flatten(),
+                }),
         ) {
-            (Some(cmd), Some(price), Some(quantity), _) if cmd == "b" || cmd == "bid" => Some(
-                ToServer::PlaceOrder(Side::Bid, price.as_bigint_and_exponent(), quantity),
-            ),
-            (Some(cmd), Some(price), Some(quantity), _) if cmd == "a" || cmd == "ask" => Some(
-                ToServer::PlaceOrder(Side::Ask, price.as_bigint_and_exponent(), quantity),
-            ),
-            (Some(cmd), _, _, Some(side)) if cmd == "depth" => Some(ToServer::GetBookDepth(side)),
-            (Some(cmd), _, _, Some(side)) if cmd == "top" => Some(ToServer::GetTopOfBook(side)),
-            (Some(cmd), Some(price), _, Some(side)) if cmd == "size" => Some(
-                ToServer::GetSizeForPriceLevel(side, price.as_bigint_and_exponent()),
-            ),
+            (Some(cmd), Some(price), Some(quantity), _) if cmd == "b" || cmd == "bid" => {
+                Some(ToServer::PlaceOrder(
+                    Side::Bid,
+                    price.as_bigint_and_exponent(),
+                    quantity,
+                    time_in_force,
+                ))
+            }
+            (Some(cmd), Some(price), Some(quantity), _) if cmd == "a" || cmd == "ask" => {
+                Some(ToServer::PlaceOrder(
+                    Side::Ask,
+                    price.as_bigint_and_exponent(),
+                    quantity,
+                    time_in_force,
+                ))
+            }
+            (Some("depth"), _, _, Some(side)) => Some(ToServer::GetBookDepth(side)),
+            (Some("top"), _, _, Some(side)) => Some(ToServer::GetTopOfBook(side)),
+            (Some("size"), Some(price), _, Some(side)) => Some(ToServer::GetSizeForPriceLevel(
+                side,
+                price.as_bigint_and_exponent(),
+            )),
             _ => None,
         };
     }
@@ -0,0 +1,197 @@
+//! Statistics of the current state of the book
+//!
+//! Ratios and averages are rounded to [`ANALYTICS_DECIMALS`] decimals.
+
+use crate::{Level2Query, OrderBook, Quantity, Side};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+pub const ANALYTICS_DECIMALS: i64 = 8;
+
+/// Rounds to [`ANALYTICS_DECIMALS`] decimals
+///
+/// `BigDecimal::round` panics on more digits than fit an `i128`, which the
+/// quotient of a division that doesn't terminate has, so the value is cut
+/// down to one more decimal first.
+pub fn rounded(value: BigDecimal) -> BigDecimal {
+    value
+        .with_scale(ANALYTICS_DECIMALS + 1)
+        .round(ANALYTICS_DECIMALS)
+        .normalized()
+}
+
+/// All analytics of a book at once, values are `None` while a side they
+/// need is empty
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Analytics {
+    pub spread: Option<BigDecimal>,
+    pub mid_price: Option<BigDecimal>,
+    pub microprice: Option<BigDecimal>,
+    pub imbalance: Option<BigDecimal>,
+    pub bid_depth: Option<Quantity>,
+    pub ask_depth: Option<Quantity>,
+    pub bid_average_price: Option<BigDecimal>,
+    pub ask_average_price: Option<BigDecimal>,
+}
+
+impl OrderBook {
+    fn best(&self, side: Side) -> Option<(BigDecimal, Quantity)> {
+        self.levels(side)
+            .next()
+            .map(|(price, quantity)| (price.clone(), *quantity))
+    }
+
+    /// Best ask minus best bid
+    pub fn spread(&self) -> Option<BigDecimal> {
+        Some(self.best(Side::Ask)?.0 - self.best(Side::Bid)?.0)
+    }
+
+    /// Halfway between the best bid and the best ask
+    pub fn mid_price(&self) -> Option<BigDecimal> {
+        Some((self.best(Side::Bid)?.0 + self.best(Side::Ask)?.0) / BigDecimal::from(2))
+    }
+
+    /// Mid price weighted by the size on the other side of the touch, which
+    /// leans towards the side the price is more likely to move to
+    pub fn microprice(&self) -> Option<BigDecimal> {
+        let (bid, bid_size) = self.best(Side::Bid)?;
+        let (ask, ask_size) = self.best(Side::Ask)?;
+        let (bid_size, ask_size) = (
+            BigDecimal::from(bid_size as u64),
+            BigDecimal::from(ask_size as u64),
+        );
+        Some(rounded(
+            (bid * &ask_size + ask * &bid_size) / (bid_size + ask_size),
+        ))
+    }
+
+    fn volume(&self, side: Side, levels: usize) -> Quantity {
+        self.levels(side)
+            .take(levels)
+            .map(|(_, quantity)| quantity)
+            .sum()
+    }
+
+    /// `(bid volume - ask volume) / (bid volume + ask volume)` over the best
+    /// `levels` levels of each side, from -1 with only asks to 1 with only bids
+    pub fn imbalance(&self, levels: usize) -> Option<BigDecimal> {
+        let bids = self.volume(Side::Bid, levels) as i64;
+        let asks = self.volume(Side::Ask, levels) as i64;
+        if bids + asks == 0 {
+            return None;
+        }
+        Some(rounded(
+            BigDecimal::from(bids - asks) / BigDecimal::from(bids + asks),
+        ))
+    }
+
+    /// Quantity of one side priced at most `distance` away from the mid
+    /// price, pass ticks times the tick size for a distance in ticks
+    pub fn depth_within(&self, side: Side, distance: &BigDecimal) -> Option<Quantity> {
+        let mid = self.mid_price()?;
+        Some(
+            self.levels(side)
+                .take_while(|(price, _)| (*price - &mid).abs() <= *distance)
+                .map(|(_, quantity)| quantity)
+                .sum(),
+        )
+    }
+
+    /// Quantity of one side priced at most `bps` basis points away from the
+    /// mid price
+    pub fn depth_within_bps(&self, side: Side, bps: &BigDecimal) -> Option<Quantity> {
+        let distance = self.mid_price()? * bps / BigDecimal::from(10_000);
+        self.depth_within(side, &distance)
+    }
+
+    /// Average price of the best `levels` levels of one side weighted by their
+    /// quantity
+    pub fn weighted_average_price(&self, side: Side, levels: usize) -> Option<BigDecimal> {
+        let (notional, volume) = self.levels(side).take(levels).fold(
+            (BigDecimal::from(0), 0),
+            |(notional, volume), (price, quantity)| {
+                (
+                    notional + price * BigDecimal::from(*quantity as u64),
+                    volume + quantity,
+                )
+            },
+        );
+        if volume == 0 {
+            return None;
+        }
+        Some(rounded(notional / BigDecimal::from(volume as u64)))
+    }
+
+    /// All analytics, imbalance and average prices over the best `levels`
+    /// levels and depth within `bps` basis points of the mid price
+    pub fn analytics(&self, levels: usize, bps: &BigDecimal) -> Analytics {
+        Analytics {
+            spread: self.spread(),
+            mid_price: self.mid_price(),
+            microprice: self.microprice(),
+            imbalance: self.imbalance(levels),
+            bid_depth: self.depth_within_bps(Side::Bid, bps),
+            ask_depth: self.depth_within_bps(Side::Ask, bps),
+            bid_average_price: self.weighted_average_price(Side::Bid, levels),
+            ask_average_price: self.weighted_average_price(Side::Ask, levels),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rounded;
+    use crate::*;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn order_book() -> OrderBook {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 99.into(), 3, 1);
+        order_book.on_new_order(Side::Bid, 98.into(), 5, 2);
+        order_book.on_new_order(Side::Ask, 101.into(), 1, 3);
+        order_book.on_new_order(Side::Ask, 103.into(), 1, 4);
+        order_book
+    }
+
+    #[test]
+    fn touch() {
+        let order_book = order_book();
+        assert_eq!(order_book.spread(), Some(2.into()));
+        assert_eq!(order_book.mid_price(), Some(100.into()));
+        // (99 * 1 + 101 * 3) / 4
+        assert_eq!(order_book.microprice(), Some(decimal("100.5")));
+        assert_eq!(OrderBook::default().microprice(), None);
+    }
+
+    #[test]
+    fn repeating_decimals() {
+        assert_eq!(
+            rounded(decimal("299") / decimal("3")),
+            decimal("99.66666667")
+        );
+    }
+
+    #[test]
+    fn depth() {
+        let order_book = order_book();
+        assert_eq!(order_book.imbalance(1), Some(decimal("0.5")));
+        assert_eq!(order_book.imbalance(2), Some(decimal("0.6")));
+        assert_eq!(order_book.depth_within(Side::Bid, &2.into()), Some(8));
+        assert_eq!(order_book.depth_within_bps(Side::Ask, &100.into()), Some(1));
+        assert_eq!(
+            order_book.weighted_average_price(Side::Bid, 2),
+            Some(decimal("98.375"))
+        );
+        assert_eq!(
+            order_book.weighted_average_price(Side::Ask, 2),
+            Some(102.into())
+        );
+        let analytics = order_book.analytics(2, &100.into());
+        assert_eq!(analytics.bid_depth, Some(3));
+        assert_eq!(analytics.imbalance, Some(decimal("0.6")));
+    }
+}
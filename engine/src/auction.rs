@@ -0,0 +1,182 @@
+//! Call auctions where orders accumulate in a possibly crossed book and are
+//! executed together at a single clearing price
+
+use crate::{Level2View, OrderBook, Quantity, Side, Trade};
+use bigdecimal::BigDecimal;
+
+/// Equilibrium of a crossed book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uncross {
+    pub price: BigDecimal,
+    pub volume: Quantity,
+}
+
+impl OrderBook {
+    /// Indicative clearing price and matched volume if the auction was
+    /// uncrossed now, or `None` if the book is not crossed.
+    ///
+    /// The price maximises executable volume. Ties are broken by the smallest
+    /// surplus, then towards the side with the surplus if it is the same at
+    /// all remaining prices, and finally by the middle of the remaining
+    /// prices, the lower one of two. The price is always one that orders were
+    /// entered at.
+    pub fn indicative_uncross(&self) -> Option<Uncross> {
+        let best_bid = self.bids.keys().next_back()?;
+        let best_ask = self.asks.keys().next()?;
+        if best_bid < best_ask {
+            return None;
+        }
+
+        // (price, executable volume, surplus, bid volume - ask volume) per crossed price
+        let candidates: Vec<_> = self
+            .bids
+            .keys()
+            .chain(self.asks.keys())
+            .filter(|price| *price >= best_ask && *price <= best_bid)
+            .map(|price| {
+                let bid_volume: Quantity = self
+                    .bids
+                    .range(price..)
+                    .map(|(_, level)| level.quantity)
+                    .sum();
+                let ask_volume: Quantity = self
+                    .asks
+                    .range(..=price)
+                    .map(|(_, level)| level.quantity)
+                    .sum();
+                let volume = bid_volume.min(ask_volume);
+                let surplus = bid_volume.max(ask_volume) - volume;
+                let pressure = bid_volume as i64 - ask_volume as i64;
+                (price, volume, surplus, pressure)
+            })
+            .collect();
+        let volume = candidates.iter().map(|(_, volume, _, _)| *volume).max()?;
+        let surplus = candidates
+            .iter()
+            .filter(|(_, v, _, _)| *v == volume)
+            .map(|(_, _, surplus, _)| *surplus)
+            .min()?;
+        let mut best: Vec<_> = candidates
+            .into_iter()
+            .filter(|(_, v, s, _)| *v == volume && *s == surplus)
+            .collect();
+        best.sort_by(|a, b| a.0.cmp(b.0));
+        best.dedup_by(|a, b| a.0 == b.0);
+
+        let price = if best.iter().all(|(_, _, _, pressure)| *pressure > 0) {
+            best[best.len() - 1].0
+        } else if best.iter().all(|(_, _, _, pressure)| *pressure < 0) {
+            best[0].0
+        } else {
+            best[(best.len() - 1) / 2].0
+        };
+        Some(Uncross {
+            price: price.clone(),
+            volume,
+        })
+    }
+
+    /// Executes all crossing orders at the indicative clearing price.
+    ///
+    /// Orders are allocated in price then time priority and completely
+    /// filled orders are removed from the book.
+    pub fn uncross(&mut self) -> Vec<Trade> {
+        let mut trades = vec![];
+        let Uncross { price, mut volume } = match self.indicative_uncross() {
+            Some(uncross) => uncross,
+            None => return trades,
+        };
+        while volume > 0 {
+            let bid = self.first_in_queue(Side::Bid);
+            let ask = self.first_in_queue(Side::Ask);
+            let (bid_order_id, ask_order_id, quantity) = match (bid, ask) {
+                (Some((bid_id, bid, bid_left)), Some((ask_id, ask, ask_left)))
+                    if bid >= price && ask <= price =>
+                {
+                    (bid_id, ask_id, volume.min(bid_left).min(ask_left))
+                }
+                _ => break,
+            };
+            self.on_trade(quantity, bid_order_id);
+            self.on_trade(quantity, ask_order_id);
+            volume -= quantity;
+            trades.push(Trade {
+                price: price.clone(),
+                quantity,
+                bid_order_id,
+                ask_order_id,
+                time: self.now(),
+            });
+        }
+        trades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Level2Query, Level2View};
+
+    #[test]
+    fn uncrossed_book_has_no_equilibrium() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 10.into(), 5, 1);
+        order_book.on_new_order(Side::Ask, 11.into(), 5, 2);
+        assert_eq!(order_book.indicative_uncross(), None);
+        assert!(order_book.uncross().is_empty());
+    }
+
+    #[test]
+    fn equilibrium_maximises_volume() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 5, 2);
+        order_book.on_new_order(Side::Ask, 10.into(), 3, 3);
+        order_book.on_new_order(Side::Ask, 11.into(), 6, 4);
+        assert_eq!(
+            order_book.indicative_uncross(),
+            Some(Uncross {
+                price: 11.into(),
+                volume: 9
+            })
+        );
+    }
+
+    #[test]
+    fn uncross_executes_at_single_price() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 5, 2);
+        order_book.on_new_order(Side::Ask, 10.into(), 3, 3);
+        order_book.on_new_order(Side::Ask, 11.into(), 6, 4);
+        let trades = order_book.uncross();
+        assert!(trades.iter().all(|trade| trade.price == 11.into()));
+        assert_eq!(trades.iter().map(|trade| trade.quantity).sum::<usize>(), 9);
+        assert_eq!(order_book.get_book_depth(Side::Ask), 0);
+        assert_eq!(order_book.get_size_for_price_level(Side::Bid, 11.into()), 1);
+        assert_eq!(order_book.indicative_uncross(), None);
+    }
+
+    #[test]
+    fn balanced_tie_uses_middle_price() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 1, 2);
+        order_book.on_new_order(Side::Ask, 10.into(), 5, 3);
+        order_book.on_new_order(Side::Ask, 12.into(), 1, 4);
+        // The surplus is on the bid at 10 and 11 but on the ask at 12
+        assert_eq!(order_book.indicative_uncross().unwrap().price, 11.into());
+
+        // No price between two that orders were entered at
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, "10.00000002".parse().unwrap(), 5, 1);
+        order_book.on_new_order(Side::Ask, "10.00000001".parse().unwrap(), 5, 2);
+        assert_eq!(
+            order_book.indicative_uncross(),
+            Some(Uncross {
+                price: "10.00000001".parse().unwrap(),
+                volume: 5
+            })
+        );
+    }
+}
@@ -0,0 +1,268 @@
+//! OHLCV bars aggregated from trades
+//!
+//! Time bars cover intervals aligned to multiples of their length, intervals
+//! without trades have no bar. Tick and volume bars end with the trade that
+//! brings them to their number of trades or quantity.
+
+use crate::analytics::rounded;
+use crate::Quantity;
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How trades are grouped into bars, written `1s`, `1m`, `5m` or `1h` for
+/// time bars, `100t` for tick bars and `500v` for volume bars
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BarKind {
+    Time(Duration),
+    Tick(usize),
+    Volume(Quantity),
+}
+
+impl FromStr for BarKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let unit = s.chars().last().ok_or("Empty bar kind")?;
+        let count = s[..s.len() - unit.len_utf8()]
+            .parse::<u64>()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or(format!("Invalid bar kind {}", s))?;
+        let invalid = || format!("Invalid bar kind {}", s);
+        let seconds = |unit: u64| count.checked_mul(unit).ok_or_else(invalid);
+        match unit {
+            's' => Ok(BarKind::Time(Duration::from_secs(count))),
+            'm' => Ok(BarKind::Time(Duration::from_secs(seconds(60)?))),
+            'h' => Ok(BarKind::Time(Duration::from_secs(seconds(3600)?))),
+            't' => Ok(BarKind::Tick(count as usize)),
+            'v' => Ok(BarKind::Volume(count as Quantity)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for BarKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BarKind::Time(interval) => match interval.as_secs() {
+                secs if secs % 3600 == 0 => write!(f, "{}h", secs / 3600),
+                secs if secs % 60 == 0 => write!(f, "{}m", secs / 60),
+                secs => write!(f, "{}s", secs),
+            },
+            BarKind::Tick(trades) => write!(f, "{}t", trades),
+            BarKind::Volume(volume) => write!(f, "{}v", volume),
+        }
+    }
+}
+
+impl TryFrom<String> for BarKind {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BarKind> for String {
+    fn from(kind: BarKind) -> Self {
+        kind.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bar {
+    /// Position of the bar among those of its aggregator, an update of a bar
+    /// has the same number
+    pub number: u64,
+    /// Start of the interval of a time bar, time of the first trade otherwise
+    pub start: Duration,
+    pub open: BigDecimal,
+    pub high: BigDecimal,
+    pub low: BigDecimal,
+    pub close: BigDecimal,
+    pub volume: Quantity,
+    pub vwap: BigDecimal,
+    pub trades: usize,
+}
+
+/// Builds bars of one kind from a stream of trades and keeps the latest
+///
+/// Times are offsets from any fixed instant, e.g. the Unix epoch.
+pub struct BarAggregator {
+    kind: BarKind,
+    capacity: usize,
+    /// Completed bars, oldest first
+    bars: VecDeque<Bar>,
+    /// Bar still taking trades and the notional of its trades
+    current: Option<(Bar, BigDecimal)>,
+    next_number: u64,
+}
+
+impl BarAggregator {
+    /// Aggregator keeping at most `capacity` completed bars
+    pub fn new(kind: BarKind, capacity: usize) -> Self {
+        BarAggregator {
+            kind,
+            capacity,
+            bars: VecDeque::new(),
+            current: None,
+            next_number: 0,
+        }
+    }
+
+    pub fn kind(&self) -> BarKind {
+        self.kind
+    }
+
+    /// Adds a trade and returns the bar it went into, trades without quantity
+    /// are left out
+    pub fn record(&mut self, price: &BigDecimal, quantity: Quantity, at: Duration) -> Option<Bar> {
+        if quantity == 0 {
+            return None;
+        }
+        let start = match self.kind {
+            BarKind::Time(interval) => {
+                let interval = interval.as_nanos();
+                Duration::from_nanos((at.as_nanos() / interval * interval) as u64)
+            }
+            BarKind::Tick(_) | BarKind::Volume(_) => at,
+        };
+        if let (BarKind::Time(_), Some((bar, _))) = (self.kind, &self.current) {
+            if bar.start != start {
+                self.complete();
+            }
+        }
+        let next_number = &mut self.next_number;
+        let (bar, notional) = self.current.get_or_insert_with(|| {
+            *next_number += 1;
+            let bar = Bar {
+                number: *next_number - 1,
+                start,
+                open: price.clone(),
+                high: price.clone(),
+                low: price.clone(),
+                close: price.clone(),
+                volume: 0,
+                vwap: price.clone(),
+                trades: 0,
+            };
+            (bar, BigDecimal::from(0))
+        });
+        if *price > bar.high {
+            bar.high = price.clone();
+        }
+        if *price < bar.low {
+            bar.low = price.clone();
+        }
+        bar.close = price.clone();
+        bar.volume += quantity;
+        bar.trades += 1;
+        *notional += price * BigDecimal::from(quantity as u64);
+        bar.vwap = rounded(&*notional / BigDecimal::from(bar.volume as u64));
+        let bar = bar.clone();
+        let complete = match self.kind {
+            BarKind::Time(_) => false,
+            BarKind::Tick(trades) => bar.trades >= trades,
+            BarKind::Volume(volume) => bar.volume >= volume,
+        };
+        if complete {
+            self.complete();
+        }
+        Some(bar)
+    }
+
+    fn complete(&mut self) {
+        if let Some((bar, _)) = self.current.take() {
+            self.bars.push_back(bar);
+            if self.bars.len() > self.capacity {
+                self.bars.pop_front();
+            }
+        }
+    }
+
+    /// The latest `limit` bars including the one still taking trades, oldest
+    /// first
+    pub fn history(&self, limit: usize) -> Vec<Bar> {
+        let mut bars: Vec<_> = self
+            .bars
+            .iter()
+            .chain(self.current.iter().map(|(bar, _)| bar))
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect();
+        bars.reverse();
+        bars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kind() {
+        assert_eq!("5m".parse(), Ok(BarKind::Time(Duration::from_secs(300))));
+        assert_eq!("100t".parse(), Ok(BarKind::Tick(100)));
+        assert!("0s".parse::<BarKind>().is_err());
+        assert!("5x".parse::<BarKind>().is_err());
+        assert!("18446744073709551615h".parse::<BarKind>().is_err());
+        for kind in &["1s", "5m", "1h", "90s", "100t", "500v"] {
+            assert_eq!(kind.parse::<BarKind>().unwrap().to_string(), *kind);
+        }
+    }
+
+    #[test]
+    fn time_bars() {
+        let mut bars = BarAggregator::new(BarKind::Time(Duration::from_secs(60)), 10);
+        bars.record(&100.into(), 2, Duration::from_secs(61));
+        bars.record(&103.into(), 1, Duration::from_secs(90));
+        let bar = bars
+            .record(&99.into(), 1, Duration::from_secs(119))
+            .unwrap();
+        assert_eq!(bar.number, 0);
+        assert_eq!(bar.start, Duration::from_secs(60));
+        assert_eq!(
+            (bar.open, bar.high, bar.low, bar.close),
+            (100.into(), 103.into(), 99.into(), 99.into())
+        );
+        // (100 * 2 + 103 + 99) / 4
+        assert_eq!(
+            (bar.volume, bar.vwap, bar.trades),
+            (4, "100.5".parse().unwrap(), 3)
+        );
+
+        let bar = bars
+            .record(&101.into(), 5, Duration::from_secs(240))
+            .unwrap();
+        assert_eq!((bar.number, bar.start), (1, Duration::from_secs(240)));
+        assert_eq!(bars.history(10).len(), 2);
+        assert_eq!(bars.history(1), vec![bar.clone()]);
+        assert_eq!(bars.record(&102.into(), 0, Duration::from_secs(241)), None);
+        assert_eq!(bars.history(1), vec![bar]);
+    }
+
+    #[test]
+    fn tick_and_volume_bars() {
+        let mut ticks = BarAggregator::new(BarKind::Tick(2), 1);
+        let mut volume = BarAggregator::new(BarKind::Volume(5), 10);
+        for (second, quantity) in [(1, 3), (2, 3), (3, 1)].iter() {
+            ticks.record(&100.into(), *quantity, Duration::from_secs(*second));
+            volume.record(&100.into(), *quantity, Duration::from_secs(*second));
+        }
+        let history = ticks.history(10);
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].trades, history[0].volume), (2, 6));
+        assert_eq!((history[1].number, history[1].trades), (1, 1));
+        let history = volume.history(10);
+        assert_eq!(history[0].volume, 6);
+        assert_eq!(history[1].start, Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,111 @@
+//! Checksums of the top of a book, modelled on the one OKX sends with its
+//! order book updates
+//!
+//! The checksum is the CRC32 of the best levels interleaved as
+//! `bid_price:bid_size:ask_price:ask_size:...`, with both numbers in their
+//! shortest decimal form and the missing levels of the shorter side left out.
+
+use crate::{Level2Query, OrderBook, Quantity, Side};
+use bigdecimal::BigDecimal;
+
+/// Levels of each side covered by a checksum
+pub const CHECKSUM_DEPTH: usize = 25;
+
+/// CRC32 with the polynomial of zlib and Ethernet
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn decimal(value: &BigDecimal) -> String {
+    value.normalized().to_string()
+}
+
+/// Checksum of the levels of both sides, each from the top of the book
+/// outwards and already cut to the depth the checksum covers
+pub fn checksum(bids: &[(BigDecimal, BigDecimal)], asks: &[(BigDecimal, BigDecimal)]) -> u32 {
+    let mut parts = vec![];
+    for level in 0..bids.len().max(asks.len()) {
+        for side in &[bids, asks] {
+            if let Some((price, size)) = side.get(level) {
+                parts.push(decimal(price));
+                parts.push(decimal(size));
+            }
+        }
+    }
+    crc32(parts.join(":").as_bytes())
+}
+
+fn top_levels<'a, I>(levels: I) -> Vec<(BigDecimal, BigDecimal)>
+where
+    I: Iterator<Item = (&'a BigDecimal, &'a Quantity)>,
+{
+    levels
+        .take(CHECKSUM_DEPTH)
+        .map(|(price, quantity)| (price.clone(), BigDecimal::from(*quantity as u64)))
+        .collect()
+}
+
+/// Checksum of levels with whole quantities, e.g. those of the maps a client
+/// keeps from depth updates
+pub fn levels_checksum<'a, B, A>(bids: B, asks: A) -> u32
+where
+    B: Iterator<Item = (&'a BigDecimal, &'a Quantity)>,
+    A: Iterator<Item = (&'a BigDecimal, &'a Quantity)>,
+{
+    checksum(&top_levels(bids), &top_levels(asks))
+}
+
+impl OrderBook {
+    /// Checksum of the top [`CHECKSUM_DEPTH`] levels of each side
+    pub fn checksum(&self) -> u32 {
+        levels_checksum(self.levels(Side::Bid), self.levels(Side::Ask))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn level(price: &str, size: &str) -> (BigDecimal, BigDecimal) {
+        (
+            BigDecimal::from_str(price).unwrap(),
+            BigDecimal::from_str(size).unwrap(),
+        )
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn interleaves_levels() {
+        let bids = vec![level("3366.1", "7.0"), level("3366", "6")];
+        let asks = vec![level("3366.8", "9")];
+        assert_eq!(checksum(&bids, &asks), crc32(b"3366.1:7:3366.8:9:3366:6"));
+    }
+
+    #[test]
+    fn order_book_checksum() {
+        use crate::Level2View;
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, BigDecimal::from_str("99.50").unwrap(), 3, 1);
+        order_book.on_new_order(Side::Ask, 101.into(), 2, 2);
+        order_book.on_new_order(Side::Ask, 100.into(), 4, 3);
+        assert_eq!(order_book.checksum(), crc32(b"99.5:3:100:4:101:2"));
+        order_book.on_trade(4, 3);
+        assert_eq!(order_book.checksum(), crc32(b"99.5:3:101:2"));
+    }
+}
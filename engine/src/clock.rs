@@ -0,0 +1,70 @@
+//! Sources of time for the order book
+//!
+//! Times are durations since the Unix epoch, or since any other fixed instant
+//! a simulation chooses, e.g. midnight of a historical trading day.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send {
+    fn now(&self) -> Duration;
+}
+
+/// Wall clock time
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when told to, for replays and tests
+///
+/// Clones share the same time, so a book can be given a clone while the
+/// caller keeps another to move it.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(start: Duration) -> Self {
+        let clock = ManualClock::default();
+        clock.set(start);
+        clock
+    }
+
+    pub fn set(&self, time: Duration) {
+        self.nanos.store(time.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manual_clock_is_shared_by_clones() {
+        let clock = ManualClock::new(Duration::from_secs(10));
+        let book_clock = clock.clone();
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(book_clock.now(), Duration::from_millis(11_500));
+        clock.set(Duration::from_secs(3));
+        assert_eq!(book_clock.now(), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,489 @@
+//! Books rebuilt from the price level updates of venues that don't publish
+//! orders
+//!
+//! Messages are read one JSON object per line in the formats of Binance
+//! (`lastUpdateId` snapshots and `depthUpdate` events) and Coinbase
+//! (`snapshot` and `l2update`). Either may carry an OKX style `checksum` of
+//! the book after the message, see [`crate::checksum`].
+
+use crate::{
+    checksum::{checksum, CHECKSUM_DEPTH},
+    Level2Query, Quantity, Side,
+};
+use bigdecimal::{num_bigint::BigInt, BigDecimal, ToPrimitive};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    io::BufRead,
+};
+
+type Changes = Vec<(BigDecimal, BigDecimal)>;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CoinbaseMessage {
+    Snapshot {
+        bids: Changes,
+        asks: Changes,
+        checksum: Option<i64>,
+    },
+    L2update {
+        changes: Vec<(String, BigDecimal, BigDecimal)>,
+        checksum: Option<i64>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMessage {
+    BinanceSnapshot {
+        #[serde(rename = "lastUpdateId")]
+        last_update_id: u64,
+        bids: Changes,
+        asks: Changes,
+        checksum: Option<i64>,
+    },
+    BinanceUpdate {
+        #[serde(rename = "U")]
+        first_update_id: u64,
+        #[serde(rename = "u")]
+        last_update_id: u64,
+        #[serde(rename = "b")]
+        bids: Changes,
+        #[serde(rename = "a")]
+        asks: Changes,
+        checksum: Option<i64>,
+    },
+    Coinbase(CoinbaseMessage),
+}
+
+/// Snapshot or update of price levels, sizes of zero remove a level
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthMessage {
+    pub snapshot: bool,
+    /// First and last update id of an update, only the last one for snapshots
+    pub first_update_id: Option<u64>,
+    pub last_update_id: Option<u64>,
+    pub bids: Changes,
+    pub asks: Changes,
+    /// CRC32 of the book after the message
+    pub checksum: Option<u32>,
+}
+
+impl DepthMessage {
+    pub fn parse(line: &str) -> Result<Self, DepthError> {
+        let raw = serde_json::from_str(line).map_err(|e| DepthError::Parse(e.to_string()))?;
+        let checksum = |checksum: Option<i64>| checksum.map(|checksum| checksum as u32);
+        Ok(match raw {
+            RawMessage::BinanceSnapshot {
+                last_update_id,
+                bids,
+                asks,
+                checksum: sum,
+            } => DepthMessage {
+                snapshot: true,
+                first_update_id: None,
+                last_update_id: Some(last_update_id),
+                bids,
+                asks,
+                checksum: checksum(sum),
+            },
+            RawMessage::BinanceUpdate {
+                first_update_id,
+                last_update_id,
+                bids,
+                asks,
+                checksum: sum,
+            } => DepthMessage {
+                snapshot: false,
+                first_update_id: Some(first_update_id),
+                last_update_id: Some(last_update_id),
+                bids,
+                asks,
+                checksum: checksum(sum),
+            },
+            RawMessage::Coinbase(CoinbaseMessage::Snapshot {
+                bids,
+                asks,
+                checksum: sum,
+            }) => DepthMessage {
+                snapshot: true,
+                first_update_id: None,
+                last_update_id: None,
+                bids,
+                asks,
+                checksum: checksum(sum),
+            },
+            RawMessage::Coinbase(CoinbaseMessage::L2update {
+                changes,
+                checksum: sum,
+            }) => {
+                let (mut bids, mut asks) = (vec![], vec![]);
+                for (side, price, size) in changes {
+                    match side.as_ref() {
+                        "buy" => bids.push((price, size)),
+                        "sell" => asks.push((price, size)),
+                        _ => return Err(DepthError::Parse(format!("Invalid side {}", side))),
+                    }
+                }
+                DepthMessage {
+                    snapshot: false,
+                    first_update_id: None,
+                    last_update_id: None,
+                    bids,
+                    asks,
+                    checksum: checksum(sum),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DepthError {
+    Parse(String),
+    /// Updates without ids can't be ordered against a later snapshot
+    NoSnapshot,
+    /// Updates between the last applied one and this one are missing
+    Gap {
+        expected: u64,
+        first_update_id: u64,
+    },
+    Checksum {
+        expected: u32,
+        actual: u32,
+    },
+}
+
+impl fmt::Display for DepthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DepthError::Parse(e) => write!(f, "Invalid depth message: {}", e),
+            DepthError::NoSnapshot => write!(f, "Update without ids before the first snapshot"),
+            DepthError::Gap {
+                expected,
+                first_update_id,
+            } => write!(
+                f,
+                "Expected update {} but the next update starts at {}",
+                expected, first_update_id
+            ),
+            DepthError::Checksum { expected, actual } => {
+                write!(f, "Checksum is {} instead of {}", actual, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DepthError {}
+
+/// Updates kept while waiting for a snapshot, older ones are dropped
+pub const MAX_PENDING: usize = 10_000;
+
+/// Level 2 book kept from snapshots and price level updates.
+///
+/// Sizes are counted in units of `10^-size_decimals`, so fractional sizes of
+/// crypto venues become whole quantities. `apply` returns gaps, checksum
+/// mismatches and sizes finer than that as errors, after which the book waits
+/// for the next snapshot and keeps up to `MAX_PENDING` updates with ids until
+/// then. `replay` stops at the first error.
+#[derive(Default)]
+pub struct DepthBook {
+    bids: BTreeMap<BigDecimal, Quantity>,
+    asks: BTreeMap<BigDecimal, Quantity>,
+    size_decimals: i64,
+    synced: bool,
+    last_update_id: Option<u64>,
+    /// Updates received before the snapshot they follow
+    pending: VecDeque<DepthMessage>,
+}
+
+impl DepthBook {
+    pub fn new(size_decimals: i64) -> Self {
+        DepthBook {
+            size_decimals,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the size of a price level, removing it when the size is zero
+    pub fn set_level(&mut self, side: Side, price: BigDecimal, quantity: Quantity) {
+        let book = match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        };
+        if quantity == 0 {
+            book.remove(&price);
+        } else {
+            book.insert(price, quantity);
+        }
+    }
+
+    /// Size of a price level, zero if there is no such level
+    pub fn level_size(&self, side: Side, price: &BigDecimal) -> Quantity {
+        match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+        .get(price)
+        .copied()
+        .unwrap_or(0)
+    }
+
+    /// Whether the book follows the feed, false before the first snapshot and
+    /// after an error until the next one
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    pub fn last_update_id(&self) -> Option<u64> {
+        self.last_update_id
+    }
+
+    /// Size in whole units, an error if it has more decimals than the book
+    /// counts or doesn't fit
+    fn quantity(&self, size: &BigDecimal) -> Result<Quantity, DepthError> {
+        let units = size * BigDecimal::new(BigInt::from(1), -self.size_decimals);
+        let whole = units.with_scale(0);
+        match whole.to_usize() {
+            Some(quantity) if whole == units => Ok(quantity),
+            _ => Err(DepthError::Parse(format!("Invalid size {}", size))),
+        }
+    }
+
+    fn size(&self, quantity: Quantity) -> BigDecimal {
+        BigDecimal::new(BigInt::from(quantity), self.size_decimals)
+    }
+
+    /// CRC32 of the top levels in the format of [`crate::checksum`]
+    pub fn checksum(&self) -> u32 {
+        let side = |side| -> Vec<_> {
+            self.levels(side)
+                .take(CHECKSUM_DEPTH)
+                .map(|(price, quantity)| (price.clone(), self.size(*quantity)))
+                .collect()
+        };
+        checksum(&side(Side::Bid), &side(Side::Ask))
+    }
+
+    /// Applies all changes of a message or, if a size is invalid, none
+    fn set_levels(&mut self, message: &DepthMessage) -> Result<(), DepthError> {
+        let mut levels = vec![];
+        for (side, changes) in &[(Side::Bid, &message.bids), (Side::Ask, &message.asks)] {
+            for (price, size) in changes.iter() {
+                levels.push((*side, price.clone(), self.quantity(size)?));
+            }
+        }
+        for (side, price, quantity) in levels {
+            self.set_level(side, price, quantity);
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self, message: &DepthMessage) -> Result<(), DepthError> {
+        match message.checksum {
+            Some(expected) if expected != self.checksum() => {
+                self.synced = false;
+                Err(DepthError::Checksum {
+                    expected,
+                    actual: self.checksum(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn apply_update(&mut self, update: &DepthMessage) -> Result<(), DepthError> {
+        match (
+            self.last_update_id,
+            update.first_update_id,
+            update.last_update_id,
+        ) {
+            // Already part of the snapshot or a previous update
+            (Some(last), _, Some(update_last)) if update_last <= last => return Ok(()),
+            (Some(last), Some(first), _) if first > last + 1 => {
+                self.synced = false;
+                return Err(DepthError::Gap {
+                    expected: last + 1,
+                    first_update_id: first,
+                });
+            }
+            _ => {}
+        }
+        if let Err(e) = self.set_levels(update) {
+            self.synced = false;
+            return Err(e);
+        }
+        if update.last_update_id.is_some() {
+            self.last_update_id = update.last_update_id;
+        }
+        self.verify(update)
+    }
+
+    /// Applies a snapshot or an update following the sequencing rules of
+    /// Binance: updates up to the id of the snapshot are dropped, the first
+    /// one applied must cover the id after it and each one after that must
+    /// start right after the previous one.
+    pub fn apply(&mut self, message: DepthMessage) -> Result<(), DepthError> {
+        if message.snapshot {
+            self.bids.clear();
+            self.asks.clear();
+            self.synced = false;
+            self.set_levels(&message)?;
+            self.last_update_id = message.last_update_id;
+            self.synced = true;
+            self.verify(&message)?;
+            for update in std::mem::take(&mut self.pending) {
+                self.apply_update(&update)?;
+            }
+            Ok(())
+        } else if self.synced {
+            self.apply_update(&message)
+        } else if message.last_update_id.is_some() {
+            if self.pending.len() >= MAX_PENDING {
+                self.pending.pop_front();
+            }
+            self.pending.push_back(message);
+            Ok(())
+        } else {
+            Err(DepthError::NoSnapshot)
+        }
+    }
+
+    /// Applies every message of a file with one JSON message per line
+    pub fn replay<R: BufRead>(&mut self, reader: R) -> Result<(), DepthError> {
+        for line in reader.lines() {
+            let line = line.map_err(|e| DepthError::Parse(e.to_string()))?;
+            if !line.trim().is_empty() {
+                self.apply(DepthMessage::parse(&line)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Level2Query for DepthBook {
+    fn get_size_for_price_level(&mut self, side: Side, price: BigDecimal) -> Quantity {
+        *match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+        .get(&price)
+        .unwrap_or_else(|| panic!("Price level did not exist {}", price))
+    }
+
+    fn get_book_depth(&self, side: Side) -> usize {
+        match side {
+            Side::Ask => self.asks.len(),
+            Side::Bid => self.bids.len(),
+        }
+    }
+
+    fn get_top_of_book(&self, side: Side) -> BigDecimal {
+        match side {
+            Side::Bid => self.bids.iter().next_back(),
+            Side::Ask => self.asks.iter().next(),
+        }
+        .expect("Order book is empty")
+        .0
+        .clone()
+    }
+
+    fn size_for_price_level(&self, side: Side, price: &BigDecimal) -> Option<Quantity> {
+        match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+        .get(price)
+        .copied()
+    }
+
+    fn levels(&self, side: Side) -> Box<dyn Iterator<Item = (&BigDecimal, &Quantity)> + '_> {
+        match side {
+            Side::Bid => Box::new(self.bids.iter().rev()),
+            Side::Ask => Box::new(self.asks.iter()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::crc32;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    const BINANCE: &str = r#"
+{"e":"depthUpdate","E":1,"s":"BNBBTC","U":95,"u":99,"b":[["0.0023","5"]],"a":[]}
+{"lastUpdateId":100,"bids":[["0.0024","10.5"],["0.0022","3"]],"asks":[["0.0026","100"]]}
+{"e":"depthUpdate","E":2,"s":"BNBBTC","U":98,"u":101,"b":[["0.0024","0"]],"a":[["0.0025","2"]]}
+{"e":"depthUpdate","E":3,"s":"BNBBTC","U":102,"u":103,"b":[["0.0022","4.25"]],"a":[]}
+"#;
+
+    #[test]
+    fn binance_sequencing() {
+        let mut book = DepthBook::new(2);
+        book.replay(BINANCE.as_bytes()).unwrap();
+        assert!(book.is_synced());
+        assert_eq!(book.last_update_id(), Some(103));
+        assert_eq!(book.get_top_of_book(Side::Bid), decimal("0.0022"));
+        assert_eq!(
+            book.get_size_for_price_level(Side::Bid, decimal("0.0022")),
+            425
+        );
+        assert_eq!(book.get_top_of_book(Side::Ask), decimal("0.0025"));
+        assert_eq!(book.get_book_depth(Side::Ask), 2);
+
+        // Sizes finer than the book counts are an error, not rounded away
+        let fine = r#"{"e":"depthUpdate","E":4,"s":"BNBBTC","U":104,"u":104,"b":[["0.0021","0.001"]],"a":[]}"#;
+        let mut copy = DepthBook::new(2);
+        copy.replay(BINANCE.as_bytes()).unwrap();
+        assert!(matches!(
+            copy.apply(DepthMessage::parse(fine).unwrap()),
+            Err(DepthError::Parse(_))
+        ));
+        assert_eq!(copy.get_book_depth(Side::Bid), 1);
+        assert!(!copy.is_synced());
+
+        let gap = r#"{"e":"depthUpdate","E":4,"s":"BNBBTC","U":105,"u":106,"b":[],"a":[]}"#;
+        assert_eq!(
+            book.apply(DepthMessage::parse(gap).unwrap()),
+            Err(DepthError::Gap {
+                expected: 104,
+                first_update_id: 105
+            })
+        );
+        assert!(!book.is_synced());
+    }
+
+    #[test]
+    fn coinbase_with_checksum() {
+        let expected = crc32(b"10101.1:0.45:10102.5:2");
+        let messages = format!(
+            r#"{{"type":"snapshot","product_id":"BTC-USD","bids":[["10101.10","0.45"]],"asks":[["10102.55","0.1"]]}}
+{{"type":"l2update","product_id":"BTC-USD","changes":[["sell","10102.55","0"],["sell","10102.50","2.0"]],"checksum":{}}}"#,
+            expected as i32
+        );
+        let mut book = DepthBook::new(8);
+        book.replay(messages.as_bytes()).unwrap();
+        assert_eq!(book.level_size(Side::Ask, &decimal("10102.5")), 200_000_000);
+        assert_eq!(book.checksum(), expected);
+
+        let wrong = r#"{"type":"l2update","product_id":"BTC-USD","changes":[["buy","10100","1"]],"checksum":1}"#;
+        assert!(matches!(
+            book.apply(DepthMessage::parse(wrong).unwrap()),
+            Err(DepthError::Checksum { expected: 1, .. })
+        ));
+        assert!(!book.is_synced());
+        let update =
+            r#"{"type":"l2update","product_id":"BTC-USD","changes":[["buy","10100","2"]]}"#;
+        assert_eq!(
+            book.apply(DepthMessage::parse(update).unwrap()),
+            Err(DepthError::NoSnapshot)
+        );
+    }
+}
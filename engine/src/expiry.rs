@@ -0,0 +1,304 @@
+//! Time in force of resting orders and their expiry
+//!
+//! Deadlines are measured on the clock of the book, days are UTC days when
+//! its times are offsets from the Unix epoch. An order expires once the clock
+//! reaches its deadline and `expire_orders` is called.
+
+use crate::{EventKind, OrderBook, OrderId, Quantity, Side};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// How long an order rests, written `gtc`, `day`, `gtt:<milliseconds since
+/// the epoch>` or `gtd:<YYYY-MM-DD>`
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum TimeInForce {
+    #[default]
+    GoodTillCancel,
+    /// Until this time
+    GoodTillTime(Duration),
+    /// Until the end of this day, counted in days since the epoch
+    GoodTillDate(u64),
+    /// Until the end of the day the order was entered
+    Day,
+}
+
+impl TimeInForce {
+    /// Time an order entered at `entered` expires at, `None` if it doesn't
+    pub fn deadline(&self, entered: Duration) -> Option<Duration> {
+        match self {
+            TimeInForce::GoodTillCancel => None,
+            TimeInForce::GoodTillTime(time) => Some(*time),
+            // Parsing rejects dates whose end doesn't fit
+            TimeInForce::GoodTillDate(day) => Some(end_of_day(*day).unwrap_or(Duration::MAX)),
+            TimeInForce::Day => end_of_day(entered.as_secs() / SECONDS_PER_DAY),
+        }
+    }
+}
+
+/// `None` if the end of the day doesn't fit in a `Duration`
+fn end_of_day(day: u64) -> Option<Duration> {
+    day.checked_add(1)?
+        .checked_mul(SECONDS_PER_DAY)
+        .map(Duration::from_secs)
+}
+
+/// Days since the epoch of a proleptic Gregorian date, `None` if they don't
+/// fit in an `i64`, see http://howardhinnant.github.io/date_algorithms.html
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> Option<i64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era.checked_mul(146_097)?.checked_add(doe - 719_468)
+}
+
+/// Number of days of a month of the proleptic Gregorian calendar
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Year, month and day of a number of days since the epoch
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.splitn(3, '-');
+    let year = parts.next()?.parse().ok()?;
+    let month = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|m| (1..=12).contains(m))?;
+    let day = parts
+        .next()?
+        .parse()
+        .ok()
+        .filter(|d| (1..=days_in_month(year, month)).contains(d))?;
+    u64::try_from(days_from_civil(year, month, day)?)
+        .ok()
+        .filter(|days| end_of_day(*days).is_some())
+}
+
+impl FromStr for TimeInForce {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let invalid = || format!("Invalid time in force {}", s);
+        match s.split_once(':') {
+            None if s == "gtc" => Ok(TimeInForce::GoodTillCancel),
+            None if s == "day" => Ok(TimeInForce::Day),
+            Some(("gtt", millis)) => millis
+                .parse()
+                .map(|millis| TimeInForce::GoodTillTime(Duration::from_millis(millis)))
+                .map_err(|_| invalid()),
+            Some(("gtd", date)) => parse_date(date)
+                .map(TimeInForce::GoodTillDate)
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for TimeInForce {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeInForce::GoodTillCancel => write!(f, "gtc"),
+            TimeInForce::GoodTillTime(time) => write!(f, "gtt:{}", time.as_millis()),
+            TimeInForce::GoodTillDate(day) => {
+                let (year, month, day) = civil_from_days(*day as i64);
+                write!(f, "gtd:{:04}-{:02}-{:02}", year, month, day)
+            }
+            TimeInForce::Day => write!(f, "day"),
+        }
+    }
+}
+
+impl TryFrom<String> for TimeInForce {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<TimeInForce> for String {
+    fn from(time_in_force: TimeInForce) -> Self {
+        time_in_force.to_string()
+    }
+}
+
+/// Order removed from the book at its deadline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Expired {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: BigDecimal,
+    pub quantity: Quantity,
+}
+
+impl OrderBook {
+    /// Sets when a resting order expires, days counting from its entry
+    pub fn set_time_in_force(&mut self, order_id: OrderId, time_in_force: TimeInForce) {
+        if !self.orders.contains_key(&order_id) {
+            panic!("Missing order id {}", order_id);
+        }
+        if let Some(deadline) = self.expiries.remove(&order_id) {
+            self.deadlines.remove(&(deadline, order_id));
+        }
+        let entered = match self.times.get(&order_id) {
+            Some(times) => times.entered,
+            None => self.now(),
+        };
+        if let Some(deadline) = time_in_force.deadline(entered) {
+            self.deadlines.insert((deadline, order_id));
+            self.expiries.insert(order_id, deadline);
+        }
+    }
+
+    /// Deadline of an order, `None` if it rests until cancelled
+    pub fn expiry(&self, order_id: OrderId) -> Option<Duration> {
+        self.expiries.get(&order_id).copied()
+    }
+
+    /// Earliest deadline of the orders in the book
+    pub fn next_expiry(&self) -> Option<Duration> {
+        self.deadlines.iter().next().map(|(deadline, _)| *deadline)
+    }
+
+    /// Removes the orders whose deadline has been reached, earliest first
+    pub fn expire_orders(&mut self) -> Vec<Expired> {
+        let now = self.now();
+        let mut expired = vec![];
+        while let Some(&(deadline, order_id)) = self.deadlines.iter().next() {
+            if deadline > now {
+                break;
+            }
+            let (side, price, quantity) = self.orders[&order_id].clone();
+            self.remove(order_id);
+            self.forget(order_id);
+            self.stamp(EventKind::ExpireOrder { order_id });
+            expired.push(Expired {
+                order_id,
+                side,
+                price,
+                quantity,
+            });
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::{Level2Query, Level2View};
+
+    #[test]
+    fn parse_time_in_force() {
+        assert_eq!("GTC".parse(), Ok(TimeInForce::GoodTillCancel));
+        assert_eq!(
+            "gtt:1500".parse(),
+            Ok(TimeInForce::GoodTillTime(Duration::from_millis(1500)))
+        );
+        assert_eq!("gtd:1970-01-02".parse(), Ok(TimeInForce::GoodTillDate(1)));
+        assert!("gtd:2026-13-01".parse::<TimeInForce>().is_err());
+        assert!("gtd:9223372036854775807-12-31"
+            .parse::<TimeInForce>()
+            .is_err());
+        assert!("gtd:1000000000000000-01-01".parse::<TimeInForce>().is_err());
+        assert!("gtd:2024-02-29".parse::<TimeInForce>().is_ok());
+        assert!("gtd:2000-02-29".parse::<TimeInForce>().is_ok());
+        for date in &[
+            "gtd:2026-02-29",
+            "gtd:1900-02-29",
+            "gtd:2024-02-30",
+            "gtd:2026-04-31",
+        ] {
+            assert!(date.parse::<TimeInForce>().is_err());
+        }
+        assert!("ioc".parse::<TimeInForce>().is_err());
+        for time_in_force in &["gtc", "day", "gtt:1760745600000", "gtd:2026-10-18"] {
+            let parsed: TimeInForce = time_in_force.parse().unwrap();
+            assert_eq!(parsed.to_string(), *time_in_force);
+        }
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), Some(0));
+        assert_eq!(days_from_civil(2024, 3, 1), Some(19_783));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+        assert_eq!(days_from_civil(i64::MAX, 12, 31), None);
+        assert_eq!(days_from_civil(i64::MIN, 1, 1), None);
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2100, 2), 28);
+        assert_eq!(days_in_month(2026, 4), 30);
+    }
+
+    #[test]
+    fn expire_at_deadline() {
+        let day = Duration::from_secs(SECONDS_PER_DAY);
+        let clock = ManualClock::new(day * 3 + Duration::from_secs(60));
+        let mut book = OrderBook::with_clock(clock.clone()).with_journal();
+        book.on_new_order(Side::Bid, 10.into(), 5, 1);
+        book.on_new_order(Side::Bid, 10.into(), 3, 2);
+        book.on_new_order(Side::Ask, 12.into(), 4, 3);
+        book.on_new_order(Side::Ask, 13.into(), 4, 4);
+        let soon = clock.now() + Duration::from_secs(10);
+        book.set_time_in_force(1, TimeInForce::GoodTillTime(soon));
+        book.set_time_in_force(2, TimeInForce::Day);
+        book.set_time_in_force(3, TimeInForce::GoodTillDate(5));
+        book.set_time_in_force(4, TimeInForce::Day);
+        book.on_cancel_order(4);
+        assert_eq!(book.next_expiry(), Some(soon));
+        assert_eq!(book.expiry(2), Some(day * 4));
+
+        clock.set(soon);
+        let expired = book.expire_orders();
+        assert_eq!(expired.len(), 1);
+        assert_eq!((expired[0].order_id, expired[0].quantity), (1, 5));
+        assert_eq!(book.get_size_for_price_level(Side::Bid, 10.into()), 3);
+        assert_eq!(
+            book.drain_journal().last().unwrap().kind,
+            EventKind::ExpireOrder { order_id: 1 }
+        );
+
+        clock.set(day * 4);
+        assert_eq!(book.expire_orders()[0].order_id, 2);
+        assert_eq!(book.get_book_depth(Side::Bid), 0);
+        clock.set(day * 6);
+        assert_eq!(book.expire_orders()[0].order_id, 3);
+        assert!(book.expire_orders().is_empty());
+        assert_eq!(book.next_expiry(), None);
+    }
+}
@@ -0,0 +1,140 @@
+//! Cost of filling an order against the current book, without changing it
+
+use crate::analytics::rounded;
+use crate::{Level2Query, OrderBook, Quantity, Side};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+/// Outcome of walking the book with a simulated order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Impact {
+    pub filled: Quantity,
+    pub unfilled: Quantity,
+    /// Average price of the fills, `None` when nothing is filled
+    pub average_price: Option<BigDecimal>,
+    /// Price of the last level reached
+    pub worst_price: Option<BigDecimal>,
+    pub levels_consumed: usize,
+    /// How much worse than the mid price the average price is, positive
+    /// when the order pays more or receives less than the mid
+    pub slippage: Option<BigDecimal>,
+}
+
+impl OrderBook {
+    /// Fills `quantity` on `side` against every level of the opposite side
+    pub fn simulate_market_order(&self, side: Side, quantity: Quantity) -> Impact {
+        self.simulate(side, quantity, None)
+    }
+
+    /// Fills `quantity` on `side` against the levels of the opposite side up
+    /// to `limit_price`, the rest would rest in the book
+    pub fn simulate_limit_sweep(
+        &self,
+        side: Side,
+        quantity: Quantity,
+        limit_price: &BigDecimal,
+    ) -> Impact {
+        self.simulate(side, quantity, Some(limit_price))
+    }
+
+    fn simulate(&self, side: Side, quantity: Quantity, limit: Option<&BigDecimal>) -> Impact {
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mut remaining = quantity;
+        let mut notional = BigDecimal::from(0);
+        let mut worst_price = None;
+        let mut levels_consumed = 0;
+        for (price, size) in self.levels(opposite) {
+            let crosses = match (side, limit) {
+                (_, None) => true,
+                (Side::Bid, Some(limit)) => price <= limit,
+                (Side::Ask, Some(limit)) => price >= limit,
+            };
+            if remaining == 0 || !crosses {
+                break;
+            }
+            let fill = remaining.min(*size);
+            notional += price * BigDecimal::from(fill as u64);
+            remaining -= fill;
+            worst_price = Some(price.clone());
+            levels_consumed += 1;
+        }
+        let filled = quantity - remaining;
+        let average_price = if filled == 0 {
+            None
+        } else {
+            Some(rounded(notional / BigDecimal::from(filled as u64)))
+        };
+        let slippage = match (&average_price, self.mid_price()) {
+            (Some(average), Some(mid)) => Some(
+                match side {
+                    Side::Bid => average - mid,
+                    Side::Ask => mid - average,
+                }
+                .normalized(),
+            ),
+            _ => None,
+        };
+        Impact {
+            filled,
+            unfilled: remaining,
+            average_price,
+            worst_price,
+            levels_consumed,
+            slippage,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::str::FromStr;
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    fn order_book() -> OrderBook {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 99.into(), 3, 1);
+        order_book.on_new_order(Side::Ask, 101.into(), 2, 2);
+        order_book.on_new_order(Side::Ask, 102.into(), 2, 3);
+        order_book.on_new_order(Side::Ask, 104.into(), 4, 4);
+        order_book
+    }
+
+    #[test]
+    fn market_order() {
+        let order_book = order_book();
+        let impact = order_book.simulate_market_order(Side::Bid, 5);
+        assert_eq!(impact.filled, 5);
+        assert_eq!(impact.unfilled, 0);
+        // (101 * 2 + 102 * 2 + 104) / 5
+        assert_eq!(impact.average_price, Some(decimal("102")));
+        assert_eq!(impact.worst_price, Some(104.into()));
+        assert_eq!(impact.levels_consumed, 3);
+        assert_eq!(impact.slippage, Some(2.into()));
+
+        let impact = order_book.simulate_market_order(Side::Ask, 5);
+        assert_eq!((impact.filled, impact.unfilled), (3, 2));
+        assert_eq!(impact.slippage, Some(1.into()));
+        assert_eq!(order_book.get_book_depth(Side::Ask), 3);
+    }
+
+    #[test]
+    fn limit_sweep() {
+        let order_book = order_book();
+        let impact = order_book.simulate_limit_sweep(Side::Bid, 10, &decimal("102.5"));
+        assert_eq!((impact.filled, impact.unfilled), (4, 6));
+        assert_eq!(impact.average_price, Some(decimal("101.5")));
+        assert_eq!(impact.levels_consumed, 2);
+
+        let impact = order_book.simulate_limit_sweep(Side::Bid, 10, &100.into());
+        assert_eq!(impact.filled, 0);
+        assert_eq!(impact.average_price, None);
+        assert_eq!(impact.slippage, None);
+    }
+}
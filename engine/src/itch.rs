@@ -0,0 +1,478 @@
+//! Reader of NASDAQ TotalView-ITCH 5.0 files rebuilding order books
+//!
+//! Files are a sequence of messages each prefixed by its big-endian 2 byte
+//! length, the way NASDAQ distributes them. Only the messages that change the
+//! visible book are decoded, the others are kept as [`Message::Other`].
+
+use crate::clock::ManualClock;
+use crate::{BookSnapshot, Level2Query, Level2View, OrderBook, OrderId, Quantity, Side};
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read},
+    time::Duration,
+};
+
+/// Implied decimals of ITCH prices
+pub const PRICE_DECIMALS: i64 = 4;
+
+/// A decoded message with the fields common to all of them
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItchMessage {
+    pub locate: u16,
+    /// Nanoseconds since midnight
+    pub timestamp: u64,
+    pub message: Message,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    /// `R`, names the stock of a locate code
+    StockDirectory { stock: String },
+    /// `A` and `F`, the attribution of `F` is dropped
+    AddOrder {
+        order_ref: u64,
+        side: Side,
+        shares: u32,
+        stock: String,
+        price: BigDecimal,
+    },
+    /// `E`
+    OrderExecuted {
+        order_ref: u64,
+        shares: u32,
+        match_number: u64,
+    },
+    /// `C`, executed at a price other than the one of the order
+    OrderExecutedWithPrice {
+        order_ref: u64,
+        shares: u32,
+        match_number: u64,
+        price: BigDecimal,
+    },
+    /// `X`, removes part of the shares of an order
+    OrderCancel { order_ref: u64, shares: u32 },
+    /// `D`
+    OrderDelete { order_ref: u64 },
+    /// `U`, the order loses its priority and gets a new reference
+    OrderReplace {
+        order_ref: u64,
+        new_order_ref: u64,
+        shares: u32,
+        price: BigDecimal,
+    },
+    /// Any other message type
+    Other(u8),
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, byte| value << 8 | u64::from(*byte))
+}
+
+fn price(bytes: &[u8]) -> BigDecimal {
+    BigDecimal::new(BigInt::from(uint(bytes)), PRICE_DECIMALS)
+}
+
+fn stock(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).trim_end().to_string()
+}
+
+/// Length of the known message types
+fn message_len(message_type: u8) -> Option<usize> {
+    match message_type {
+        b'R' => Some(39),
+        b'A' => Some(36),
+        b'F' => Some(40),
+        b'E' => Some(31),
+        b'C' => Some(36),
+        b'X' => Some(23),
+        b'D' => Some(19),
+        b'U' => Some(35),
+        _ => None,
+    }
+}
+
+/// Decodes one message without its length prefix
+pub fn parse_message(bytes: &[u8]) -> io::Result<ItchMessage> {
+    let message_type = *bytes
+        .first()
+        .ok_or_else(|| invalid("Empty message".to_string()))?;
+    let expected = message_len(message_type).unwrap_or(11);
+    if bytes.len() < expected {
+        return Err(invalid(format!(
+            "Message {} has {} bytes instead of {}",
+            message_type as char,
+            bytes.len(),
+            expected
+        )));
+    }
+    let message = match message_type {
+        b'R' => Message::StockDirectory {
+            stock: stock(&bytes[11..19]),
+        },
+        b'A' | b'F' => Message::AddOrder {
+            order_ref: uint(&bytes[11..19]),
+            side: match bytes[19] {
+                b'B' => Side::Bid,
+                b'S' => Side::Ask,
+                side => return Err(invalid(format!("Invalid side {}", side as char))),
+            },
+            shares: uint(&bytes[20..24]) as u32,
+            stock: stock(&bytes[24..32]),
+            price: price(&bytes[32..36]),
+        },
+        b'E' => Message::OrderExecuted {
+            order_ref: uint(&bytes[11..19]),
+            shares: uint(&bytes[19..23]) as u32,
+            match_number: uint(&bytes[23..31]),
+        },
+        b'C' => Message::OrderExecutedWithPrice {
+            order_ref: uint(&bytes[11..19]),
+            shares: uint(&bytes[19..23]) as u32,
+            match_number: uint(&bytes[23..31]),
+            price: price(&bytes[32..36]),
+        },
+        b'X' => Message::OrderCancel {
+            order_ref: uint(&bytes[11..19]),
+            shares: uint(&bytes[19..23]) as u32,
+        },
+        b'D' => Message::OrderDelete {
+            order_ref: uint(&bytes[11..19]),
+        },
+        b'U' => Message::OrderReplace {
+            order_ref: uint(&bytes[11..19]),
+            new_order_ref: uint(&bytes[19..27]),
+            shares: uint(&bytes[27..31]) as u32,
+            price: price(&bytes[31..35]),
+        },
+        other => Message::Other(other),
+    };
+    Ok(ItchMessage {
+        locate: uint(&bytes[1..3]) as u16,
+        timestamp: uint(&bytes[5..11]),
+        message,
+    })
+}
+
+/// Iterates over the length prefixed messages of a file
+pub struct ItchReader<R> {
+    reader: R,
+}
+
+impl<R: Read> ItchReader<R> {
+    pub fn new(reader: R) -> Self {
+        ItchReader { reader }
+    }
+
+    fn next_message(&mut self) -> io::Result<Option<ItchMessage>> {
+        let mut len = [0; 2];
+        match self.reader.read(&mut len[..1])? {
+            0 => return Ok(None),
+            _ => self.reader.read_exact(&mut len[1..])?,
+        }
+        let mut bytes = vec![0; u16::from_be_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes)?;
+        parse_message(&bytes).map(Some)
+    }
+}
+
+impl<R: Read> Iterator for ItchReader<R> {
+    type Item = io::Result<ItchMessage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_message().transpose()
+    }
+}
+
+/// Order books of the stocks of a file, rebuilt message by message on a
+/// clock showing the timestamp of the latest message
+#[derive(Default)]
+pub struct ItchBooks {
+    symbols: Option<HashSet<String>>,
+    stocks: HashMap<String, u16>,
+    books: HashMap<u16, OrderBook>,
+    /// Locate and engine order id of each live order reference
+    orders: HashMap<u64, (u16, OrderId)>,
+    next_order_id: OrderId,
+    timestamp: u64,
+    clock: ManualClock,
+}
+
+impl ItchBooks {
+    /// Only rebuilds the books of the given stocks, which saves memory on full
+    /// day files
+    pub fn with_symbols<I: IntoIterator<Item = String>>(symbols: I) -> Self {
+        ItchBooks {
+            symbols: Some(symbols.into_iter().collect()),
+            ..Default::default()
+        }
+    }
+
+    /// Applies all the messages of a reader
+    pub fn replay<R: Read>(&mut self, reader: R) -> io::Result<()> {
+        for message in ItchReader::new(reader) {
+            self.apply(&message?)?;
+        }
+        Ok(())
+    }
+
+    fn add_book(&mut self, locate: u16, stock: &str) {
+        if self.symbols.as_ref().is_none_or(|s| s.contains(stock)) {
+            self.stocks.insert(stock.to_string(), locate);
+            let clock = &self.clock;
+            self.books
+                .entry(locate)
+                .or_insert_with(|| OrderBook::with_clock(clock.clone()));
+        }
+    }
+
+    /// The book and engine order id of an order of a rebuilt book
+    fn order(&mut self, order_ref: u64) -> Option<(&mut OrderBook, OrderId)> {
+        let (locate, order_id) = *self.orders.get(&order_ref)?;
+        Some((self.books.get_mut(&locate)?, order_id))
+    }
+
+    /// Updates the book of the message, messages of other stocks and of orders
+    /// added before the start of the file are ignored. Executions of more
+    /// shares than an order has left are an error.
+    pub fn apply(&mut self, message: &ItchMessage) -> io::Result<()> {
+        self.timestamp = message.timestamp;
+        self.clock.set(Duration::from_nanos(message.timestamp));
+        match &message.message {
+            Message::StockDirectory { stock } => self.add_book(message.locate, stock),
+            Message::AddOrder {
+                order_ref,
+                side,
+                shares,
+                stock,
+                price,
+            } => {
+                if !self.books.contains_key(&message.locate) {
+                    self.add_book(message.locate, stock);
+                }
+                if let Some(book) = self.books.get_mut(&message.locate) {
+                    let order_id = self.next_order_id;
+                    self.next_order_id += 1;
+                    book.on_new_order(*side, price.clone(), *shares as Quantity, order_id);
+                    self.orders.insert(*order_ref, (message.locate, order_id));
+                }
+            }
+            Message::OrderExecuted {
+                order_ref, shares, ..
+            }
+            | Message::OrderExecutedWithPrice {
+                order_ref, shares, ..
+            } => {
+                if let Some((book, order_id)) = self.order(*order_ref) {
+                    let remaining = book.get_order(order_id).map_or(0, |order| order.2);
+                    if *shares as Quantity > remaining {
+                        return Err(invalid(format!(
+                            "Execution of {} shares of order {} with {} left",
+                            shares, order_ref, remaining
+                        )));
+                    }
+                    book.on_trade(*shares as Quantity, order_id);
+                    if book.get_order(order_id).is_none() {
+                        self.orders.remove(order_ref);
+                    }
+                }
+            }
+            Message::OrderCancel { order_ref, shares } => {
+                if let Some((book, order_id)) = self.order(*order_ref) {
+                    let (_, price, quantity) = book.get_order(order_id).cloned().unwrap();
+                    match quantity.saturating_sub(*shares as Quantity) {
+                        0 => {
+                            book.on_cancel_order(order_id);
+                            self.orders.remove(order_ref);
+                        }
+                        remaining => book.on_replace_order(price, remaining, order_id),
+                    }
+                }
+            }
+            Message::OrderDelete { order_ref } => {
+                if let Some((book, order_id)) = self.order(*order_ref) {
+                    book.on_cancel_order(order_id);
+                    self.orders.remove(order_ref);
+                }
+            }
+            Message::OrderReplace {
+                order_ref,
+                new_order_ref,
+                shares,
+                price,
+            } => {
+                let new_order_id = self.next_order_id;
+                if let Some((book, order_id)) = self.order(*order_ref) {
+                    let side = book.get_order(order_id).map(|order| order.0).unwrap();
+                    book.on_cancel_order(order_id);
+                    book.on_new_order(side, price.clone(), *shares as Quantity, new_order_id);
+                    let (locate, _) = self.orders.remove(order_ref).unwrap();
+                    self.orders.insert(*new_order_ref, (locate, new_order_id));
+                    self.next_order_id += 1;
+                }
+            }
+            Message::Other(_) => {}
+        }
+        Ok(())
+    }
+
+    /// Stocks with a rebuilt book
+    pub fn symbols(&self) -> impl Iterator<Item = &String> {
+        self.stocks.keys()
+    }
+
+    pub fn book(&self, symbol: &str) -> Option<&OrderBook> {
+        self.books.get(self.stocks.get(symbol)?)
+    }
+
+    /// Timestamp of the last applied message, in nanoseconds since midnight
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// Up to `depth` price levels of each side of a book as of the last
+    /// applied message
+    pub fn snapshot(&self, symbol: &str, depth: usize) -> Option<BookSnapshot> {
+        Some(self.book(symbol)?.snapshot(Some(depth)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn message(message_type: u8, locate: u16, timestamp: u64, body: &[u8], len: usize) -> Vec<u8> {
+        let mut bytes = vec![message_type];
+        bytes.extend_from_slice(&locate.to_be_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        bytes.extend_from_slice(&timestamp.to_be_bytes()[2..]);
+        bytes.extend_from_slice(body);
+        bytes.resize(len, b' ');
+        let mut framed = (bytes.len() as u16).to_be_bytes().to_vec();
+        framed.extend(bytes);
+        framed
+    }
+
+    fn directory(locate: u16, stock: &str) -> Vec<u8> {
+        message(b'R', locate, 0, format!("{:<8}", stock).as_bytes(), 39)
+    }
+
+    fn add(locate: u16, order_ref: u64, side: u8, shares: u32, stock: &str, price: u32) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.push(side);
+        body.extend_from_slice(&shares.to_be_bytes());
+        body.extend_from_slice(format!("{:<8}", stock).as_bytes());
+        body.extend_from_slice(&price.to_be_bytes());
+        message(b'A', locate, 1, &body, 36)
+    }
+
+    fn order_message(message_type: u8, order_ref: u64, shares: u32, len: usize) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.extend_from_slice(&shares.to_be_bytes());
+        body.extend_from_slice(&7u64.to_be_bytes());
+        message(message_type, 1, 2, &body, len)
+    }
+
+    fn replace(order_ref: u64, new_order_ref: u64, shares: u32, price: u32) -> Vec<u8> {
+        let mut body = order_ref.to_be_bytes().to_vec();
+        body.extend_from_slice(&new_order_ref.to_be_bytes());
+        body.extend_from_slice(&shares.to_be_bytes());
+        body.extend_from_slice(&price.to_be_bytes());
+        message(b'U', 1, 3, &body, 35)
+    }
+
+    fn decimal(value: &str) -> BigDecimal {
+        BigDecimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn parse_add_order() {
+        let bytes = add(3, 42, b'S', 100, "AAPL", 1_502_500);
+        let parsed = parse_message(&bytes[2..]).unwrap();
+        assert_eq!(parsed.locate, 3);
+        assert_eq!(parsed.timestamp, 1);
+        assert_eq!(
+            parsed.message,
+            Message::AddOrder {
+                order_ref: 42,
+                side: Side::Ask,
+                shares: 100,
+                stock: "AAPL".to_string(),
+                price: decimal("150.25"),
+            }
+        );
+        assert!(parse_message(&bytes[2..20]).is_err());
+        let system_event = message(b'S', 0, 0, b"O", 12);
+        assert_eq!(
+            parse_message(&system_event[2..]).unwrap().message,
+            Message::Other(b'S')
+        );
+    }
+
+    #[test]
+    fn rebuild_book() {
+        let mut file = vec![];
+        file.extend(directory(1, "AAPL"));
+        file.extend(directory(2, "MSFT"));
+        file.extend(add(1, 1, b'B', 100, "AAPL", 1_000_000));
+        file.extend(add(1, 2, b'B', 50, "AAPL", 1_000_000));
+        file.extend(add(1, 3, b'B', 30, "AAPL", 990_000));
+        file.extend(add(1, 4, b'S', 40, "AAPL", 1_010_000));
+        file.extend(add(2, 5, b'S', 10, "MSFT", 3_000_000));
+        // Executes all of order 1 and part of order 4
+        file.extend(order_message(b'E', 1, 100, 31));
+        file.extend(order_message(b'C', 4, 15, 36));
+        // Cancels part of order 2 and deletes order 3
+        file.extend(order_message(b'X', 2, 20, 23));
+        file.extend(order_message(b'D', 3, 0, 19));
+        // Moves order 4 to a new price with a new reference
+        file.extend(replace(4, 6, 25, 1_020_000));
+        file.extend(order_message(b'X', 6, 5, 23));
+
+        let mut books = ItchBooks::default();
+        books.replay(file.as_slice()).unwrap();
+        let snapshot = books.snapshot("AAPL", 10).unwrap();
+        assert_eq!(books.timestamp(), 2);
+        assert_eq!(snapshot.bids, vec![(decimal("100"), 30)]);
+        assert_eq!(snapshot.asks, vec![(decimal("102"), 20)]);
+        assert_eq!(
+            books.snapshot("MSFT", 1).unwrap().asks,
+            vec![(decimal("300"), 10)]
+        );
+        assert!(books.snapshot("TSLA", 1).is_none());
+
+        let mut filtered = ItchBooks::with_symbols(vec!["MSFT".to_string()]);
+        filtered.replay(file.as_slice()).unwrap();
+        assert!(filtered.book("AAPL").is_none());
+        assert_eq!(filtered.book("MSFT").unwrap().get_book_depth(Side::Ask), 1);
+    }
+
+    #[test]
+    fn truncated_file() {
+        let file = add(1, 1, b'B', 100, "AAPL", 1_000_000);
+        let mut books = ItchBooks::default();
+        assert!(books.replay(&file[..file.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn execution_beyond_remaining_shares() {
+        let mut file = directory(1, "AAPL");
+        file.extend(add(1, 1, b'B', 100, "AAPL", 1_000_000));
+        file.extend(order_message(b'E', 1, 60, 31));
+        file.extend(order_message(b'E', 1, 60, 31));
+        let mut books = ItchBooks::default();
+        let error = books.replay(file.as_slice()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            books.snapshot("AAPL", 1).unwrap().bids,
+            vec![(decimal("100"), 40)]
+        );
+    }
+}
@@ -1,33 +1,165 @@
 //! An implementation of a level 2 order view
 
 use bigdecimal::BigDecimal;
+use clock::{Clock, SystemClock};
 use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+pub mod analytics;
+pub mod auction;
+pub mod bars;
+pub mod checksum;
+pub mod clock;
+pub mod depth;
+pub mod expiry;
+pub mod impact;
+pub mod itch;
+pub mod lobster;
+pub mod matching;
+pub mod queue;
+
+pub use auction::Uncross;
+pub use depth::DepthBook;
+pub use expiry::{Expired, TimeInForce};
+pub use impact::Impact;
+pub use matching::Trade;
 
 /// Side of the trade
-#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Side {
     Bid,
     Ask,
 }
-type OrderId = usize;
-type Quantity = usize;
+pub type OrderId = usize;
+pub type Quantity = usize;
+
+/// Price levels of both sides, each from the top of the book outwards
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub bids: Vec<(BigDecimal, Quantity)>,
+    pub asks: Vec<(BigDecimal, Quantity)>,
+}
+
+/// Queries of a level 2 book, also implemented by books built from price level
+/// updates instead of orders
+pub trait Level2Query {
+    fn get_size_for_price_level(&mut self, side: Side, price: BigDecimal) -> usize;
+    fn get_book_depth(&self, side: Side) -> usize;
+    fn get_top_of_book(&self, side: Side) -> BigDecimal;
+
+    /// Size of a price level, `None` if there are no orders at the price
+    fn size_for_price_level(&self, side: Side, price: &BigDecimal) -> Option<Quantity> {
+        self.levels(side)
+            .find(|(level, _)| *level == price)
+            .map(|(_, quantity)| *quantity)
+    }
+
+    /// Best price of one side, `None` if the side is empty
+    fn top_of_book(&self, side: Side) -> Option<BigDecimal> {
+        self.levels(side).next().map(|(price, _)| price.clone())
+    }
+
+    /// Price levels of one side from the top of the book outwards
+    fn levels(&self, side: Side) -> Box<dyn Iterator<Item = (&BigDecimal, &Quantity)> + '_>;
+
+    /// The best `n` price levels of one side, fewer if the side is shallower
+    fn top_levels(&self, side: Side, n: usize) -> Vec<(BigDecimal, Quantity)> {
+        self.levels(side)
+            .take(n)
+            .map(|(price, quantity)| (price.clone(), *quantity))
+            .collect()
+    }
+
+    /// Up to `depth` levels of each side, all of them without a depth
+    fn snapshot(&self, depth: Option<usize>) -> BookSnapshot {
+        let depth = depth.unwrap_or(usize::MAX);
+        BookSnapshot {
+            bids: self.top_levels(Side::Bid, depth),
+            asks: self.top_levels(Side::Ask, depth),
+        }
+    }
+}
 
-pub trait Level2View {
+pub trait Level2View: Level2Query {
     fn on_new_order(&mut self, side: Side, price: BigDecimal, quantity: usize, order_id: usize);
     fn on_cancel_order(&mut self, order_id: usize);
     fn on_replace_order(&mut self, price: BigDecimal, quantity: Quantity, order_id: usize);
     fn on_trade(&mut self, quantity: usize, resting_order_id: usize);
-    fn get_size_for_price_level(&mut self, side: Side, price: BigDecimal) -> usize;
-    fn get_book_depth(&self, side: Side) -> usize;
-    fn get_top_of_book(&self, side: Side) -> BigDecimal;
 }
 
+/// Entry time and time of the last change of a resting order
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderTimes {
+    pub entered: Duration,
+    /// Last replace or partial fill, the entry time if neither happened
+    pub modified: Duration,
+}
+
+/// Change of an order book, numbered in the order the changes happened
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub sequence: u64,
+    pub time: Duration,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    NewOrder {
+        order_id: OrderId,
+        side: Side,
+        price: BigDecimal,
+        quantity: Quantity,
+    },
+    CancelOrder {
+        order_id: OrderId,
+    },
+    ReplaceOrder {
+        order_id: OrderId,
+        price: BigDecimal,
+        quantity: Quantity,
+    },
+    Trade {
+        order_id: OrderId,
+        quantity: Quantity,
+    },
+    ExpireOrder {
+        order_id: OrderId,
+    },
+}
+
+/// Orders resting at one price
 #[derive(Default)]
+struct Level {
+    quantity: Quantity,
+    /// Ids of the orders by their arrival at the level, which is their
+    /// priority
+    orders: BTreeMap<u64, OrderId>,
+}
+
 pub struct OrderBook {
-    bids: BTreeMap<BigDecimal, Quantity>,
-    asks: BTreeMap<BigDecimal, Quantity>,
+    bids: BTreeMap<BigDecimal, Level>,
+    asks: BTreeMap<BigDecimal, Level>,
     orders: HashMap<OrderId, (Side, BigDecimal, Quantity)>,
+    /// When each order arrived at its level
+    arrivals: HashMap<OrderId, u64>,
+    arrival_counter: u64,
+    times: HashMap<OrderId, OrderTimes>,
+    /// Orders with a time in force other than good till cancel, by deadline
+    deadlines: BTreeSet<(Duration, OrderId)>,
+    expiries: HashMap<OrderId, Duration>,
+    clock: Box<dyn Clock>,
+    /// Number of the last change
+    sequence: u64,
+    /// Changes of the book, only kept once enabled with `with_journal`
+    journal: Option<Vec<Event>>,
+}
+
+impl Default for OrderBook {
+    fn default() -> Self {
+        OrderBook::with_clock(SystemClock)
+    }
 }
 
 impl Level2View for OrderBook {
@@ -38,60 +170,59 @@ impl Level2View for OrderBook {
         quantity: Quantity,
         order_id: OrderId,
     ) {
-        let book = match side {
-            Side::Ask => &mut self.asks,
-            Side::Bid => &mut self.bids,
-        };
-        let order_depth = book.entry(price.clone()).or_insert(0);
-        *order_depth += quantity;
-        // TODO: Implement when merged into stable Rust https://github.com/rust-lang/rust/issues/62633
-        if self
-            .orders
-            .insert(order_id, (side, price, quantity))
-            .is_some()
-        {
-            panic!("Order id {} is already present", order_id);
-        }
+        self.insert(side, price.clone(), quantity, order_id);
+        let time = self.stamp(EventKind::NewOrder {
+            order_id,
+            side,
+            price,
+            quantity,
+        });
+        self.times.insert(
+            order_id,
+            OrderTimes {
+                entered: time,
+                modified: time,
+            },
+        );
     }
 
     fn on_cancel_order(&mut self, order_id: usize) {
-        let (side, price, quantity) = self
-            .orders
-            .remove(&order_id)
-            .unwrap_or_else(|| panic!("Missing order id {}", order_id));
-
-        let order_depth = match side {
-            Side::Ask => &mut self.asks,
-            Side::Bid => &mut self.bids,
-        }
-        .get_mut(&price)
-        .unwrap_or_else(|| panic!("Missing order id {} in order book", order_id));
-        *order_depth -= quantity;
-
-        if *order_depth == 0 {
-            match side {
-                Side::Ask => &mut self.asks,
-                Side::Bid => &mut self.bids,
-            }
-            .remove(&price);
-        }
+        self.remove(order_id);
+        self.forget(order_id);
+        self.stamp(EventKind::CancelOrder { order_id });
     }
 
+    /// Moves an order to the back of the queue at the new price unless only
+    /// its quantity goes down
     fn on_replace_order(&mut self, price: BigDecimal, quantity: Quantity, order_id: usize) {
-        let current_order_side = self
-            .orders
-            .get(&order_id)
-            .unwrap_or_else(|| {
+        let (side, current_price, current_quantity) =
+            self.orders.get(&order_id).cloned().unwrap_or_else(|| {
                 panic!(
                     "Can't replace order with non-existent order id {}",
                     order_id
                 )
-            })
-            .0;
-        self.on_cancel_order(order_id);
-        self.on_new_order(current_order_side, price, quantity, order_id);
+            });
+        let arrival = (current_price == price && quantity <= current_quantity)
+            .then(|| self.arrivals[&order_id]);
+        self.remove(order_id);
+        self.insert(side, price.clone(), quantity, order_id);
+        if let Some(arrival) = arrival {
+            self.requeue(order_id, arrival);
+        }
+        let time = self.stamp(EventKind::ReplaceOrder {
+            order_id,
+            price,
+            quantity,
+        });
+        if let Some(times) = self.times.get_mut(&order_id) {
+            times.modified = time;
+        }
     }
     fn on_trade(&mut self, quantity: usize, resting_order_id: usize) {
+        let time = self.stamp(EventKind::Trade {
+            order_id: resting_order_id,
+            quantity,
+        });
         let (side, price, resting_quantity) = self
             .orders
             .get_mut(&resting_order_id)
@@ -105,17 +236,30 @@ impl Level2View for OrderBook {
             Side::Ask => &mut self.asks,
             Side::Bid => &mut self.bids,
         };
-        let order_depth = book.get_mut(price).expect("Price depth did not exist");
-        *order_depth -= quantity;
+        let level = book.get_mut(price).expect("Price depth did not exist");
+        level.quantity -= quantity;
+        if *resting_quantity == 0 {
+            level.orders.remove(&self.arrivals[&resting_order_id]);
+        }
+
+        // Neither a filled order nor an empty level stays in the book
+        if level.quantity == 0 {
+            book.remove(price);
+        }
+        if *resting_quantity == 0 {
+            self.orders.remove(&resting_order_id);
+            self.arrivals.remove(&resting_order_id);
+            self.forget(resting_order_id);
+        } else if let Some(times) = self.times.get_mut(&resting_order_id) {
+            times.modified = time;
+        }
     }
+}
 
+impl Level2Query for OrderBook {
     fn get_size_for_price_level(&mut self, side: Side, price: BigDecimal) -> Quantity {
-        *match side {
-            Side::Ask => &self.asks,
-            Side::Bid => &self.bids,
-        }
-        .get(&price)
-        .unwrap_or_else(|| panic!("Price level did not exist {}", price))
+        self.size_for_price_level(side, &price)
+            .unwrap_or_else(|| panic!("Price level did not exist {}", price))
     }
 
     fn get_book_depth(&self, side: Side) -> usize {
@@ -128,13 +272,188 @@ impl Level2View for OrderBook {
     fn get_top_of_book(&self, side: Side) -> BigDecimal {
         // TODO: Implement when merged into stable Rust https://github.com/rust-lang/rust/issues/62924
         match side {
-            Side::Bid => self.bids.iter().rev().next(),
+            Side::Bid => self.bids.iter().next_back(),
             Side::Ask => self.asks.iter().next(),
         }
         .expect("Order book is empty")
         .0
         .clone()
     }
+
+    fn size_for_price_level(&self, side: Side, price: &BigDecimal) -> Option<Quantity> {
+        match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+        .get(price)
+        .map(|level| level.quantity)
+    }
+
+    fn levels(&self, side: Side) -> Box<dyn Iterator<Item = (&BigDecimal, &Quantity)> + '_> {
+        let levels = match side {
+            Side::Bid => Box::new(self.bids.iter().rev()) as Box<dyn Iterator<Item = _>>,
+            Side::Ask => Box::new(self.asks.iter()),
+        };
+        Box::new(levels.map(|(price, level)| (price, &level.quantity)))
+    }
+}
+
+impl OrderBook {
+    /// Empty book taking the times of orders, trades and events from `clock`
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Self {
+        OrderBook {
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            arrivals: HashMap::new(),
+            arrival_counter: 0,
+            times: HashMap::new(),
+            deadlines: BTreeSet::new(),
+            expiries: HashMap::new(),
+            clock: Box::new(clock),
+            sequence: 0,
+            journal: None,
+        }
+    }
+
+    /// Keeps every change of the book until it is drained
+    pub fn with_journal(mut self) -> Self {
+        self.journal = Some(vec![]);
+        self
+    }
+
+    /// Stops keeping changes and drops the ones not drained yet
+    pub fn disable_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Changes since the journal was last drained, oldest first
+    pub fn drain_journal(&mut self) -> Vec<Event> {
+        self.journal
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    pub fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Number of the last change, zero before the first one
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Numbers a change and returns its time
+    fn stamp(&mut self, kind: EventKind) -> Duration {
+        self.sequence += 1;
+        let time = self.clock.now();
+        if let Some(journal) = &mut self.journal {
+            journal.push(Event {
+                sequence: self.sequence,
+                time,
+                kind,
+            });
+        }
+        time
+    }
+
+    fn insert(&mut self, side: Side, price: BigDecimal, quantity: Quantity, order_id: OrderId) {
+        let book = match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        };
+        self.arrival_counter += 1;
+        let level = book.entry(price.clone()).or_default();
+        level.quantity += quantity;
+        level.orders.insert(self.arrival_counter, order_id);
+        // TODO: Implement when merged into stable Rust https://github.com/rust-lang/rust/issues/62633
+        if self
+            .orders
+            .insert(order_id, (side, price, quantity))
+            .is_some()
+        {
+            panic!("Order id {} is already present", order_id);
+        }
+        self.arrivals.insert(order_id, self.arrival_counter);
+    }
+
+    /// Drops the times and deadline of an order that left the book
+    fn forget(&mut self, order_id: OrderId) {
+        self.times.remove(&order_id);
+        if let Some(deadline) = self.expiries.remove(&order_id) {
+            self.deadlines.remove(&(deadline, order_id));
+        }
+    }
+
+    fn remove(&mut self, order_id: OrderId) {
+        let (side, price, quantity) = self
+            .orders
+            .remove(&order_id)
+            .unwrap_or_else(|| panic!("Missing order id {}", order_id));
+        let arrival = self
+            .arrivals
+            .remove(&order_id)
+            .expect("Orders have an arrival");
+
+        let level = match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        }
+        .get_mut(&price)
+        .unwrap_or_else(|| panic!("Missing order id {} in order book", order_id));
+        level.quantity -= quantity;
+        level.orders.remove(&arrival);
+
+        if level.quantity == 0 {
+            match side {
+                Side::Ask => &mut self.asks,
+                Side::Bid => &mut self.bids,
+            }
+            .remove(&price);
+        }
+    }
+
+    /// Gives an order its place in the queue of its level back
+    fn requeue(&mut self, order_id: OrderId, arrival: u64) {
+        let (side, price, _) = &self.orders[&order_id];
+        let level = match side {
+            Side::Ask => self.asks.get_mut(price),
+            Side::Bid => self.bids.get_mut(price),
+        }
+        .expect("The order rests at the level");
+        let latest = self.arrivals.insert(order_id, arrival);
+        level
+            .orders
+            .remove(&latest.expect("Orders have an arrival"));
+        level.orders.insert(arrival, order_id);
+    }
+
+    /// Entry and last change of a resting order
+    pub fn order_times(&self, order_id: OrderId) -> Option<&OrderTimes> {
+        self.times.get(&order_id)
+    }
+
+    /// Side, price and remaining quantity of a resting order
+    pub fn get_order(&self, order_id: OrderId) -> Option<&(Side, BigDecimal, Quantity)> {
+        self.orders.get(&order_id)
+    }
+
+    /// Size of a price level, zero if there are no orders at the price
+    pub fn level_size(&self, side: Side, price: &BigDecimal) -> Quantity {
+        self.size_for_price_level(side, price).unwrap_or(0)
+    }
+
+    /// Resting order of one side with priority, the earliest arrival at the
+    /// best price
+    fn first_in_queue(&self, side: Side) -> Option<(OrderId, BigDecimal, Quantity)> {
+        let (price, level) = match side {
+            Side::Bid => self.bids.iter().next_back(),
+            Side::Ask => self.asks.iter().next(),
+        }?;
+        let (_, &order_id) = level.orders.iter().next()?;
+        Some((order_id, price.clone(), self.orders[&order_id].2))
+    }
 }
 
 #[cfg(test)]
@@ -154,6 +473,19 @@ mod tests {
         assert_eq!(order_book.get_top_of_book(Side::Bid), 11.into());
     }
 
+    #[test]
+    fn missing_levels() {
+        let mut order_book = OrderBook::default();
+        assert_eq!(order_book.top_of_book(Side::Ask), None);
+        order_book.on_new_order(Side::Ask, 12.into(), 5, 1);
+        assert_eq!(order_book.top_of_book(Side::Ask), Some(12.into()));
+        assert_eq!(
+            order_book.size_for_price_level(Side::Ask, &12.into()),
+            Some(5)
+        );
+        assert_eq!(order_book.size_for_price_level(Side::Ask, &13.into()), None);
+    }
+
     #[test]
     fn trade() {
         let mut order_book = OrderBook::default();
@@ -162,6 +494,17 @@ mod tests {
         assert_eq!(order_book.get_size_for_price_level(Side::Ask, 12.into()), 1);
     }
 
+    #[test]
+    fn trade_whole_level() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Ask, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Ask, 13.into(), 2, 2);
+        order_book.on_trade(5, 1);
+        assert_eq!(order_book.get_book_depth(Side::Ask), 1);
+        assert_eq!(order_book.get_top_of_book(Side::Ask), 13.into());
+        assert!(order_book.get_order(1).is_none());
+    }
+
     #[test]
     #[should_panic]
     fn trade_more_than_available() {
@@ -189,6 +532,48 @@ mod tests {
         assert_eq!(order_book.get_size_for_price_level(Side::Ask, 12.into()), 2);
     }
 
+    #[test]
+    fn levels_from_top_of_book() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 10.into(), 1, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 2, 2);
+        order_book.on_new_order(Side::Ask, 13.into(), 3, 3);
+        order_book.on_new_order(Side::Ask, 12.into(), 4, 4);
+        let bids: Vec<_> = order_book
+            .levels(Side::Bid)
+            .map(|(p, q)| (p.clone(), *q))
+            .collect();
+        let asks: Vec<_> = order_book
+            .levels(Side::Ask)
+            .map(|(p, q)| (p.clone(), *q))
+            .collect();
+        assert_eq!(bids, vec![(11.into(), 2), (10.into(), 1)]);
+        assert_eq!(asks, vec![(12.into(), 4), (13.into(), 3)]);
+    }
+
+    #[test]
+    fn top_levels_and_snapshot() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 10.into(), 1, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 2, 2);
+        order_book.on_new_order(Side::Bid, 9.into(), 5, 3);
+        order_book.on_new_order(Side::Ask, 12.into(), 4, 4);
+        assert_eq!(
+            order_book.top_levels(Side::Bid, 2),
+            vec![(11.into(), 2), (10.into(), 1)]
+        );
+        assert_eq!(order_book.top_levels(Side::Ask, 5), vec![(12.into(), 4)]);
+        assert_eq!(
+            order_book.snapshot(Some(1)),
+            BookSnapshot {
+                bids: vec![(11.into(), 2)],
+                asks: vec![(12.into(), 4)],
+            }
+        );
+        assert_eq!(order_book.snapshot(None).bids.len(), 3);
+        assert_eq!(OrderBook::default().snapshot(None), BookSnapshot::default());
+    }
+
     #[test]
     #[should_panic]
     fn test_invalid_cancel_twice() {
@@ -197,4 +582,44 @@ mod tests {
         order_book.on_cancel_order(1);
         order_book.on_cancel_order(1);
     }
+
+    #[test]
+    fn timestamps_and_journal() {
+        use clock::ManualClock;
+        let clock = ManualClock::new(Duration::from_secs(100));
+        let mut order_book = OrderBook::with_clock(clock.clone()).with_journal();
+        order_book.on_new_order(Side::Ask, 12.into(), 5, 1);
+        clock.advance(Duration::from_secs(1));
+        order_book.on_new_order(Side::Bid, 12.into(), 2, 2);
+        let trades = order_book.match_order(2);
+        assert_eq!(trades[0].time, Duration::from_secs(101));
+        assert_eq!(
+            order_book.order_times(1),
+            Some(&OrderTimes {
+                entered: Duration::from_secs(100),
+                modified: Duration::from_secs(101),
+            })
+        );
+        assert_eq!(order_book.order_times(2), None);
+
+        clock.advance(Duration::from_secs(1));
+        order_book.on_replace_order(13.into(), 3, 1);
+        assert_eq!(
+            order_book.order_times(1).unwrap().modified,
+            Duration::from_secs(102)
+        );
+        let journal = order_book.drain_journal();
+        assert_eq!(order_book.sequence(), 5);
+        assert_eq!(journal.len(), 5);
+        assert_eq!(journal[4].sequence, 5);
+        assert_eq!(journal[4].time, Duration::from_secs(102));
+        assert_eq!(
+            journal[2].kind,
+            EventKind::Trade {
+                order_id: 1,
+                quantity: 2
+            }
+        );
+        assert!(order_book.drain_journal().is_empty());
+    }
 }
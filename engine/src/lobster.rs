@@ -0,0 +1,411 @@
+//! LOBSTER message and orderbook files
+//!
+//! A message file has one event per line, `Time,Type,Order ID,Size,Price,Direction`,
+//! with the time in seconds after midnight, prices in dollars times 10000 and
+//! the direction 1 for buy orders and -1 for sell orders. Each line of the
+//! orderbook file is the book after the event on the same line of the message
+//! file, `Ask Price 1,Ask Size 1,Bid Price 1,Bid Size 1,Ask Price 2,...`, with
+//! empty levels at the dummy prices 9999999999 and -9999999999.
+
+use crate::clock::ManualClock;
+use crate::{Level2Query, Level2View, OrderBook, OrderId, Quantity, Side};
+use bigdecimal::{num_bigint::BigInt, BigDecimal};
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+/// Price decimals kept by LOBSTER
+pub const PRICE_DECIMALS: i64 = 4;
+const DUMMY_ASK: i64 = 9_999_999_999;
+const DUMMY_BID: i64 = -9_999_999_999;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EventType {
+    Submission = 1,
+    Cancellation = 2,
+    Deletion = 3,
+    Execution = 4,
+    HiddenExecution = 5,
+    CrossTrade = 6,
+    TradingHalt = 7,
+}
+
+/// A line of a message file
+#[derive(Debug, Clone, PartialEq)]
+pub struct LobsterMessage {
+    /// Nanoseconds after midnight
+    pub time: u64,
+    pub event: EventType,
+    pub order_id: OrderId,
+    pub size: Quantity,
+    pub price: BigDecimal,
+    /// Side of the order, of the resting order for executions
+    pub side: Side,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn field<T: std::str::FromStr>(value: Option<&str>, name: &str) -> io::Result<T> {
+    let value = value.ok_or_else(|| invalid(format!("Missing {}", name)))?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| invalid(format!("Invalid {} {}", name, value)))
+}
+
+fn parse_time(value: &str) -> io::Result<u64> {
+    let error = || invalid(format!("Invalid time {}", value));
+    let (seconds, fraction) = match value.find('.') {
+        Some(dot) => (&value[..dot], &value[dot + 1..]),
+        None => (value, ""),
+    };
+    if fraction.len() > 9 {
+        return Err(error());
+    }
+    let seconds: u64 = seconds.parse().map_err(|_| error())?;
+    let nanos: u64 = match fraction {
+        "" => 0,
+        fraction => format!("{:0<9}", fraction).parse().map_err(|_| error())?,
+    };
+    seconds
+        .checked_mul(1_000_000_000)
+        .and_then(|time| time.checked_add(nanos))
+        .ok_or_else(error)
+}
+
+fn price_from_file(value: i64) -> BigDecimal {
+    BigDecimal::new(BigInt::from(value), PRICE_DECIMALS)
+}
+
+/// Prices are written in units of 1/10000, more decimals are an error
+fn price_to_file(price: &BigDecimal) -> io::Result<String> {
+    let units = price * BigDecimal::from(10_000);
+    let whole = units.with_scale(0);
+    if whole != units {
+        return Err(invalid(format!(
+            "Price {} has more than four decimals",
+            price
+        )));
+    }
+    Ok(whole.to_string())
+}
+
+impl LobsterMessage {
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let mut fields = line.split(',');
+        let time = parse_time(fields.next().unwrap_or_default().trim())?;
+        let event = match field::<u8>(fields.next(), "type")? {
+            1 => EventType::Submission,
+            2 => EventType::Cancellation,
+            3 => EventType::Deletion,
+            4 => EventType::Execution,
+            5 => EventType::HiddenExecution,
+            6 => EventType::CrossTrade,
+            7 => EventType::TradingHalt,
+            other => return Err(invalid(format!("Invalid type {}", other))),
+        };
+        let order_id = field(fields.next(), "order id")?;
+        let size = field(fields.next(), "size")?;
+        let price = price_from_file(field(fields.next(), "price")?);
+        let side = match field::<i8>(fields.next(), "direction")? {
+            1 => Side::Bid,
+            -1 => Side::Ask,
+            other => return Err(invalid(format!("Invalid direction {}", other))),
+        };
+        Ok(LobsterMessage {
+            time,
+            event,
+            order_id,
+            size,
+            price,
+            side,
+        })
+    }
+
+    pub fn to_line(&self) -> io::Result<String> {
+        Ok(format!(
+            "{}.{:09},{},{},{},{},{}",
+            self.time / 1_000_000_000,
+            self.time % 1_000_000_000,
+            self.event as u8,
+            self.order_id,
+            self.size,
+            price_to_file(&self.price)?,
+            match self.side {
+                Side::Bid => 1,
+                Side::Ask => -1,
+            }
+        ))
+    }
+}
+
+/// A line of an orderbook file without its empty levels
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OrderbookRow {
+    pub asks: Vec<(BigDecimal, Quantity)>,
+    pub bids: Vec<(BigDecimal, Quantity)>,
+}
+
+impl OrderbookRow {
+    pub fn parse(line: &str) -> io::Result<Self> {
+        let values = line
+            .split(',')
+            .map(|value| field::<i64>(Some(value), "orderbook value"))
+            .collect::<io::Result<Vec<_>>>()?;
+        if values.len() % 4 != 0 {
+            return Err(invalid(format!("{} orderbook values", values.len())));
+        }
+        let mut row = OrderbookRow::default();
+        for level in values.chunks(4) {
+            if level[0] != DUMMY_ASK && level[1] > 0 {
+                row.asks
+                    .push((price_from_file(level[0]), level[1] as Quantity));
+            }
+            if level[2] != DUMMY_BID && level[3] > 0 {
+                row.bids
+                    .push((price_from_file(level[2]), level[3] as Quantity));
+            }
+        }
+        Ok(row)
+    }
+
+    /// Number of levels of a line of an orderbook file
+    pub fn levels(line: &str) -> usize {
+        line.split(',').count() / 4
+    }
+
+    pub fn to_line(&self, levels: usize) -> io::Result<String> {
+        let levels = (0..levels)
+            .map(|level| {
+                let (ask_price, ask_size) = match self.asks.get(level) {
+                    Some((price, size)) => (price_to_file(price)?, *size),
+                    None => (DUMMY_ASK.to_string(), 0),
+                };
+                let (bid_price, bid_size) = match self.bids.get(level) {
+                    Some((price, size)) => (price_to_file(price)?, *size),
+                    None => (DUMMY_BID.to_string(), 0),
+                };
+                Ok(format!(
+                    "{},{},{},{}",
+                    ask_price, ask_size, bid_price, bid_size
+                ))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(levels.join(","))
+    }
+}
+
+/// Order book driven by the events of a message file, on a clock showing the
+/// time of the latest event since midnight
+pub struct LobsterBook {
+    book: OrderBook,
+    clock: ManualClock,
+}
+
+impl Default for LobsterBook {
+    fn default() -> Self {
+        let clock = ManualClock::default();
+        LobsterBook {
+            book: OrderBook::with_clock(clock.clone()),
+            clock,
+        }
+    }
+}
+
+impl LobsterBook {
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Updates the book with an event. Events of orders submitted before the
+    /// start of the file are ignored, as well as hidden executions, cross
+    /// trades and halts which don't change the visible book.
+    pub fn apply(&mut self, message: &LobsterMessage) {
+        self.clock.set(Duration::from_nanos(message.time));
+        let order_id = message.order_id;
+        let remaining = self.book.get_order(order_id).map(|order| order.2);
+        match (message.event, remaining) {
+            (EventType::Submission, None) => {
+                self.book
+                    .on_new_order(message.side, message.price.clone(), message.size, order_id)
+            }
+            (EventType::Cancellation, Some(remaining)) if message.size < remaining => {
+                let price = self.book.get_order(order_id).unwrap().1.clone();
+                self.book
+                    .on_replace_order(price, remaining - message.size, order_id);
+            }
+            (EventType::Cancellation, Some(_)) | (EventType::Deletion, Some(_)) => {
+                self.book.on_cancel_order(order_id)
+            }
+            (EventType::Execution, Some(remaining)) => {
+                self.book.on_trade(message.size.min(remaining), order_id)
+            }
+            _ => {}
+        }
+    }
+
+    /// Levels of the book the way an orderbook file has them
+    pub fn row(&self, levels: usize) -> OrderbookRow {
+        OrderbookRow {
+            asks: self.book.top_levels(Side::Ask, levels),
+            bids: self.book.top_levels(Side::Bid, levels),
+        }
+    }
+
+    /// Applies all the events of a message file
+    pub fn replay<R: BufRead>(&mut self, messages: R) -> io::Result<()> {
+        for line in messages.lines() {
+            self.apply(&LobsterMessage::parse(&line?)?);
+        }
+        Ok(())
+    }
+
+    /// Applies all the events of a message file and checks the book after
+    /// each of them against the orderbook file, failing on the first line
+    /// that differs. This only holds for files starting with an empty book.
+    pub fn validate<M: BufRead, B: BufRead>(
+        &mut self,
+        messages: M,
+        orderbook: B,
+    ) -> io::Result<()> {
+        let mut rows = orderbook.lines();
+        for (number, line) in messages.lines().enumerate() {
+            self.apply(&LobsterMessage::parse(&line?)?);
+            let row = rows.next().ok_or_else(|| {
+                invalid(format!("Orderbook file ends before line {}", number + 1))
+            })??;
+            let expected = OrderbookRow::parse(&row)?;
+            let actual = self.row(OrderbookRow::levels(&row));
+            if actual != expected {
+                return Err(invalid(format!(
+                    "Line {} has the book {} instead of {}",
+                    number + 1,
+                    actual.to_line(OrderbookRow::levels(&row))?,
+                    row
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a message file and the matching orderbook file
+pub struct LobsterWriter<W> {
+    messages: W,
+    orderbook: W,
+    levels: usize,
+    book: LobsterBook,
+}
+
+impl<W: Write> LobsterWriter<W> {
+    /// Orderbook lines have `levels` levels of each side
+    pub fn new(messages: W, orderbook: W, levels: usize) -> Self {
+        LobsterWriter {
+            messages,
+            orderbook,
+            levels,
+            book: LobsterBook::default(),
+        }
+    }
+
+    /// Appends an event and the book after it, an event with a price that
+    /// can't be written is an error and leaves the files as they were
+    pub fn write(&mut self, message: &LobsterMessage) -> io::Result<()> {
+        let line = message.to_line()?;
+        self.book.apply(message);
+        writeln!(self.messages, "{}", line)?;
+        writeln!(
+            self.orderbook,
+            "{}",
+            self.book.row(self.levels).to_line(self.levels)?
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.messages.flush()?;
+        self.orderbook.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    const MESSAGES: &str = "\
+34200.004241176,1,16113575,18,5853300,1
+34200.00426064,1,16113584,18,5853200,1
+34200.004447484,1,16113594,18,5859100,-1
+34200.025551909,2,16113575,8,5853300,1
+34200.2,4,16113594,10,5859100,-1
+34200.3,3,16113575,10,5853300,1
+34200.4,5,0,7,5855000,-1";
+
+    const ORDERBOOK: &str = "\
+9999999999,0,5853300,18,9999999999,0,-9999999999,0
+9999999999,0,5853300,18,9999999999,0,5853200,18
+5859100,18,5853300,18,9999999999,0,5853200,18
+5859100,18,5853300,10,9999999999,0,5853200,18
+5859100,8,5853300,10,9999999999,0,5853200,18
+5859100,8,5853200,18,9999999999,0,-9999999999,0
+5859100,8,5853200,18,9999999999,0,-9999999999,0";
+
+    #[test]
+    fn parse_message() {
+        let message = LobsterMessage::parse("34200.00426064,4,16113584,18,5853200,-1").unwrap();
+        assert_eq!(
+            message,
+            LobsterMessage {
+                time: 34_200_004_260_640,
+                event: EventType::Execution,
+                order_id: 16113584,
+                size: 18,
+                price: BigDecimal::from_str("585.32").unwrap(),
+                side: Side::Ask,
+            }
+        );
+        assert_eq!(
+            message.to_line().unwrap(),
+            "34200.004260640,4,16113584,18,5853200,-1"
+        );
+        assert!(LobsterMessage::parse("34200.1,9,1,1,1,1").is_err());
+        assert!(LobsterMessage::parse("34200.1,1,1,1,1").is_err());
+        assert!(LobsterMessage::parse("18446744073709551615,1,1,1,1,1").is_err());
+        let mut message = message;
+        message.price = BigDecimal::from_str("585.32001").unwrap();
+        assert!(message.to_line().is_err());
+    }
+
+    #[test]
+    fn validate_against_orderbook() {
+        let mut book = LobsterBook::default();
+        book.validate(MESSAGES.as_bytes(), ORDERBOOK.as_bytes())
+            .unwrap();
+        assert_eq!(book.book().get_book_depth(Side::Bid), 1);
+
+        let wrong = ORDERBOOK.replacen("5853300,10", "5853300,11", 1);
+        let mut book = LobsterBook::default();
+        assert!(book
+            .validate(MESSAGES.as_bytes(), wrong.as_bytes())
+            .is_err());
+    }
+
+    #[test]
+    fn write_and_read_back() {
+        let (mut messages, mut orderbook) = (vec![], vec![]);
+        let mut writer = LobsterWriter::new(&mut messages, &mut orderbook, 2);
+        for line in MESSAGES.lines() {
+            writer.write(&LobsterMessage::parse(line).unwrap()).unwrap();
+        }
+        drop(writer);
+        let orderbook = String::from_utf8(orderbook).unwrap();
+        assert_eq!(
+            orderbook.lines().collect::<Vec<_>>(),
+            ORDERBOOK.lines().collect::<Vec<_>>()
+        );
+        LobsterBook::default()
+            .validate(messages.as_slice(), orderbook.as_bytes())
+            .unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+//! Continuous matching of incoming orders against the resting book
+
+use crate::{Level2View, OrderBook, OrderId, Quantity, Side};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// An execution between a buy and a sell order
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub price: BigDecimal,
+    pub quantity: Quantity,
+    pub bid_order_id: OrderId,
+    pub ask_order_id: OrderId,
+    /// Time of the execution on the clock of the book
+    pub time: Duration,
+}
+
+impl OrderBook {
+    /// Matches an order already added to the book against the opposite side.
+    ///
+    /// Executions happen at the price of the resting order, in price then
+    /// time priority. Orders that are completely filled are removed.
+    pub fn match_order(&mut self, order_id: OrderId) -> Vec<Trade> {
+        let mut trades = vec![];
+        let (side, limit, mut remaining) = match self.get_order(order_id) {
+            Some((side, price, quantity)) => (*side, price.clone(), *quantity),
+            None => return trades,
+        };
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        while remaining > 0 {
+            let (resting_id, price, quantity) = match self.first_in_queue(opposite) {
+                Some(resting) => resting,
+                None => break,
+            };
+            let crosses = match side {
+                Side::Bid => price <= limit,
+                Side::Ask => price >= limit,
+            };
+            if !crosses {
+                break;
+            }
+            let quantity = quantity.min(remaining);
+            self.on_trade(quantity, resting_id);
+            self.on_trade(quantity, order_id);
+            remaining -= quantity;
+            let (bid_order_id, ask_order_id) = match side {
+                Side::Bid => (order_id, resting_id),
+                Side::Ask => (resting_id, order_id),
+            };
+            trades.push(Trade {
+                price,
+                quantity,
+                bid_order_id,
+                ask_order_id,
+                time: self.now(),
+            });
+        }
+        trades
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn match_at_resting_price() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Ask, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Ask, 13.into(), 5, 2);
+        order_book.on_new_order(Side::Bid, 13.into(), 7, 3);
+        let trades = order_book.match_order(3);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price, 12.into());
+        assert_eq!(trades[0].quantity, 5);
+        assert_eq!(trades[1].price, 13.into());
+        assert_eq!(trades[1].quantity, 2);
+        assert_eq!(order_book.get_book_depth(Side::Bid), 0);
+        assert_eq!(order_book.get_size_for_price_level(Side::Ask, 13.into()), 3);
+        assert!(order_book.get_order(1).is_none());
+    }
+
+    #[test]
+    fn no_match_when_not_crossed() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Ask, 12.into(), 5, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 5, 2);
+        assert!(order_book.match_order(2).is_empty());
+        assert_eq!(order_book.get_size_for_price_level(Side::Bid, 11.into()), 5);
+    }
+
+    #[test]
+    fn time_priority_within_level() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 10.into(), 2, 1);
+        order_book.on_new_order(Side::Bid, 10.into(), 2, 2);
+        order_book.on_new_order(Side::Ask, 10.into(), 3, 3);
+        let trades = order_book.match_order(3);
+        assert_eq!(trades[0].bid_order_id, 1);
+        assert_eq!(trades[1].bid_order_id, 2);
+        assert_eq!(order_book.get_order(2).unwrap().2, 1);
+    }
+
+    #[test]
+    fn replace_loses_priority() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 10.into(), 2, 1);
+        order_book.on_new_order(Side::Bid, 11.into(), 2, 2);
+        order_book.on_new_order(Side::Bid, 11.into(), 2, 3);
+        order_book.on_replace_order(11.into(), 2, 1);
+        // Only a smaller quantity at the same price keeps the place
+        order_book.on_replace_order(11.into(), 1, 2);
+        order_book.on_new_order(Side::Ask, 11.into(), 5, 4);
+        let trades = order_book.match_order(4);
+        let filled: Vec<_> = trades.iter().map(|trade| trade.bid_order_id).collect();
+        assert_eq!(filled, [2, 3, 1]);
+    }
+}
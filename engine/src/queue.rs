@@ -0,0 +1,187 @@
+//! Where a resting order stands in the queue of its price level and how long
+//! it may take to fill at the recent pace of trading
+
+use crate::{OrderBook, OrderId, Quantity, Side};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Quantity traded at each price over a sliding window of time
+///
+/// Times are offsets from any fixed instant, e.g. the Unix epoch.
+pub struct TradeRates {
+    window: Duration,
+    /// Time, price and quantity of the trades within the window, oldest first
+    trades: VecDeque<(Duration, BigDecimal, Quantity)>,
+}
+
+impl TradeRates {
+    pub fn new(window: Duration) -> Self {
+        TradeRates {
+            window,
+            trades: VecDeque::new(),
+        }
+    }
+
+    /// Records a trade and forgets those that fell out of the window
+    pub fn record(&mut self, price: BigDecimal, quantity: Quantity, at: Duration) {
+        self.trades.push_back((at, price, quantity));
+        while let Some((time, _, _)) = self.trades.front() {
+            if *time + self.window >= at {
+                break;
+            }
+            self.trades.pop_front();
+        }
+    }
+
+    /// Quantity traded at `price` within the window ending at `now`
+    pub fn volume(&self, price: &BigDecimal, now: Duration) -> Quantity {
+        self.trades
+            .iter()
+            .filter(|(time, trade_price, _)| *time + self.window >= now && trade_price == price)
+            .map(|(_, _, quantity)| quantity)
+            .sum()
+    }
+
+    /// Time to trade `quantity` at `price` if trading goes on at the pace of
+    /// the window ending at `now`, `None` without recent trades at the price
+    /// or if the time is too long to tell
+    pub fn time_to_fill(
+        &self,
+        price: &BigDecimal,
+        quantity: Quantity,
+        now: Duration,
+    ) -> Option<Duration> {
+        match self.volume(price, now) {
+            0 => None,
+            volume => Duration::try_from_secs_f64(
+                self.window.as_secs_f64() * quantity as f64 / volume as f64,
+            )
+            .ok(),
+        }
+    }
+}
+
+/// Place of a resting order in its price level
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePosition {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: BigDecimal,
+    /// Remaining quantity of the order
+    pub quantity: Quantity,
+    /// Quantity of the orders at the same price with priority over this one
+    pub ahead: Quantity,
+    pub level_size: Quantity,
+    /// Estimated time until the order is completely filled
+    pub time_to_fill: Option<Duration>,
+}
+
+impl OrderBook {
+    /// Position of a resting order, orders at the same price being filled in
+    /// the order they arrived at it
+    pub fn queue_position(
+        &self,
+        order_id: OrderId,
+        rates: &TradeRates,
+        now: Duration,
+    ) -> Option<QueuePosition> {
+        let (side, price, quantity) = self.get_order(order_id)?;
+        let level = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        }
+        .get(price)?;
+        let ahead = level
+            .orders
+            .range(..self.arrivals[&order_id])
+            .filter_map(|(_, other_id)| self.orders.get(other_id))
+            .map(|(_, _, quantity)| quantity)
+            .sum::<Quantity>();
+        Some(QueuePosition {
+            order_id,
+            side: *side,
+            price: price.clone(),
+            quantity: *quantity,
+            ahead,
+            level_size: level.quantity,
+            time_to_fill: rates.time_to_fill(price, ahead + quantity, now),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use queue::TradeRates;
+    use std::time::Duration;
+
+    #[test]
+    fn trade_rates() {
+        let mut rates = TradeRates::new(Duration::from_secs(60));
+        rates.record(100.into(), 4, Duration::from_secs(0));
+        rates.record(100.into(), 2, Duration::from_secs(30));
+        rates.record(101.into(), 9, Duration::from_secs(30));
+        assert_eq!(rates.volume(&100.into(), Duration::from_secs(30)), 6);
+        assert_eq!(rates.volume(&100.into(), Duration::from_secs(61)), 2);
+        // 6 every 60 seconds
+        assert_eq!(
+            rates.time_to_fill(&100.into(), 3, Duration::from_secs(30)),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            rates.time_to_fill(&99.into(), 3, Duration::from_secs(30)),
+            None
+        );
+        assert_eq!(
+            rates.time_to_fill(&100.into(), usize::MAX, Duration::from_secs(30)),
+            None
+        );
+    }
+
+    #[test]
+    fn queue_position() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 100.into(), 3, 1);
+        order_book.on_new_order(Side::Bid, 99.into(), 7, 2);
+        order_book.on_new_order(Side::Bid, 100.into(), 2, 3);
+        order_book.on_new_order(Side::Bid, 100.into(), 5, 4);
+        let mut rates = TradeRates::new(Duration::from_secs(10));
+        rates.record(100.into(), 5, Duration::from_secs(0));
+
+        let position = order_book
+            .queue_position(3, &rates, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!((position.ahead, position.level_size), (3, 10));
+        assert_eq!(position.time_to_fill, Some(Duration::from_secs(10)));
+
+        order_book.on_trade(3, 1);
+        let position = order_book
+            .queue_position(4, &rates, Duration::from_secs(20))
+            .unwrap();
+        assert_eq!((position.ahead, position.quantity), (2, 5));
+        assert_eq!(position.time_to_fill, None);
+        assert!(order_book
+            .queue_position(1, &rates, Duration::from_secs(20))
+            .is_none());
+    }
+
+    #[test]
+    fn replaced_order_queue_position() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Ask, 100.into(), 3, 1);
+        order_book.on_new_order(Side::Ask, 101.into(), 4, 2);
+        order_book.on_replace_order(101.into(), 3, 1);
+        let rates = TradeRates::new(Duration::from_secs(10));
+
+        let position = order_book
+            .queue_position(1, &rates, Duration::from_secs(0))
+            .unwrap();
+        assert_eq!((position.ahead, position.level_size), (4, 7));
+        let position = order_book
+            .queue_position(2, &rates, Duration::from_secs(0))
+            .unwrap();
+        assert_eq!(position.ahead, 0);
+    }
+}
@@ -0,0 +1,156 @@
+//! Histogram of latencies in the manner of HdrHistogram
+//!
+//! Values below `2 * SUB_BUCKETS` are counted exactly. Above that every power
+//! of two is split into `SUB_BUCKETS` equal buckets, so a value is known to
+//! within one part in `SUB_BUCKETS` however large it is.
+
+const SUB_BUCKET_BITS: u32 = 10;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+
+#[derive(Debug, Clone, Default)]
+pub struct Histogram {
+    /// Grows up to the bucket of the largest value recorded
+    counts: Vec<u64>,
+    total: u64,
+    sum: u128,
+}
+
+fn index(value: u64) -> usize {
+    let bits = 64 - value.leading_zeros();
+    let shift = bits.saturating_sub(SUB_BUCKET_BITS + 1);
+    shift as usize * SUB_BUCKETS + (value >> shift) as usize
+}
+
+/// Lowest and highest value counted in a bucket
+fn bounds(index: usize) -> (u64, u64) {
+    if index < 2 * SUB_BUCKETS {
+        return (index as u64, index as u64);
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let mantissa = (index - shift * SUB_BUCKETS) as u64;
+    (mantissa << shift, ((mantissa + 1) << shift) - 1)
+}
+
+impl Histogram {
+    pub fn record(&mut self, value: u64) {
+        let index = index(value);
+        if index >= self.counts.len() {
+            self.counts.resize(index + 1, 0);
+        }
+        self.counts[index] += 1;
+        self.total += 1;
+        self.sum += u128::from(value);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+        self.sum += other.sum;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        match self.total {
+            0 => None,
+            total => Some(self.sum as f64 / total as f64),
+        }
+    }
+
+    pub fn min(&self) -> Option<u64> {
+        let index = self.counts.iter().position(|count| *count > 0)?;
+        Some(bounds(index).0)
+    }
+
+    pub fn max(&self) -> Option<u64> {
+        let index = self.counts.iter().rposition(|count| *count > 0)?;
+        Some(bounds(index).1)
+    }
+
+    /// Highest value of the bucket the given fraction of the values is at or
+    /// below
+    pub fn value_at_quantile(&self, quantile: f64) -> Option<u64> {
+        if self.total == 0 {
+            return None;
+        }
+        let target = ((quantile * self.total as f64).ceil() as u64).clamp(1, self.total);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Some(bounds(index).1);
+            }
+        }
+        self.max()
+    }
+
+    /// Values at quantiles closing in on the maximum, `ticks` of them each
+    /// time the distance to it halves, with the number of values at or below
+    /// each, as in the percentile distribution of HdrHistogram
+    pub fn distribution(&self, ticks: u32) -> Vec<(u64, f64, u64)> {
+        let mut lines = vec![];
+        if self.total == 0 {
+            return lines;
+        }
+        let ticks = ticks.max(1);
+        for step in 0..64 * ticks {
+            let quantile = 1.0 - 0.5f64.powf(f64::from(step) / f64::from(ticks));
+            let value = self.value_at_quantile(quantile).unwrap_or_default();
+            let below = self.counts[..=index(value)].iter().sum::<u64>();
+            if below == self.total {
+                break;
+            }
+            lines.push((value, quantile, below));
+        }
+        lines.push((self.max().unwrap_or_default(), 1.0, self.total));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precision() {
+        let mut histogram = Histogram::default();
+        for value in 1..=100_000 {
+            histogram.record(value);
+        }
+        assert_eq!(histogram.count(), 100_000);
+        assert_eq!(histogram.min(), Some(1));
+        assert_eq!(histogram.mean(), Some(50_000.5));
+        let within = |value: Option<u64>, expected: u64| {
+            let value = value.unwrap() as f64;
+            (value - expected as f64).abs() <= expected as f64 / SUB_BUCKETS as f64
+        };
+        assert!(within(histogram.value_at_quantile(0.5), 50_000));
+        assert!(within(histogram.value_at_quantile(0.99), 99_000));
+        assert!(within(histogram.max(), 100_000));
+        // Small values are exact
+        assert_eq!(histogram.value_at_quantile(0.00001), Some(1));
+    }
+
+    #[test]
+    fn merged_distribution() {
+        let mut fast = Histogram::default();
+        let mut slow = Histogram::default();
+        for _ in 0..99 {
+            fast.record(10);
+        }
+        slow.record(1_000_000);
+        fast.merge(&slow);
+        assert_eq!(fast.value_at_quantile(0.99), Some(10));
+        assert!(fast.value_at_quantile(1.0).unwrap() >= 1_000_000);
+        let distribution = fast.distribution(2);
+        assert_eq!(distribution[0], (10, 0.0, 99));
+        assert_eq!(distribution.last().unwrap().2, 100);
+    }
+}
@@ -0,0 +1,535 @@
+use bigdecimal::BigDecimal;
+use clap::{App, Arg, ArgMatches};
+use engine::Side;
+use futures::{SinkExt, StreamExt};
+use histogram::Histogram;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use server::{OrderId, Quantity, RequestId, ToClient, ToServer, CLIENT_ADDR};
+use std::{collections::HashMap, io, str::FromStr, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+mod histogram;
+
+/// How long sessions wait for the answers to their last messages
+const GRACE: Duration = Duration::from_secs(2);
+/// Quantiles of the latency summary
+const QUANTILES: [(&str, f64); 4] = [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)];
+
+/// Messages sent, each answered by one reply of the server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Kind {
+    Place,
+    Cancel,
+    Replace,
+    Depth,
+    Snapshot,
+    Analytics,
+}
+
+const KINDS: [Kind; 6] = [
+    Kind::Place,
+    Kind::Cancel,
+    Kind::Replace,
+    Kind::Depth,
+    Kind::Snapshot,
+    Kind::Analytics,
+];
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Place => "place",
+            Kind::Cancel => "cancel",
+            Kind::Replace => "replace",
+            Kind::Depth => "depth",
+            Kind::Snapshot => "snapshot",
+            Kind::Analytics => "analytics",
+        }
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KINDS
+            .iter()
+            .find(|kind| kind.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!("Unknown message type {}", s))
+    }
+}
+
+/// Weights of the message types sent, e.g. place=60,cancel=30,depth=10
+struct Mix(Vec<(Kind, u32)>);
+
+impl FromStr for Mix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = vec![];
+        for part in s.split(',') {
+            let (kind, weight) = match part.split_once('=') {
+                Some((kind, weight)) => (kind.trim(), weight.trim()),
+                None => return Err(format!("Expected type=weight in {}", part)),
+            };
+            let weight = weight
+                .parse::<u32>()
+                .map_err(|_| format!("Invalid weight {}", weight))?;
+            weights.push((kind.parse()?, weight));
+        }
+        if weights.iter().all(|(_, weight)| *weight == 0) {
+            return Err("The mix needs a message type with some weight".to_string());
+        }
+        Ok(Mix(weights))
+    }
+}
+
+impl Mix {
+    fn sample(&self, rng: &mut StdRng) -> Kind {
+        let total: u32 = self.0.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.gen_range(0..total);
+        for (kind, weight) in &self.0 {
+            if pick < *weight {
+                return *kind;
+            }
+            pick -= weight;
+        }
+        Kind::Place
+    }
+}
+
+struct Config {
+    addr: String,
+    /// Accounts are this followed by the number of the session
+    account: String,
+    password: String,
+    /// Time between two messages of a session
+    interval: Duration,
+    duration: Duration,
+    mix: Mix,
+    price: BigDecimal,
+    tick: BigDecimal,
+    /// Orders are placed up to this many ticks away from the price, those at
+    /// the price cross the book
+    width: u32,
+    max_size: Quantity,
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: [u64; KINDS.len()],
+    rejected: [u64; KINDS.len()],
+    /// Nanoseconds from sending a message to its answer
+    latencies: [Histogram; KINDS.len()],
+    unanswered: u64,
+    fills: u64,
+    filled_quantity: u64,
+}
+
+impl Stats {
+    fn merge(&mut self, other: &Stats) {
+        for index in 0..KINDS.len() {
+            self.sent[index] += other.sent[index];
+            self.rejected[index] += other.rejected[index];
+            self.latencies[index].merge(&other.latencies[index]);
+        }
+        self.unanswered += other.unanswered;
+        self.fills += other.fills;
+        self.filled_quantity += other.filled_quantity;
+    }
+}
+
+/// A message waiting for its answer
+struct Outstanding {
+    kind: Kind,
+    sent: Instant,
+    side: Side,
+    quantity: Quantity,
+}
+
+type Socket = Framed<TcpStream, LengthDelimitedCodec>;
+
+fn invalid<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+async fn send(socket: &mut Socket, message: &ToServer) -> io::Result<()> {
+    socket
+        .send(bincode::serialize(message).map_err(invalid)?.into())
+        .await
+}
+
+async fn receive(socket: &mut Socket) -> io::Result<ToClient> {
+    match socket.next().await {
+        Some(frame) => bincode::deserialize(&frame?).map_err(invalid),
+        None => Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The server closed the connection",
+        )),
+    }
+}
+
+fn random_side(rng: &mut StdRng) -> Side {
+    if rng.gen() {
+        Side::Bid
+    } else {
+        Side::Ask
+    }
+}
+
+/// Price up to `width` ticks away from the price on the side of the order
+fn random_price(config: &Config, side: Side, rng: &mut StdRng) -> BigDecimal {
+    let offset = &config.tick * BigDecimal::from(rng.gen_range(0..=config.width));
+    match side {
+        Side::Bid => &config.price - offset,
+        Side::Ask => &config.price + offset,
+    }
+}
+
+/// Sends messages at the interval of the config until its duration is over
+/// and measures how long the server takes to answer them. Orders that were
+/// not cancelled are left to the server to cancel on disconnect.
+async fn session(number: usize, config: Arc<Config>) -> io::Result<Stats> {
+    let stream = TcpStream::connect(&config.addr).await?;
+    // Small messages are sent right away rather than batched
+    stream.set_nodelay(true)?;
+    let mut socket = Framed::new(stream, LengthDelimitedCodec::new());
+    send(
+        &mut socket,
+        &ToServer::Login(
+            format!("{}{}", config.account, number),
+            config.password.clone(),
+        ),
+    )
+    .await?;
+    // Messages sent before the server assigned the session an id are dropped,
+    // the snapshot comes after it
+    while !matches!(receive(&mut socket).await?, ToClient::Snapshot(..)) {}
+
+    let mut rng = StdRng::from_entropy();
+    let mut stats = Stats::default();
+    // Requests waiting for their reply by their number
+    let mut outstanding: HashMap<RequestId, Outstanding> = HashMap::new();
+    let mut request_counter: RequestId = 0;
+    // Resting orders of the session with their side and quantity left
+    let mut orders: HashMap<OrderId, (Side, Quantity)> = HashMap::new();
+    let end = Instant::now() + config.duration;
+    let mut timer = time::interval(config.interval);
+    let drained = time::sleep_until(end + GRACE);
+    tokio::pin!(drained);
+    let mut sending = true;
+    loop {
+        tokio::select! {
+            due = timer.tick(), if sending => {
+                if due >= end {
+                    sending = false;
+                    continue;
+                }
+                let mut kind = config.mix.sample(&mut rng);
+                let order = match kind {
+                    Kind::Cancel | Kind::Replace if !orders.is_empty() => {
+                        let index = rng.gen_range(0..orders.len());
+                        orders.keys().nth(index).copied()
+                    }
+                    _ => None,
+                };
+                let order = order.and_then(|order_id| Some((order_id, orders.remove(&order_id)?)));
+                if order.is_none() && matches!(kind, Kind::Cancel | Kind::Replace) {
+                    kind = Kind::Place;
+                }
+                let side = order.map_or_else(|| random_side(&mut rng), |(_, (side, _))| side);
+                let quantity = rng.gen_range(1..=config.max_size);
+                let message = match (kind, order) {
+                    (Kind::Cancel, Some((order_id, _))) => ToServer::CancelOrder(order_id),
+                    (Kind::Replace, Some((order_id, _))) => ToServer::ReplaceOrder(
+                        order_id,
+                        random_price(&config, side, &mut rng).as_bigint_and_exponent(),
+                        quantity,
+                    ),
+                    (Kind::Depth, _) => ToServer::GetBookDepth(side),
+                    (Kind::Snapshot, _) => ToServer::RequestSnapshot,
+                    (Kind::Analytics, _) => {
+                        ToServer::GetAnalytics(5, BigDecimal::from(10).as_bigint_and_exponent())
+                    }
+                    _ => ToServer::PlaceOrder(
+                        side,
+                        random_price(&config, side, &mut rng).as_bigint_and_exponent(),
+                        quantity,
+                        engine::TimeInForce::GoodTillCancel,
+                    ),
+                };
+                let request_id = request_counter;
+                request_counter += 1;
+                let sent = Instant::now();
+                send(&mut socket, &ToServer::Request(request_id, Box::new(message))).await?;
+                stats.sent[kind as usize] += 1;
+                outstanding.insert(request_id, Outstanding { kind, sent, side, quantity });
+            }
+            _ = &mut drained => break,
+            message = receive(&mut socket) => match message? {
+                ToClient::Reply(request_id, reply) => match (outstanding.remove(&request_id), *reply) {
+                    (Some(rejected), ToClient::Rejected(_)) => {
+                        stats.rejected[rejected.kind as usize] += 1;
+                    }
+                    (Some(answered), reply) => {
+                        let latency = answered.sent.elapsed().as_nanos() as u64;
+                        stats.latencies[answered.kind as usize].record(latency);
+                        if let ToClient::OrderAccepted(order_id)
+                        | ToClient::OrderReplaced(_, order_id) = reply
+                        {
+                            orders.insert(order_id, (answered.side, answered.quantity));
+                        }
+                    }
+                    (None, _) => {}
+                },
+                ToClient::Fill(order_id, _, quantity) => {
+                    stats.fills += 1;
+                    stats.filled_quantity += quantity as u64;
+                    if let Some((_, remaining)) = orders.get_mut(&order_id) {
+                        *remaining = remaining.saturating_sub(quantity);
+                        if *remaining == 0 {
+                            orders.remove(&order_id);
+                        }
+                    }
+                }
+                // Resyncs and updates of the book aren't answers
+                _ => {}
+            },
+        }
+        if !sending && outstanding.is_empty() {
+            break;
+        }
+    }
+    stats.unanswered = outstanding.len() as u64;
+    Ok(stats)
+}
+
+fn micros(nanos: Option<u64>) -> String {
+    nanos.map_or("-".to_string(), |nanos| {
+        format!("{:.1}", nanos as f64 / 1000.0)
+    })
+}
+
+fn print_report(stats: &Stats, sessions: usize, duration: Duration, distribution: bool) {
+    let seconds = duration.as_secs_f64();
+    let sent: u64 = stats.sent.iter().sum();
+    let rejected: u64 = stats.rejected.iter().sum();
+    let answered: u64 = stats.latencies.iter().map(Histogram::count).sum();
+    println!("sessions: {}, duration: {:.1}s", sessions, seconds);
+    println!(
+        "sent: {} ({:.1}/s), answered: {} ({:.1}/s), rejected: {}, unanswered: {}",
+        sent,
+        sent as f64 / seconds,
+        answered,
+        answered as f64 / seconds,
+        rejected,
+        stats.unanswered
+    );
+    println!(
+        "fills: {}, filled quantity: {}",
+        stats.fills, stats.filled_quantity
+    );
+    println!();
+    print!(
+        "{:<12}{:>10}{:>10}{:>10}{:>10}",
+        "latency µs", "sent", "rejected", "mean", "min"
+    );
+    for (name, _) in &QUANTILES {
+        print!("{:>10}", name);
+    }
+    println!("{:>10}", "max");
+    for (kind, index) in KINDS.iter().zip(0..) {
+        let latencies = &stats.latencies[index];
+        if stats.sent[index] == 0 {
+            continue;
+        }
+        print!(
+            "{:<12}{:>10}{:>10}{:>10}{:>10}",
+            kind.name(),
+            stats.sent[index],
+            stats.rejected[index],
+            micros(latencies.mean().map(|mean| mean as u64)),
+            micros(latencies.min())
+        );
+        for (_, quantile) in &QUANTILES {
+            print!("{:>10}", micros(latencies.value_at_quantile(*quantile)));
+        }
+        println!("{:>10}", micros(latencies.max()));
+    }
+    if !distribution {
+        return;
+    }
+    for (kind, latencies) in KINDS.iter().zip(&stats.latencies) {
+        if latencies.count() == 0 {
+            continue;
+        }
+        println!();
+        println!("{}", kind.name());
+        println!(
+            "{:>12} {:>12} {:>12} {:>16}",
+            "Value µs", "Percentile", "TotalCount", "1/(1-Percentile)"
+        );
+        for (value, quantile, count) in latencies.distribution(5) {
+            let inverse = if quantile < 1.0 {
+                format!("{:.2}", 1.0 / (1.0 - quantile))
+            } else {
+                "inf".to_string()
+            };
+            println!(
+                "{:>12} {:>12.6} {:>12} {:>16}",
+                micros(Some(value)),
+                quantile,
+                count,
+                inverse
+            );
+        }
+    }
+}
+
+fn parse_arg<T: FromStr>(args: &ArgMatches, name: &str) -> io::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = args.value_of(name).unwrap_or_default();
+    value.parse::<T>().map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid value {} for {}: {}", value, name, e),
+        )
+    })
+}
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = App::new("loadgen")
+        .about("Sends orders, cancels and queries over many sessions and reports the latency of their answers")
+        .arg(
+            Arg::new("addr")
+                .long("addr")
+                .takes_value(true)
+                .default_value(CLIENT_ADDR)
+                .help("Address of the server"),
+        )
+        .arg(
+            Arg::new("sessions")
+                .long("sessions")
+                .short('n')
+                .takes_value(true)
+                .default_value("10")
+                .help("Concurrent sessions"),
+        )
+        .arg(
+            Arg::new("rate")
+                .long("rate")
+                .short('r')
+                .takes_value(true)
+                .default_value("1000")
+                .help("Messages per second of all sessions together"),
+        )
+        .arg(
+            Arg::new("duration")
+                .long("duration")
+                .short('d')
+                .takes_value(true)
+                .default_value("10")
+                .help("Seconds to send messages for"),
+        )
+        .arg(
+            Arg::new("mix")
+                .long("mix")
+                .takes_value(true)
+                .default_value("place=50,cancel=30,replace=10,depth=5,snapshot=3,analytics=2")
+                .help("Weights of the message types sent"),
+        )
+        .arg(
+            Arg::new("account")
+                .long("account")
+                .takes_value(true)
+                .default_value("loadgen-")
+                .help("Accounts of the sessions, followed by their number"),
+        )
+        .arg(
+            Arg::new("password")
+                .long("password")
+                .takes_value(true)
+                .default_value("")
+                .help("Password of all accounts"),
+        )
+        .arg(
+            Arg::new("price")
+                .long("price")
+                .takes_value(true)
+                .default_value("100")
+                .help("Orders are placed around this price, crossing at it"),
+        )
+        .arg(
+            Arg::new("tick")
+                .long("tick")
+                .takes_value(true)
+                .default_value("0.01")
+                .help("Distance between the prices of orders"),
+        )
+        .arg(
+            Arg::new("width")
+                .long("width")
+                .takes_value(true)
+                .default_value("10")
+                .help("Ticks away from the price orders are placed up to"),
+        )
+        .arg(
+            Arg::new("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .default_value("10")
+                .help("Largest quantity of an order"),
+        )
+        .arg(
+            Arg::new("distribution")
+                .long("distribution")
+                .help("Print the percentile distribution of the latencies of each message type"),
+        )
+        .get_matches();
+
+    let sessions: usize = parse_arg::<usize>(&args, "sessions")?.max(1);
+    let rate: f64 = parse_arg(&args, "rate")?;
+    let interval = match Duration::try_from_secs_f64(sessions as f64 / rate) {
+        Ok(interval) if rate > 0.0 => interval.max(Duration::from_nanos(1)),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The rate must be a positive number of messages per second",
+            ))
+        }
+    };
+    let duration = Duration::try_from_secs_f64(parse_arg(&args, "duration")?).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The duration must be a number of seconds of at least 0",
+        )
+    })?;
+    let config = Arc::new(Config {
+        addr: parse_arg(&args, "addr")?,
+        account: parse_arg(&args, "account")?,
+        password: parse_arg(&args, "password")?,
+        interval,
+        duration,
+        mix: parse_arg(&args, "mix")?,
+        price: parse_arg(&args, "price")?,
+        tick: parse_arg(&args, "tick")?,
+        width: parse_arg(&args, "width")?,
+        max_size: parse_arg::<Quantity>(&args, "max-size")?.max(1),
+    });
+
+    let handles: Vec<_> = (0..sessions)
+        .map(|number| tokio::spawn(session(number, config.clone())))
+        .collect();
+    let mut stats = Stats::default();
+    for handle in handles {
+        stats.merge(&handle.await.map_err(invalid)??);
+    }
+    print_report(&stats, sessions, duration, args.is_present("distribution"));
+    Ok(())
+}
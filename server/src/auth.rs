@@ -0,0 +1,80 @@
+//! Passwords of the accounts sessions log in as
+
+use std::{collections::HashMap, fs, io, path::Path, str::FromStr};
+
+/// Outcome of a login
+#[derive(Debug, PartialEq, Eq)]
+pub enum Login {
+    /// The password matches the one of the account
+    Verified,
+    /// The server has no passwords, so the account is taken on trust
+    Unverified,
+    Refused,
+}
+
+/// Accounts and their passwords, one `account password` pair per line
+#[derive(Debug, Default)]
+pub struct Accounts(Option<HashMap<String, String>>);
+
+impl Accounts {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        fs::read_to_string(path)?
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn login(&self, account: &str, password: &str) -> Login {
+        let passwords = match &self.0 {
+            Some(passwords) => passwords,
+            None => return Login::Unverified,
+        };
+        match passwords.get(account) {
+            Some(expected) if constant_time_eq(expected.as_bytes(), password.as_bytes()) => {
+                Login::Verified
+            }
+            _ => Login::Refused,
+        }
+    }
+}
+
+impl FromStr for Accounts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(account), Some(password), None) => {
+                        Ok((account.to_string(), password.to_string()))
+                    }
+                    _ => Err(format!("Expected account and password in {}", line)),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(|passwords| Accounts(Some(passwords)))
+    }
+}
+
+/// Compares without returning early, so the time taken does not tell how
+/// much of a password was right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login() {
+        let accounts: Accounts = "# comment\nalice secret\n\nbob hunter2".parse().unwrap();
+        assert_eq!(accounts.login("alice", "secret"), Login::Verified);
+        assert_eq!(accounts.login("alice", "hunter2"), Login::Refused);
+        assert_eq!(accounts.login("carol", ""), Login::Refused);
+        assert_eq!(Accounts::default().login("carol", ""), Login::Unverified);
+        assert!("alice".parse::<Accounts>().is_err());
+    }
+}
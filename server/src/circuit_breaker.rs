@@ -0,0 +1,103 @@
+//! Price bands and volatility halts protecting the order book
+
+use crate::Price;
+use bigdecimal::BigDecimal;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Whether `price` is more than `percent` away from `reference`
+fn moved_more_than(reference: &Price, price: &Price, percent: &BigDecimal) -> bool {
+    (price - reference).abs() * BigDecimal::from(100) > percent * reference.abs()
+}
+
+/// Static band rejecting orders priced too far from a reference price
+pub struct PriceBand {
+    pub percent: BigDecimal,
+    pub reference: Option<Price>,
+}
+
+impl PriceBand {
+    /// Orders are always allowed until there is a reference price
+    pub fn allows(&self, price: &Price) -> bool {
+        match &self.reference {
+            Some(reference) => !moved_more_than(reference, price, &self.percent),
+            None => true,
+        }
+    }
+}
+
+/// Detects trades moving the price more than a percentage within a time window
+pub struct VolatilityGuard {
+    pub percent: BigDecimal,
+    pub window: Duration,
+    trades: VecDeque<(Instant, Price)>,
+}
+
+impl VolatilityGuard {
+    pub fn new(percent: BigDecimal, window: Duration) -> Self {
+        VolatilityGuard {
+            percent,
+            window,
+            trades: VecDeque::new(),
+        }
+    }
+
+    /// Records a trade and returns true if trading should be halted
+    pub fn on_trade(&mut self, now: Instant, price: Price) -> bool {
+        while let Some((time, _)) = self.trades.front() {
+            if now.duration_since(*time) <= self.window {
+                break;
+            }
+            self.trades.pop_front();
+        }
+        let breached = self
+            .trades
+            .iter()
+            .any(|(_, reference)| moved_more_than(reference, &price, &self.percent));
+        self.trades.push_back((now, price));
+        breached
+    }
+
+    /// Forgets the trades leading up to a halt so trading can resume
+    pub fn reset(&mut self) {
+        self.trades.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn price_band() {
+        let mut band = PriceBand {
+            percent: 10.into(),
+            reference: None,
+        };
+        assert!(band.allows(&1000.into()));
+        band.reference = Some(100.into());
+        assert!(band.allows(&110.into()));
+        assert!(band.allows(&90.into()));
+        assert!(!band.allows(&111.into()));
+        assert!(!band.allows(&89.into()));
+    }
+
+    #[test]
+    fn volatility_within_window() {
+        let mut guard = VolatilityGuard::new(5.into(), Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(!guard.on_trade(start, 100.into()));
+        assert!(!guard.on_trade(start + Duration::from_secs(1), 104.into()));
+        assert!(guard.on_trade(start + Duration::from_secs(2), 106.into()));
+    }
+
+    #[test]
+    fn volatility_outside_window() {
+        let mut guard = VolatilityGuard::new(5.into(), Duration::from_secs(10));
+        let start = Instant::now();
+        assert!(!guard.on_trade(start, 100.into()));
+        assert!(!guard.on_trade(start + Duration::from_secs(11), 106.into()));
+    }
+}
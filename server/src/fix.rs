@@ -0,0 +1,1553 @@
+//! FIX 4.4 order entry for the acceptor on the FIX port
+//!
+//! Sessions support Logon, Logout, Heartbeat, TestRequest, ResendRequest,
+//! SequenceReset and Reject. Orders are entered with NewOrderSingle (limit
+//! orders only), OrderCancelRequest and OrderCancelReplaceRequest and are
+//! answered with ExecutionReport and OrderCancelReject. Sequence numbers and
+//! sent application messages are kept on disk per counterparty so a session
+//! can resume after a reconnect or a restart of the server. Counterparties log
+//! on with the password of their SenderCompID in the accounts of the server.
+
+use crate::{
+    auth::{Accounts, Login},
+    OrderId, Price, Quantity, RejectReason, RequestId, ToClient, ToServer,
+};
+use bigdecimal::{BigDecimal, Zero};
+use bytes::{BufMut, BytesMut};
+use engine::{
+    expiry::{civil_from_days, days_from_civil, days_in_month},
+    Side, TimeInForce,
+};
+use std::{
+    collections::HashMap,
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 1;
+/// Largest BodyLength accepted from a counterparty
+const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+pub mod tag {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const EXPIRE_TIME: u32 = 126;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const EXPIRE_DATE: u32 = 432;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const PASSWORD: u32 = 554;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+}
+
+/// Tags set by the session when a message is sent
+const HEADER_TAGS: [u32; 7] = [
+    tag::MSG_TYPE,
+    tag::SENDER_COMP_ID,
+    tag::TARGET_COMP_ID,
+    tag::MSG_SEQ_NUM,
+    tag::POSS_DUP_FLAG,
+    tag::SENDING_TIME,
+    tag::ORIG_SENDING_TIME,
+];
+
+/// Fields of a message without BeginString, BodyLength and CheckSum
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Message {
+    pub fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Self {
+        Message {
+            fields: vec![(tag::MSG_TYPE, msg_type.to_string())],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_ref())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tag::MSG_TYPE).unwrap_or_default()
+    }
+
+    /// Value of a required field, the error names the missing or invalid tag
+    pub fn parse<T: FromStr>(&self, tag: u32) -> Result<T, u32> {
+        self.get(tag)
+            .and_then(|value| value.parse().ok())
+            .ok_or(tag)
+    }
+
+    fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+
+    fn encode_body(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+        body
+    }
+
+    fn decode_body(body: &[u8]) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid FIX field");
+        let fields = body
+            .split(|byte| *byte == SOH)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let field = std::str::from_utf8(field).map_err(|_| invalid())?;
+                let (tag, value) = field.split_once('=').ok_or_else(invalid)?;
+                Ok((tag.parse().map_err(|_| invalid())?, value.to_string()))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Message { fields })
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// Frames FIX messages by their BodyLength and verifies their CheckSum
+#[derive(Default)]
+pub struct FixCodec;
+
+impl Decoder for FixCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Message>> {
+        let invalid = |error: &str| io::Error::new(io::ErrorKind::InvalidData, error.to_string());
+        let prefix = format!("8={}\x019=", BEGIN_STRING);
+        if src.len() < prefix.len() {
+            return Ok(None);
+        }
+        if !src.starts_with(prefix.as_bytes()) {
+            return Err(invalid("Expected BeginString FIX.4.4"));
+        }
+        let length_digits = MAX_BODY_LENGTH.to_string().len();
+        let length_end = match src[prefix.len()..].iter().position(|byte| *byte == SOH) {
+            Some(position) if position <= length_digits => prefix.len() + position,
+            None if src.len() - prefix.len() <= length_digits => return Ok(None),
+            _ => return Err(invalid("Invalid BodyLength")),
+        };
+        let body_length: usize = std::str::from_utf8(&src[prefix.len()..length_end])
+            .ok()
+            .and_then(|length| length.parse().ok())
+            .filter(|length| *length <= MAX_BODY_LENGTH)
+            .ok_or_else(|| invalid("Invalid BodyLength"))?;
+        // The trailer is always 10=nnn<SOH>
+        let (body_end, end) = length_end
+            .checked_add(1 + body_length)
+            .and_then(|body_end| Some((body_end, body_end.checked_add(7)?)))
+            .ok_or_else(|| invalid("Invalid BodyLength"))?;
+        if src.len() < end {
+            return Ok(None);
+        }
+        let frame = src.split_to(end);
+        let trailer = std::str::from_utf8(&frame[body_end..end])
+            .ok()
+            .and_then(|trailer| trailer.strip_prefix("10="))
+            .and_then(|checksum| checksum.trim_end_matches('\x01').parse::<u8>().ok())
+            .ok_or_else(|| invalid("Invalid CheckSum field"))?;
+        if trailer != checksum(&frame[..body_end]) {
+            return Err(invalid("CheckSum mismatch"));
+        }
+        Message::decode_body(&frame[length_end + 1..body_end]).map(Some)
+    }
+}
+
+impl Encoder<Message> for FixCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> io::Result<()> {
+        let body = message.encode_body();
+        let start = dst.len();
+        dst.put_slice(format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).as_bytes());
+        dst.put_slice(&body);
+        let checksum = checksum(&dst[start..]);
+        dst.put_slice(format!("10={:03}\x01", checksum).as_bytes());
+        Ok(())
+    }
+}
+
+/// UTC time in the FIX format `YYYYMMDD-HH:MM:SS.sss`
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, secs_of_day) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Days since the epoch of a FIX date `YYYYMMDD`
+fn parse_utc_date(date: &str) -> Option<i64> {
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year = date[..4].parse().ok()?;
+    let month = date[4..6].parse().ok().filter(|m| (1..=12).contains(m))?;
+    let day = date[6..]
+        .parse()
+        .ok()
+        .filter(|d| (1..=days_in_month(year, month)).contains(d))?;
+    days_from_civil(year, month, day)
+}
+
+/// Time since the epoch of a FIX timestamp `YYYYMMDD-HH:MM:SS` with optional
+/// milliseconds
+fn parse_utc_timestamp(timestamp: &str) -> Option<Duration> {
+    let (date, time) = timestamp.split_once('-')?;
+    let days = u64::try_from(parse_utc_date(date)?).ok()?;
+    let (time, millis) = match time.split_once('.') {
+        Some((time, millis)) if millis.len() == 3 => (time, millis.parse().ok()?),
+        Some(_) => return None,
+        None => (time, 0),
+    };
+    let mut parts = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (parts.next()??, parts.next()??, parts.next()??);
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let secs = days * 86_400 + hours * 3600 + minutes * 60 + seconds;
+    Some(Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// Whether a comp id can name the files of a store, which rules out paths
+/// leading out of the store directory
+pub fn is_valid_comp_id(comp_id: &str) -> bool {
+    !comp_id.is_empty()
+        && !comp_id.starts_with('.')
+        && comp_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// Sequence numbers and sent application messages of a session
+pub struct Store {
+    seqnums_path: PathBuf,
+    messages_path: PathBuf,
+    pub next_in: u64,
+    pub next_out: u64,
+}
+
+impl Store {
+    /// Opens the files of the session between two comp ids, starting at 1 if there are none
+    pub fn open(dir: &Path, sender: &str, target: &str) -> io::Result<Self> {
+        if !is_valid_comp_id(sender) || !is_valid_comp_id(target) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid comp id in {}-{}", sender, target),
+            ));
+        }
+        fs::create_dir_all(dir)?;
+        let name = format!("{}-{}", sender, target);
+        let seqnums_path = dir.join(format!("{}.seqnums", name));
+        let (next_in, next_out) = match fs::read_to_string(&seqnums_path) {
+            Ok(seqnums) => {
+                let mut numbers = seqnums.split_whitespace().map(str::parse::<u64>);
+                match (numbers.next(), numbers.next()) {
+                    (Some(Ok(next_in)), Some(Ok(next_out))) => (next_in, next_out),
+                    _ => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Invalid sequence numbers in {:?}", seqnums_path),
+                        ))
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (1, 1),
+            Err(e) => return Err(e),
+        };
+        Ok(Store {
+            seqnums_path,
+            messages_path: dir.join(format!("{}.messages", name)),
+            next_in,
+            next_out,
+        })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(
+            &self.seqnums_path,
+            format!("{} {}\n", self.next_in, self.next_out),
+        )
+    }
+
+    /// Starts both sequences over at 1 and forgets the sent messages
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.next_in = 1;
+        self.next_out = 1;
+        File::create(&self.messages_path)?;
+        self.save()
+    }
+
+    fn append(&self, seq_num: u64, message: &Message) -> io::Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.messages_path)?;
+        let mut line = format!("{}\t", seq_num).into_bytes();
+        line.extend(message.encode_body());
+        line.push(b'\n');
+        file.write_all(&line)
+    }
+
+    /// Sent application messages with sequence numbers from `begin` to `end` inclusive
+    fn sent(&self, begin: u64, end: u64) -> io::Result<Vec<(u64, Message)>> {
+        let file = match File::open(&self.messages_path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut messages = Vec::new();
+        for line in BufReader::new(file).split(b'\n') {
+            let line = line?;
+            let tab = line.iter().position(|byte| *byte == b'\t').unwrap_or(0);
+            let seq_num = std::str::from_utf8(&line[..tab])
+                .ok()
+                .and_then(|seq_num| seq_num.parse().ok())
+                .unwrap_or(0);
+            if (begin..=end).contains(&seq_num) {
+                messages.push((seq_num, Message::decode_body(&line[tab + 1..])?));
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// What the gateway should do after the session handled an event
+#[derive(Debug)]
+pub enum Action {
+    Send(Message),
+    Forward(ToServer),
+    Disconnect,
+}
+
+/// Live order of the counterparty
+struct Order {
+    cl_ord_id: String,
+    side: Side,
+    price: Price,
+    quantity: Quantity,
+    cum_qty: Quantity,
+    notional: BigDecimal,
+}
+
+/// Request forwarded to the order manager and waiting for its reply
+enum Pending {
+    New(Order),
+    Cancel {
+        cl_ord_id: String,
+        order_id: OrderId,
+    },
+    Replace {
+        cl_ord_id: String,
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+}
+
+/// State of one FIX session with a counterparty
+///
+/// Requests are numbered when forwarded to the order manager, which answers
+/// with the same number, so replies are matched to the ClOrdID of the request.
+pub struct FixSession {
+    comp_id: String,
+    symbol: String,
+    store_dir: PathBuf,
+    accounts: Arc<Accounts>,
+    /// Comp id of the counterparty and the store once logged on
+    logon: Option<(String, Store)>,
+    heartbeat_interval: Duration,
+    last_received: Instant,
+    last_sent: Instant,
+    test_request_sent: bool,
+    /// Sequence number up to which a resend was requested
+    resend_until: Option<u64>,
+    orders: HashMap<OrderId, Order>,
+    cl_ord_ids: HashMap<String, OrderId>,
+    pending: HashMap<RequestId, Pending>,
+    request_counter: RequestId,
+    exec_counter: u64,
+}
+
+fn side_from_fix(side: &str) -> Option<Side> {
+    match side {
+        "1" => Some(Side::Bid),
+        "2" => Some(Side::Ask),
+        _ => None,
+    }
+}
+
+fn side_to_fix(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "1",
+        Side::Ask => "2",
+    }
+}
+
+/// TimeInForce Day, GTC or GTD with ExpireTime or ExpireDate, or the tag that
+/// is missing or invalid
+fn time_in_force_from_fix(message: &Message) -> Result<TimeInForce, u32> {
+    match message.get(tag::TIME_IN_FORCE) {
+        None | Some("1") => Ok(TimeInForce::GoodTillCancel),
+        Some("0") => Ok(TimeInForce::Day),
+        Some("6") => match (message.get(tag::EXPIRE_TIME), message.get(tag::EXPIRE_DATE)) {
+            (Some(time), _) => parse_utc_timestamp(time)
+                .map(TimeInForce::GoodTillTime)
+                .ok_or(tag::EXPIRE_TIME),
+            (None, Some(date)) => parse_utc_date(date)
+                .and_then(|days| u64::try_from(days).ok())
+                .map(TimeInForce::GoodTillDate)
+                .ok_or(tag::EXPIRE_DATE),
+            (None, None) => Err(tag::EXPIRE_TIME),
+        },
+        Some(_) => Err(tag::TIME_IN_FORCE),
+    }
+}
+
+impl FixSession {
+    pub fn new(
+        comp_id: &str,
+        symbol: &str,
+        store_dir: &Path,
+        accounts: Arc<Accounts>,
+        now: Instant,
+    ) -> Self {
+        FixSession {
+            comp_id: comp_id.to_string(),
+            symbol: symbol.to_string(),
+            store_dir: store_dir.to_path_buf(),
+            accounts,
+            logon: None,
+            heartbeat_interval: Duration::from_secs(30),
+            last_received: now,
+            last_sent: now,
+            test_request_sent: false,
+            resend_until: None,
+            orders: HashMap::new(),
+            cl_ord_ids: HashMap::new(),
+            pending: HashMap::new(),
+            request_counter: 0,
+            exec_counter: 0,
+        }
+    }
+
+    /// Adds the header and the next outgoing sequence number, storing
+    /// application messages for resends
+    fn stamp(&mut self, message: Message, now: Instant) -> io::Result<Message> {
+        let (target, store) = match &mut self.logon {
+            Some(logon) => logon,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "Not logged on")),
+        };
+        let seq_num = store.next_out;
+        let stamped = header(
+            &message,
+            &self.comp_id,
+            target,
+            seq_num,
+            utc_timestamp(SystemTime::now()),
+        );
+        if !is_session_message(message.msg_type()) {
+            store.append(seq_num, &stamped)?;
+        }
+        store.next_out += 1;
+        store.save()?;
+        self.last_sent = now;
+        Ok(stamped)
+    }
+
+    fn send(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: Message,
+        now: Instant,
+    ) -> io::Result<()> {
+        let stamped = self.stamp(message, now)?;
+        actions.push(Action::Send(stamped));
+        Ok(())
+    }
+
+    fn reject(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: &Message,
+        reason: u32,
+        ref_tag: Option<u32>,
+        text: &str,
+        now: Instant,
+    ) -> io::Result<()> {
+        let mut reject = Message::new(msg_type::REJECT)
+            .with(
+                tag::REF_SEQ_NUM,
+                message.get(tag::MSG_SEQ_NUM).unwrap_or_default(),
+            )
+            .with(tag::REF_MSG_TYPE, message.msg_type())
+            .with(tag::SESSION_REJECT_REASON, reason)
+            .with(tag::TEXT, text);
+        if let Some(ref_tag) = ref_tag {
+            reject = reject.with(tag::REF_TAG_ID, ref_tag);
+        }
+        self.send(actions, reject, now)
+    }
+
+    /// Forwards a request to the order manager under a new number
+    fn forward(&mut self, actions: &mut Vec<Action>, msg: ToServer, pending: Pending) {
+        self.request_counter += 1;
+        self.pending.insert(self.request_counter, pending);
+        actions.push(Action::Forward(ToServer::Request(
+            self.request_counter,
+            Box::new(msg),
+        )));
+    }
+
+    fn logout(&mut self, actions: &mut Vec<Action>, text: &str, now: Instant) -> io::Result<()> {
+        if self.logon.is_some() {
+            let logout = Message::new(msg_type::LOGOUT).with(tag::TEXT, text);
+            self.send(actions, logout, now)?;
+        }
+        actions.push(Action::Disconnect);
+        Ok(())
+    }
+
+    /// Handles a message from the counterparty
+    pub fn on_message(&mut self, message: Message, now: Instant) -> io::Result<Vec<Action>> {
+        let mut actions = Vec::new();
+        self.last_received = now;
+        self.test_request_sent = false;
+        let seq_num: u64 = match message.parse(tag::MSG_SEQ_NUM) {
+            Ok(seq_num) => seq_num,
+            Err(_) => {
+                self.logout(&mut actions, "MsgSeqNum missing", now)?;
+                return Ok(actions);
+            }
+        };
+
+        if self.logon.is_none() {
+            if message.msg_type() != msg_type::LOGON {
+                actions.push(Action::Disconnect);
+                return Ok(actions);
+            }
+            self.on_logon(&mut actions, &message, now)?;
+            if self.logon.is_none() {
+                return Ok(actions);
+            }
+        }
+
+        // A reset moves the expected sequence number whatever the message's own
+        let gap_fill = message.flag(tag::GAP_FILL_FLAG);
+        if message.msg_type() == msg_type::SEQUENCE_RESET && !gap_fill {
+            if let Ok(new_seq_no) = message.parse::<u64>(tag::NEW_SEQ_NO) {
+                self.set_next_in(new_seq_no)?;
+            }
+            return Ok(actions);
+        }
+
+        let next_in = self.next_in();
+        if seq_num < next_in {
+            if !message.flag(tag::POSS_DUP_FLAG) {
+                let text = format!("MsgSeqNum too low, expecting {}", next_in);
+                self.logout(&mut actions, &text, now)?;
+            }
+            return Ok(actions);
+        }
+        if seq_num > next_in {
+            if self.resend_until.is_none_or(|until| until < next_in) {
+                self.resend_until = Some(seq_num);
+                let resend = Message::new(msg_type::RESEND_REQUEST)
+                    .with(tag::BEGIN_SEQ_NO, next_in)
+                    .with(tag::END_SEQ_NO, 0);
+                self.send(&mut actions, resend, now)?;
+            }
+            // Resend requests are answered even while the gap is filled
+            if message.msg_type() == msg_type::RESEND_REQUEST {
+                self.on_resend_request(&mut actions, &message, now)?;
+            }
+            return Ok(actions);
+        }
+        self.set_next_in(seq_num + 1)?;
+
+        match message.msg_type() {
+            msg_type::LOGON | msg_type::HEARTBEAT | msg_type::REJECT => {}
+            msg_type::TEST_REQUEST => {
+                let heartbeat = Message::new(msg_type::HEARTBEAT).with(
+                    tag::TEST_REQ_ID,
+                    message.get(tag::TEST_REQ_ID).unwrap_or_default(),
+                );
+                self.send(&mut actions, heartbeat, now)?;
+            }
+            msg_type::RESEND_REQUEST => self.on_resend_request(&mut actions, &message, now)?,
+            msg_type::SEQUENCE_RESET => {
+                if let Ok(new_seq_no) = message.parse::<u64>(tag::NEW_SEQ_NO) {
+                    if new_seq_no > seq_num {
+                        self.set_next_in(new_seq_no)?;
+                    }
+                }
+            }
+            msg_type::LOGOUT => self.logout(&mut actions, "Logout acknowledged", now)?,
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order(&mut actions, &message, now)?,
+            msg_type::ORDER_CANCEL_REQUEST | msg_type::ORDER_CANCEL_REPLACE_REQUEST => {
+                self.on_cancel(&mut actions, &message, now)?
+            }
+            _ => {
+                // Invalid MsgType
+                self.reject(&mut actions, &message, 11, None, "Unsupported MsgType", now)?
+            }
+        }
+        Ok(actions)
+    }
+
+    fn next_in(&self) -> u64 {
+        self.logon.as_ref().map_or(1, |(_, store)| store.next_in)
+    }
+
+    fn set_next_in(&mut self, next_in: u64) -> io::Result<()> {
+        if let Some((_, store)) = &mut self.logon {
+            store.next_in = next_in;
+            store.save()?;
+        }
+        Ok(())
+    }
+
+    fn on_logon(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: &Message,
+        now: Instant,
+    ) -> io::Result<()> {
+        let target = message
+            .get(tag::SENDER_COMP_ID)
+            .unwrap_or_default()
+            .to_string();
+        let password = message.get(tag::PASSWORD).unwrap_or_default().to_string();
+        // Only counterparties with a password may log on, anyone else could
+        // reset or read the store of another
+        if !is_valid_comp_id(&target)
+            || message.get(tag::TARGET_COMP_ID) != Some(self.comp_id.as_str())
+            || self.accounts.login(&target, &password) != Login::Verified
+        {
+            actions.push(Action::Disconnect);
+            return Ok(());
+        }
+        let mut store = Store::open(&self.store_dir, &self.comp_id, &target)?;
+        let reset = message.flag(tag::RESET_SEQ_NUM_FLAG);
+        if reset {
+            store.reset()?;
+        }
+        if let Ok(interval) = message.parse::<u64>(tag::HEART_BT_INT) {
+            self.heartbeat_interval = Duration::from_secs(interval.max(1));
+        }
+        self.logon = Some((target.clone(), store));
+        let mut reply = Message::new(msg_type::LOGON)
+            .with(tag::ENCRYPT_METHOD, 0)
+            .with(tag::HEART_BT_INT, self.heartbeat_interval.as_secs());
+        if reset {
+            reply = reply.with(tag::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        self.send(actions, reply, now)?;
+        // Rate limits are shared by the sessions of a counterparty
+        actions.push(Action::Forward(ToServer::Login(target, password)));
+        Ok(())
+    }
+
+    /// Resends stored application messages and skips session messages with gap fills
+    fn on_resend_request(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: &Message,
+        now: Instant,
+    ) -> io::Result<()> {
+        let (target, store) = match &self.logon {
+            Some(logon) => logon,
+            None => return Ok(()),
+        };
+        let last_sent = store.next_out - 1;
+        let begin: u64 = message.parse(tag::BEGIN_SEQ_NO).unwrap_or(1).max(1);
+        let end = match message.parse::<u64>(tag::END_SEQ_NO).unwrap_or(0) {
+            0 => last_sent,
+            end => end.min(last_sent),
+        };
+        let sending_time = utc_timestamp(SystemTime::now());
+        let mut next = begin;
+        let mut resent = Vec::new();
+        let gap_fill = |seq_num, new_seq_no| {
+            let gap_fill = Message::new(msg_type::SEQUENCE_RESET)
+                .with(tag::GAP_FILL_FLAG, "Y")
+                .with(tag::NEW_SEQ_NO, new_seq_no);
+            poss_dup(
+                &gap_fill,
+                &self.comp_id,
+                target,
+                seq_num,
+                &sending_time,
+                None,
+            )
+        };
+        for (seq_num, stored) in store.sent(begin, end)? {
+            if seq_num > next {
+                resent.push(gap_fill(next, seq_num));
+            }
+            let original_time = stored.get(tag::SENDING_TIME).map(str::to_string);
+            resent.push(poss_dup(
+                &stored,
+                &self.comp_id,
+                target,
+                seq_num,
+                &sending_time,
+                original_time,
+            ));
+            next = seq_num + 1;
+        }
+        if next <= end {
+            resent.push(gap_fill(next, end + 1));
+        }
+        actions.extend(resent.into_iter().map(Action::Send));
+        self.last_sent = now;
+        Ok(())
+    }
+
+    fn on_new_order(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: &Message,
+        now: Instant,
+    ) -> io::Result<()> {
+        let parsed = (|| {
+            let cl_ord_id: String = message.parse(tag::CL_ORD_ID)?;
+            let side = message
+                .get(tag::SIDE)
+                .and_then(side_from_fix)
+                .ok_or(tag::SIDE)?;
+            let quantity: Quantity = message.parse(tag::ORDER_QTY)?;
+            // Only limit orders need a price, the others are rejected below
+            let price: Price = match message.get(tag::ORD_TYPE) {
+                Some("2") => message.parse(tag::PRICE)?,
+                _ => message
+                    .parse(tag::PRICE)
+                    .unwrap_or_else(|_| BigDecimal::zero()),
+            };
+            let time_in_force = time_in_force_from_fix(message)?;
+            Ok((cl_ord_id, side, quantity, price, time_in_force))
+        })();
+        let (cl_ord_id, side, quantity, price, time_in_force) = match parsed {
+            Ok(order) => order,
+            Err(tag) => {
+                // Required tag missing or value incorrect
+                let text = format!("Missing or invalid tag {}", tag);
+                return self.reject(actions, message, 1, Some(tag), &text, now);
+            }
+        };
+        let order = Order {
+            cl_ord_id,
+            side,
+            price,
+            quantity,
+            cum_qty: 0,
+            notional: BigDecimal::zero(),
+        };
+        let rejected = if message.get(tag::ORD_TYPE) != Some("2") {
+            Some("Only limit orders are supported")
+        } else if message.get(tag::SYMBOL) != Some(self.symbol.as_str()) {
+            Some("Unknown symbol")
+        } else {
+            None
+        };
+        if let Some(text) = rejected {
+            let report = self
+                .execution_report(None, &order, "8", "8", None)
+                .with(tag::ORD_REJ_REASON, 99)
+                .with(tag::TEXT, text);
+            return self.send(actions, report, now);
+        }
+        let place = ToServer::PlaceOrder(
+            order.side,
+            order.price.as_bigint_and_exponent(),
+            order.quantity,
+            time_in_force,
+        );
+        self.forward(actions, place, Pending::New(order));
+        Ok(())
+    }
+
+    fn on_cancel(
+        &mut self,
+        actions: &mut Vec<Action>,
+        message: &Message,
+        now: Instant,
+    ) -> io::Result<()> {
+        let replace = message.msg_type() == msg_type::ORDER_CANCEL_REPLACE_REQUEST;
+        let parsed = (|| {
+            let cl_ord_id: String = message.parse(tag::CL_ORD_ID)?;
+            let orig_cl_ord_id: String = message.parse(tag::ORIG_CL_ORD_ID)?;
+            let replacement = if replace {
+                let price: Price = message.parse(tag::PRICE)?;
+                let quantity: Quantity = message.parse(tag::ORDER_QTY)?;
+                Some((price, quantity))
+            } else {
+                None
+            };
+            Ok((cl_ord_id, orig_cl_ord_id, replacement))
+        })();
+        let (cl_ord_id, orig_cl_ord_id, replacement) = match parsed {
+            Ok(request) => request,
+            Err(tag) => {
+                let text = format!("Missing or invalid tag {}", tag);
+                return self.reject(actions, message, 1, Some(tag), &text, now);
+            }
+        };
+        let order = self
+            .cl_ord_ids
+            .get(&orig_cl_ord_id)
+            .and_then(|order_id| Some((*order_id, self.orders.get(order_id)?)));
+        let (order_id, order) = match order {
+            Some(order) => order,
+            None => {
+                let reject = cancel_reject(&cl_ord_id, &orig_cl_ord_id, replace, "Unknown order")
+                    .with(tag::CXL_REJ_REASON, 1);
+                return self.send(actions, reject, now);
+            }
+        };
+        match replacement {
+            // OrderQty includes what has been filled already
+            Some((_, quantity)) if quantity <= order.cum_qty => {
+                let reject =
+                    cancel_reject(&cl_ord_id, &orig_cl_ord_id, true, "OrderQty already filled")
+                        .with(tag::ORDER_ID, order_id);
+                self.send(actions, reject, now)
+            }
+            Some((price, quantity)) => {
+                let leaves = quantity - order.cum_qty;
+                let replace =
+                    ToServer::ReplaceOrder(order_id, price.as_bigint_and_exponent(), leaves);
+                let pending = Pending::Replace {
+                    cl_ord_id,
+                    order_id,
+                    price,
+                    quantity,
+                };
+                self.forward(actions, replace, pending);
+                Ok(())
+            }
+            None => {
+                let pending = Pending::Cancel {
+                    cl_ord_id,
+                    order_id,
+                };
+                self.forward(actions, ToServer::CancelOrder(order_id), pending);
+                Ok(())
+            }
+        }
+    }
+
+    fn execution_report(
+        &mut self,
+        order_id: Option<OrderId>,
+        order: &Order,
+        exec_type: &str,
+        ord_status: &str,
+        orig_cl_ord_id: Option<&str>,
+    ) -> Message {
+        self.exec_counter += 1;
+        let avg_px = if order.cum_qty == 0 {
+            BigDecimal::zero()
+        } else {
+            // `round` panics on the digits of a quotient that doesn't
+            // terminate, cutting it down to 9 decimals first avoids that
+            (&order.notional / BigDecimal::from(order.cum_qty as u64))
+                .with_scale(9)
+                .round(8)
+                .normalized()
+        };
+        let mut report = Message::new(msg_type::EXECUTION_REPORT).with(
+            tag::ORDER_ID,
+            order_id.map_or("NONE".to_string(), |order_id| order_id.to_string()),
+        );
+        report = report.with(tag::CL_ORD_ID, &order.cl_ord_id);
+        if let Some(orig_cl_ord_id) = orig_cl_ord_id {
+            report = report.with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id);
+        }
+        let leaves = match ord_status {
+            "2" | "4" | "8" | "C" => 0,
+            _ => order.quantity - order.cum_qty,
+        };
+        report
+            .with(
+                tag::EXEC_ID,
+                format!("{}-{}", utc_timestamp(SystemTime::now()), self.exec_counter),
+            )
+            .with(tag::EXEC_TYPE, exec_type)
+            .with(tag::ORD_STATUS, ord_status)
+            .with(tag::SYMBOL, &self.symbol)
+            .with(tag::SIDE, side_to_fix(order.side))
+            .with(tag::ORDER_QTY, order.quantity)
+            .with(tag::PRICE, &order.price)
+            .with(tag::LEAVES_QTY, leaves)
+            .with(tag::CUM_QTY, order.cum_qty)
+            .with(tag::AVG_PX, avg_px)
+            .with(tag::TRANSACT_TIME, utc_timestamp(SystemTime::now()))
+    }
+
+    fn open_status(order: &Order) -> &'static str {
+        if order.cum_qty == 0 {
+            "0"
+        } else {
+            "1"
+        }
+    }
+
+    fn forget(&mut self, order_id: OrderId) -> Option<Order> {
+        let order = self.orders.remove(&order_id)?;
+        self.cl_ord_ids.remove(&order.cl_ord_id);
+        Some(order)
+    }
+
+    /// Turns a message of the order manager into execution reports
+    pub fn on_to_client(&mut self, msg: ToClient, now: Instant) -> io::Result<Vec<Action>> {
+        let mut actions = Vec::new();
+        match msg {
+            ToClient::Reply(request, msg) => {
+                if let Some(pending) = self.pending.remove(&request) {
+                    self.on_reply(&mut actions, pending, *msg, now)?;
+                }
+            }
+            ToClient::Fill(order_id, (digits, scale), quantity) => {
+                let price = BigDecimal::new(digits, scale);
+                let mut order = match self.orders.remove(&order_id) {
+                    Some(order) => order,
+                    None => return Ok(actions),
+                };
+                order.cum_qty += quantity;
+                order.notional += &price * BigDecimal::from(quantity as u64);
+                let filled = order.cum_qty >= order.quantity;
+                let ord_status = if filled { "2" } else { "1" };
+                let report = self
+                    .execution_report(Some(order_id), &order, "F", ord_status, None)
+                    .with(tag::LAST_PX, price)
+                    .with(tag::LAST_QTY, quantity);
+                if filled {
+                    self.cl_ord_ids.remove(&order.cl_ord_id);
+                } else {
+                    self.orders.insert(order_id, order);
+                }
+                self.send(&mut actions, report, now)?;
+            }
+            // Cancels not requested by the counterparty, such as from the admin
+            // interface, report the ClOrdID of the order itself
+            ToClient::OrderCancelled(order_id) => {
+                if let Some(order) = self.forget(order_id) {
+                    let report = self.execution_report(Some(order_id), &order, "4", "4", None);
+                    self.send(&mut actions, report, now)?;
+                }
+            }
+            ToClient::OrderExpired(order_id) => {
+                if let Some(order) = self.forget(order_id) {
+                    let report = self.execution_report(Some(order_id), &order, "C", "C", None);
+                    self.send(&mut actions, report, now)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(actions)
+    }
+
+    /// Answers a request of the counterparty with the reply of the order manager
+    fn on_reply(
+        &mut self,
+        actions: &mut Vec<Action>,
+        pending: Pending,
+        msg: ToClient,
+        now: Instant,
+    ) -> io::Result<()> {
+        match (pending, msg) {
+            (Pending::New(order), ToClient::OrderAccepted(order_id)) => {
+                let report = self.execution_report(Some(order_id), &order, "0", "0", None);
+                self.send(actions, report, now)?;
+                self.cl_ord_ids.insert(order.cl_ord_id.clone(), order_id);
+                self.orders.insert(order_id, order);
+            }
+            (pending, ToClient::Rejected(reason)) => {
+                let text = format!("{:?}", reason);
+                let reject = match pending {
+                    Pending::New(order) => self
+                        .execution_report(None, &order, "8", "8", None)
+                        .with(tag::ORD_REJ_REASON, ord_rej_reason(reason))
+                        .with(tag::TEXT, text),
+                    Pending::Cancel {
+                        cl_ord_id,
+                        order_id,
+                    } => self.cancel_reject(&cl_ord_id, order_id, false, &text),
+                    Pending::Replace {
+                        cl_ord_id,
+                        order_id,
+                        ..
+                    } => self.cancel_reject(&cl_ord_id, order_id, true, &text),
+                };
+                self.send(actions, reject, now)?;
+            }
+            (Pending::Cancel { cl_ord_id, .. }, ToClient::OrderCancelled(order_id)) => {
+                if let Some(mut order) = self.forget(order_id) {
+                    let orig_cl_ord_id = std::mem::replace(&mut order.cl_ord_id, cl_ord_id);
+                    let report = self.execution_report(
+                        Some(order_id),
+                        &order,
+                        "4",
+                        "4",
+                        Some(&orig_cl_ord_id),
+                    );
+                    self.send(actions, report, now)?;
+                }
+            }
+            (
+                Pending::Replace {
+                    cl_ord_id,
+                    price,
+                    quantity,
+                    ..
+                },
+                ToClient::OrderReplaced(order_id, new_order_id),
+            ) => {
+                let mut order = match self.forget(order_id) {
+                    Some(order) => order,
+                    None => return Ok(()),
+                };
+                let orig_cl_ord_id = std::mem::replace(&mut order.cl_ord_id, cl_ord_id);
+                order.price = price;
+                order.quantity = quantity;
+                let ord_status = Self::open_status(&order);
+                let report = self.execution_report(
+                    Some(new_order_id),
+                    &order,
+                    "5",
+                    ord_status,
+                    Some(&orig_cl_ord_id),
+                );
+                self.send(actions, report, now)?;
+                self.cl_ord_ids
+                    .insert(order.cl_ord_id.clone(), new_order_id);
+                self.orders.insert(new_order_id, order);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn cancel_reject(
+        &self,
+        cl_ord_id: &str,
+        order_id: OrderId,
+        replace: bool,
+        text: &str,
+    ) -> Message {
+        let orig_cl_ord_id = self
+            .orders
+            .get(&order_id)
+            .map(|order| order.cl_ord_id.as_str())
+            .unwrap_or_default();
+        cancel_reject(cl_ord_id, orig_cl_ord_id, replace, text).with(tag::ORDER_ID, order_id)
+    }
+
+    /// Sends heartbeats when idle and checks that the counterparty is alive,
+    /// disconnecting it if it stays silent after a test request
+    pub fn on_timer(&mut self, now: Instant) -> io::Result<Vec<Action>> {
+        let mut actions = Vec::new();
+        if self.logon.is_none() {
+            return Ok(actions);
+        }
+        let silent = now.saturating_duration_since(self.last_received);
+        if silent >= self.heartbeat_interval * 2 {
+            self.logout(&mut actions, "Heartbeat timeout", now)?;
+        } else if silent >= self.heartbeat_interval + self.heartbeat_interval / 5
+            && !self.test_request_sent
+        {
+            self.test_request_sent = true;
+            let test_request = Message::new(msg_type::TEST_REQUEST)
+                .with(tag::TEST_REQ_ID, utc_timestamp(SystemTime::now()));
+            self.send(&mut actions, test_request, now)?;
+        } else if now.saturating_duration_since(self.last_sent) >= self.heartbeat_interval {
+            self.send(&mut actions, Message::new(msg_type::HEARTBEAT), now)?;
+        }
+        Ok(actions)
+    }
+}
+
+fn is_session_message(msg_type: &str) -> bool {
+    matches!(
+        msg_type,
+        msg_type::HEARTBEAT
+            | msg_type::TEST_REQUEST
+            | msg_type::RESEND_REQUEST
+            | msg_type::REJECT
+            | msg_type::SEQUENCE_RESET
+            | msg_type::LOGOUT
+            | msg_type::LOGON
+    )
+}
+
+/// The message with the standard header in front of its other fields
+fn header(
+    message: &Message,
+    sender: &str,
+    target: &str,
+    seq_num: u64,
+    sending_time: String,
+) -> Message {
+    let mut fields = vec![
+        (tag::MSG_TYPE, message.msg_type().to_string()),
+        (tag::SENDER_COMP_ID, sender.to_string()),
+        (tag::TARGET_COMP_ID, target.to_string()),
+        (tag::MSG_SEQ_NUM, seq_num.to_string()),
+        (tag::SENDING_TIME, sending_time),
+    ];
+    fields.extend(
+        message
+            .fields
+            .iter()
+            .filter(|(tag, _)| !HEADER_TAGS.contains(tag))
+            .cloned(),
+    );
+    Message { fields }
+}
+
+/// A message sent again with PossDupFlag and its original sending time
+fn poss_dup(
+    message: &Message,
+    sender: &str,
+    target: &str,
+    seq_num: u64,
+    sending_time: &str,
+    original_time: Option<String>,
+) -> Message {
+    let mut resent = header(message, sender, target, seq_num, sending_time.to_string());
+    resent
+        .fields
+        .insert(4, (tag::POSS_DUP_FLAG, "Y".to_string()));
+    if let Some(original_time) = original_time {
+        resent
+            .fields
+            .insert(6, (tag::ORIG_SENDING_TIME, original_time));
+    }
+    resent
+}
+
+fn cancel_reject(cl_ord_id: &str, orig_cl_ord_id: &str, replace: bool, text: &str) -> Message {
+    Message::new(msg_type::ORDER_CANCEL_REJECT)
+        .with(tag::CL_ORD_ID, cl_ord_id)
+        .with(tag::ORIG_CL_ORD_ID, orig_cl_ord_id)
+        // Rejected
+        .with(tag::ORD_STATUS, 8)
+        .with(tag::CXL_REJ_RESPONSE_TO, if replace { 2 } else { 1 })
+        .with(tag::TEXT, text)
+}
+
+fn ord_rej_reason(reason: RejectReason) -> u32 {
+    match reason {
+        RejectReason::TradingHalted | RejectReason::MarketClosed => 2,
+        RejectReason::OutsidePriceBand => 99,
+        RejectReason::Throttled
+        | RejectReason::Expired
+        | RejectReason::NoPriceLevel
+        | RejectReason::InvalidPrice
+        | RejectReason::InvalidQuantity
+        | RejectReason::LoginFailed => 99,
+        RejectReason::UnknownOrder => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_bigint::BigInt;
+
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fix-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn incoming(msg_type: &str, seq_num: u64) -> Message {
+        Message::new(msg_type)
+            .with(tag::SENDER_COMP_ID, "CLIENT")
+            .with(tag::TARGET_COMP_ID, "LOB")
+            .with(tag::MSG_SEQ_NUM, seq_num)
+    }
+
+    fn sent(actions: &[Action]) -> Vec<&Message> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Send(message) => Some(message),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Requests forwarded to the order manager and their numbers
+    fn forwarded(actions: &[Action]) -> Vec<(RequestId, &ToServer)> {
+        actions
+            .iter()
+            .filter_map(|action| match action {
+                Action::Forward(ToServer::Request(request, msg)) => Some((*request, &**msg)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn new_session(dir: &Path, now: Instant) -> FixSession {
+        let accounts = "CLIENT secret".parse().unwrap();
+        FixSession::new("LOB", "LOB", dir, Arc::new(accounts), now)
+    }
+
+    fn logon(seq_num: u64) -> Message {
+        incoming(msg_type::LOGON, seq_num)
+            .with(tag::HEART_BT_INT, 30)
+            .with(tag::PASSWORD, "secret")
+    }
+
+    fn reply(request: RequestId, msg: ToClient) -> ToClient {
+        ToClient::Reply(request, Box::new(msg))
+    }
+
+    fn logged_on(dir: &Path, now: Instant) -> FixSession {
+        let mut session = new_session(dir, now);
+        let logon = logon(1);
+        let actions = session.on_message(logon, now).unwrap();
+        assert_eq!(sent(&actions)[0].msg_type(), msg_type::LOGON);
+        session
+    }
+
+    #[test]
+    fn codec_round_trip() {
+        let message = Message::new(msg_type::HEARTBEAT)
+            .with(tag::SENDER_COMP_ID, "A")
+            .with(tag::TARGET_COMP_ID, "B")
+            .with(tag::MSG_SEQ_NUM, 2);
+        let mut buffer = BytesMut::new();
+        FixCodec.encode(message.clone(), &mut buffer).unwrap();
+        assert_eq!(
+            &buffer[..],
+            &b"8=FIX.4.4\x019=20\x0135=0\x0149=A\x0156=B\x0134=2\x0110=126\x01"[..]
+        );
+        let mut partial = buffer.split_to(10);
+        assert_eq!(FixCodec.decode(&mut partial).unwrap(), None);
+        partial.unsplit(buffer);
+        assert_eq!(FixCodec.decode(&mut partial).unwrap(), Some(message));
+        assert!(partial.is_empty());
+
+        let mut oversized = BytesMut::from(&b"8=FIX.4.4\x019=18446744073709551615\x01"[..]);
+        let error = FixCodec.decode(&mut oversized).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let mut unterminated = BytesMut::from(&b"8=FIX.4.4\x019=1234567890"[..]);
+        assert!(FixCodec.decode(&mut unterminated).is_err());
+    }
+
+    #[test]
+    fn timestamp() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(utc_timestamp(time), "20240229-12:34:56.789");
+        assert_eq!(
+            parse_utc_timestamp("20240229-12:34:56.789"),
+            Some(Duration::from_millis(1_709_210_096_789))
+        );
+        assert_eq!(parse_utc_date("19700102"), Some(1));
+        assert_eq!(parse_utc_date("20240229"), Some(19_782));
+        for date in &["20260229", "20240230", "20260431"] {
+            assert_eq!(parse_utc_date(date), None);
+        }
+        assert_eq!(parse_utc_timestamp("20240229-24:00:00"), None);
+    }
+
+    #[test]
+    fn order_expiry() {
+        let dir = store_dir("expiry");
+        let now = Instant::now();
+        let mut session = logged_on(&dir, now);
+        let order = incoming(msg_type::NEW_ORDER_SINGLE, 2)
+            .with(tag::CL_ORD_ID, "a")
+            .with(tag::SYMBOL, "LOB")
+            .with(tag::SIDE, 2)
+            .with(tag::ORDER_QTY, 5)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "101")
+            .with(tag::TIME_IN_FORCE, 6)
+            .with(tag::EXPIRE_DATE, "20261018");
+        let actions = session.on_message(order, now).unwrap();
+        assert!(matches!(
+            forwarded(&actions).as_slice(),
+            [(
+                1,
+                ToServer::PlaceOrder(Side::Ask, _, 5, TimeInForce::GoodTillDate(20_744))
+            )]
+        ));
+        session
+            .on_to_client(reply(1, ToClient::OrderAccepted(4)), now)
+            .unwrap();
+        let actions = session
+            .on_to_client(ToClient::OrderExpired(4), now)
+            .unwrap();
+        let report = sent(&actions)[0];
+        assert_eq!(report.get(tag::EXEC_TYPE), Some("C"));
+        assert_eq!(report.get(tag::ORD_STATUS), Some("C"));
+        assert_eq!(report.get(tag::LEAVES_QTY), Some("0"));
+
+        // GTD without an expiry is rejected
+        let order = incoming(msg_type::NEW_ORDER_SINGLE, 3)
+            .with(tag::CL_ORD_ID, "b")
+            .with(tag::SYMBOL, "LOB")
+            .with(tag::SIDE, 1)
+            .with(tag::ORDER_QTY, 5)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "99")
+            .with(tag::TIME_IN_FORCE, 6);
+        let actions = session.on_message(order, now).unwrap();
+        assert_eq!(sent(&actions)[0].get(tag::REF_TAG_ID), Some("126"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn order_lifecycle() {
+        let dir = store_dir("lifecycle");
+        let now = Instant::now();
+        let mut session = logged_on(&dir, now);
+        let order = incoming(msg_type::NEW_ORDER_SINGLE, 2)
+            .with(tag::CL_ORD_ID, "a")
+            .with(tag::SYMBOL, "LOB")
+            .with(tag::SIDE, 1)
+            .with(tag::ORDER_QTY, 10)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "99.5");
+        let actions = session.on_message(order, now).unwrap();
+        assert!(matches!(
+            forwarded(&actions).as_slice(),
+            [(
+                1,
+                ToServer::PlaceOrder(Side::Bid, _, 10, TimeInForce::GoodTillCancel)
+            )]
+        ));
+
+        let actions = session
+            .on_to_client(reply(1, ToClient::OrderAccepted(4)), now)
+            .unwrap();
+        let report = sent(&actions)[0];
+        assert_eq!(report.get(tag::EXEC_TYPE), Some("0"));
+        assert_eq!(report.get(tag::ORDER_ID), Some("4"));
+        assert_eq!(report.get(tag::MSG_SEQ_NUM), Some("2"));
+
+        let price = (BigInt::from(99), 0);
+        let actions = session
+            .on_to_client(ToClient::Fill(4, price, 4), now)
+            .unwrap();
+        let report = sent(&actions)[0];
+        assert_eq!(report.get(tag::ORD_STATUS), Some("1"));
+        assert_eq!(report.get(tag::LEAVES_QTY), Some("6"));
+        assert_eq!(report.get(tag::LAST_PX), Some("99"));
+        let price = (BigInt::from(100), 0);
+        let actions = session
+            .on_to_client(ToClient::Fill(4, price, 2), now)
+            .unwrap();
+        assert_eq!(sent(&actions)[0].get(tag::AVG_PX), Some("99.33333333"));
+
+        let cancel = incoming(msg_type::ORDER_CANCEL_REQUEST, 3)
+            .with(tag::CL_ORD_ID, "b")
+            .with(tag::ORIG_CL_ORD_ID, "a");
+        let actions = session.on_message(cancel, now).unwrap();
+        assert!(matches!(
+            forwarded(&actions).as_slice(),
+            [(2, ToServer::CancelOrder(4))]
+        ));
+        let actions = session
+            .on_to_client(reply(2, ToClient::OrderCancelled(4)), now)
+            .unwrap();
+        let report = sent(&actions)[0];
+        assert_eq!(report.get(tag::CL_ORD_ID), Some("b"));
+        assert_eq!(report.get(tag::ORIG_CL_ORD_ID), Some("a"));
+        assert_eq!(report.get(tag::CUM_QTY), Some("6"));
+
+        let cancel = incoming(msg_type::ORDER_CANCEL_REQUEST, 4)
+            .with(tag::CL_ORD_ID, "c")
+            .with(tag::ORIG_CL_ORD_ID, "a");
+        let actions = session.on_message(cancel, now).unwrap();
+        assert_eq!(sent(&actions)[0].msg_type(), msg_type::ORDER_CANCEL_REJECT);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn logon_needs_password() {
+        let dir = store_dir("logon");
+        let now = Instant::now();
+        let wrong = incoming(msg_type::LOGON, 1).with(tag::PASSWORD, "guess");
+        let actions = new_session(&dir, now).on_message(wrong, now).unwrap();
+        assert!(matches!(actions.as_slice(), [Action::Disconnect]));
+        // Even an account can't name a store outside the directory
+        let accounts = Arc::new("../CLIENT secret".parse().unwrap());
+        let mut session = FixSession::new("LOB", "LOB", &dir, accounts, now);
+        let outside = Message::new(msg_type::LOGON)
+            .with(tag::SENDER_COMP_ID, "../CLIENT")
+            .with(tag::TARGET_COMP_ID, "LOB")
+            .with(tag::MSG_SEQ_NUM, 1)
+            .with(tag::PASSWORD, "secret");
+        let actions = session.on_message(outside, now).unwrap();
+        assert!(matches!(actions.as_slice(), [Action::Disconnect]));
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn replies_out_of_order() {
+        let dir = store_dir("replies");
+        let now = Instant::now();
+        let mut session = logged_on(&dir, now);
+        for (seq_num, cl_ord_id) in [(2, "a"), (3, "b")].iter() {
+            let order = incoming(msg_type::NEW_ORDER_SINGLE, *seq_num)
+                .with(tag::CL_ORD_ID, cl_ord_id)
+                .with(tag::SYMBOL, "LOB")
+                .with(tag::SIDE, 1)
+                .with(tag::ORDER_QTY, 1)
+                .with(tag::ORD_TYPE, 2)
+                .with(tag::PRICE, "99");
+            session.on_message(order, now).unwrap();
+        }
+        // A throttled second order is answered before the first is accepted
+        let rejected = reply(2, ToClient::Rejected(RejectReason::Throttled));
+        let actions = session.on_to_client(rejected, now).unwrap();
+        assert_eq!(sent(&actions)[0].get(tag::CL_ORD_ID), Some("b"));
+        assert_eq!(sent(&actions)[0].get(tag::EXEC_TYPE), Some("8"));
+        let actions = session
+            .on_to_client(reply(1, ToClient::OrderAccepted(7)), now)
+            .unwrap();
+        assert_eq!(sent(&actions)[0].get(tag::CL_ORD_ID), Some("a"));
+        assert_eq!(sent(&actions)[0].get(tag::ORDER_ID), Some("7"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resend_and_resume() {
+        let dir = store_dir("resend");
+        let now = Instant::now();
+        let mut session = logged_on(&dir, now);
+        let order = incoming(msg_type::NEW_ORDER_SINGLE, 2)
+            .with(tag::CL_ORD_ID, "a")
+            .with(tag::SYMBOL, "LOB")
+            .with(tag::SIDE, 2)
+            .with(tag::ORDER_QTY, 1)
+            .with(tag::ORD_TYPE, 2)
+            .with(tag::PRICE, "100");
+        session.on_message(order, now).unwrap();
+        session
+            .on_to_client(reply(1, ToClient::OrderAccepted(0)), now)
+            .unwrap();
+
+        // The logon is gap filled and the execution report sent again
+        let resend = incoming(msg_type::RESEND_REQUEST, 3)
+            .with(tag::BEGIN_SEQ_NO, 1)
+            .with(tag::END_SEQ_NO, 0);
+        let actions = session.on_message(resend, now).unwrap();
+        let resent = sent(&actions);
+        assert_eq!(resent.len(), 2);
+        assert_eq!(resent[0].msg_type(), msg_type::SEQUENCE_RESET);
+        assert_eq!(resent[0].get(tag::NEW_SEQ_NO), Some("2"));
+        assert_eq!(resent[1].msg_type(), msg_type::EXECUTION_REPORT);
+        assert_eq!(resent[1].get(tag::MSG_SEQ_NUM), Some("2"));
+        assert_eq!(resent[1].get(tag::POSS_DUP_FLAG), Some("Y"));
+
+        // A new session continues the sequences and asks for missed messages
+        let mut session = new_session(&dir, now);
+        let actions = session.on_message(logon(6), now).unwrap();
+        let replies = sent(&actions);
+        assert_eq!(replies[0].get(tag::MSG_SEQ_NUM), Some("3"));
+        assert_eq!(replies[1].msg_type(), msg_type::RESEND_REQUEST);
+        assert_eq!(replies[1].get(tag::BEGIN_SEQ_NO), Some("4"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn heartbeat_and_timeout() {
+        let dir = store_dir("heartbeat");
+        let now = Instant::now();
+        let mut session = logged_on(&dir, now);
+        let actions = session.on_timer(now + Duration::from_secs(30)).unwrap();
+        assert_eq!(sent(&actions)[0].msg_type(), msg_type::HEARTBEAT);
+        let actions = session.on_timer(now + Duration::from_secs(36)).unwrap();
+        assert_eq!(sent(&actions)[0].msg_type(), msg_type::TEST_REQUEST);
+        let actions = session.on_timer(now + Duration::from_secs(60)).unwrap();
+        assert!(matches!(actions.last(), Some(Action::Disconnect)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,210 @@
+//! Just enough HTTP/1.1 to serve small local endpoints
+
+use std::{collections::HashMap, io};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+/// Largest request body read, longer ones are answered with 413
+pub const MAX_BODY: usize = 64 * 1024;
+/// Longest request or header line read, including the line ending
+pub const MAX_LINE: usize = 8 * 1024;
+/// Most headers read for one request
+pub const MAX_HEADERS: usize = 100;
+
+/// Why no request could be read
+#[derive(Debug)]
+pub enum RequestError {
+    Io(io::Error),
+    /// The request is answered with this status and the connection closed
+    Status(&'static str),
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+/// A parsed HTTP request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Reads one request, returning `None` if the connection was closed first
+pub async fn read_request<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Result<Option<Request>, RequestError> {
+    let mut request_line = String::new();
+    if read_line(reader, &mut request_line, "400 Bad Request").await? == 0 {
+        return Ok(None);
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => return Err(RequestError::Status("400 Bad Request")),
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query),
+        None => (target.to_string(), ""),
+    };
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+    let mut content_length = 0;
+    let mut headers = 0;
+    loop {
+        let mut header = String::new();
+        let too_large = "431 Request Header Fields Too Large";
+        if read_line(reader, &mut header, too_large).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(RequestError::Status(too_large));
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| RequestError::Status("400 Bad Request"))?;
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(RequestError::Status("413 Payload Too Large"));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        body,
+    }))
+}
+
+/// Reads one line of at most `MAX_LINE` bytes, answering longer ones with
+/// `status`
+async fn read_line<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+    line: &mut String,
+    status: &'static str,
+) -> Result<usize, RequestError> {
+    let read = reader.take(MAX_LINE as u64).read_line(line).await?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(RequestError::Status(status));
+    }
+    Ok(read)
+}
+
+/// Writes a complete response and keeps the connection open
+pub async fn write_response<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+/// Answers a request that could not be read, if it deserves an answer
+pub async fn write_error<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    error: &RequestError,
+) -> io::Result<()> {
+    match error {
+        RequestError::Status(status) => {
+            write_response(writer, status, "text/plain", status.as_bytes()).await
+        }
+        RequestError::Io(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn parse_request() {
+        let raw: &[u8] =
+            b"POST /orders?depth=5 HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nbody";
+        let request = read_request(&mut BufReader::new(raw))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/orders");
+        assert_eq!(request.query["depth"], "5");
+        assert_eq!(request.body, b"body");
+    }
+
+    #[tokio::test]
+    async fn reject_bad_content_length() {
+        let raw: &[u8] = b"POST /orders HTTP/1.1\r\nContent-Length: 1e9\r\n\r\n";
+        let error = read_request(&mut BufReader::new(raw)).await.unwrap_err();
+        assert!(matches!(error, RequestError::Status("400 Bad Request")));
+
+        let raw = format!(
+            "POST /orders HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let error = read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RequestError::Status("413 Payload Too Large")
+        ));
+    }
+
+    #[tokio::test]
+    async fn reject_oversized_head() {
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        let error = read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RequestError::Status("400 Bad Request")));
+
+        let raw = format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", "a".repeat(MAX_LINE));
+        let error = read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RequestError::Status("431 Request Header Fields Too Large")
+        ));
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X: y\r\n".repeat(MAX_HEADERS + 1)
+        );
+        let error = read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RequestError::Status("431 Request Header Fields Too Large")
+        ));
+
+        let raw = format!("GET / HTTP/1.1\r\n{}\r\n", "X: y\r\n".repeat(MAX_HEADERS));
+        assert!(read_request(&mut BufReader::new(raw.as_bytes()))
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,638 @@
+//! Order level market data modelled on NASDAQ TotalView-ITCH
+//!
+//! Messages are fixed width and big endian, starting with their type and a
+//! timestamp in nanoseconds since the Unix epoch. Prices are signed integers
+//! with 8 implied decimal places.
+//!
+//! | Type | Message        | Fields after the timestamp                      |
+//! |------|----------------|-------------------------------------------------|
+//! | `A`  | Add order      | order id u64, side `B`/`S`, quantity u64, price |
+//! | `D`  | Delete order   | order id u64                                    |
+//! | `U`  | Replace order  | order id u64, new order id u64, quantity u64, price |
+//! | `E`  | Order executed | order id u64, quantity u64, match number u64    |
+//! | `P`  | Trade          | match number u64, quantity u64, price           |
+//! | `H`  | Trading status | status `O`/`H`/`A`/`C`                          |
+//!
+//! Every trade is published as a trade message followed by an order executed
+//! message for each of the two orders, sharing the match number.
+//!
+//! Messages are sent in packets like MoldUDP64: a 10 byte session name, the
+//! sequence number of the first message as u64 and the message count as u16,
+//! followed by each message prefixed with its length as u16.
+
+use crate::{OrderId, Price, Quantity, TradingStatus};
+use bigdecimal::{BigDecimal, ToPrimitive};
+use bytes::{Buf, BufMut};
+use engine::Side;
+use num_bigint::BigInt;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Implied decimal places of prices
+pub const PRICE_DECIMALS: i64 = 8;
+/// Bytes of the packet header
+pub const PACKET_HEADER: usize = 20;
+/// Packets stay below the usual MTU
+pub const MAX_PACKET: usize = 1400;
+
+/// Price as an integer with `PRICE_DECIMALS` implied decimal places, `None`
+/// if it has more decimals or doesn't fit
+pub fn price_to_feed(price: &Price) -> Option<i64> {
+    let scaled = price.with_scale(PRICE_DECIMALS);
+    if &scaled != price {
+        return None;
+    }
+    scaled.as_bigint_and_exponent().0.to_i64()
+}
+
+pub fn price_from_feed(price: i64) -> Price {
+    BigDecimal::new(BigInt::from(price), PRICE_DECIMALS).normalized()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeedMessage {
+    AddOrder {
+        order_id: OrderId,
+        side: Side,
+        quantity: Quantity,
+        price: i64,
+    },
+    DeleteOrder {
+        order_id: OrderId,
+    },
+    ReplaceOrder {
+        order_id: OrderId,
+        new_order_id: OrderId,
+        quantity: Quantity,
+        price: i64,
+    },
+    OrderExecuted {
+        order_id: OrderId,
+        quantity: Quantity,
+        match_number: u64,
+    },
+    Trade {
+        match_number: u64,
+        quantity: Quantity,
+        price: i64,
+    },
+    TradingStatus(TradingStatus),
+}
+
+/// A message with the time it happened at
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedEvent {
+    /// Nanoseconds since the Unix epoch
+    pub timestamp: u64,
+    pub message: FeedMessage,
+}
+
+fn side_code(side: Side) -> u8 {
+    match side {
+        Side::Bid => b'B',
+        Side::Ask => b'S',
+    }
+}
+
+fn status_code(status: TradingStatus) -> u8 {
+    match status {
+        TradingStatus::Open => b'O',
+        TradingStatus::Halted => b'H',
+        TradingStatus::Auction => b'A',
+        TradingStatus::Closed => b'C',
+    }
+}
+
+/// Bytes after the type and timestamp of a message type
+fn body_len(message_type: u8) -> Option<usize> {
+    match message_type {
+        b'A' => Some(25),
+        b'D' => Some(8),
+        b'U' => Some(32),
+        b'E' | b'P' => Some(24),
+        b'H' => Some(1),
+        _ => None,
+    }
+}
+
+impl FeedEvent {
+    fn message_type(&self) -> u8 {
+        match self.message {
+            FeedMessage::AddOrder { .. } => b'A',
+            FeedMessage::DeleteOrder { .. } => b'D',
+            FeedMessage::ReplaceOrder { .. } => b'U',
+            FeedMessage::OrderExecuted { .. } => b'E',
+            FeedMessage::Trade { .. } => b'P',
+            FeedMessage::TradingStatus(_) => b'H',
+        }
+    }
+
+    pub fn encode(&self, buf: &mut impl BufMut) {
+        buf.put_u8(self.message_type());
+        buf.put_u64(self.timestamp);
+        match &self.message {
+            FeedMessage::AddOrder {
+                order_id,
+                side,
+                quantity,
+                price,
+            } => {
+                buf.put_u64(*order_id as u64);
+                buf.put_u8(side_code(*side));
+                buf.put_u64(*quantity as u64);
+                buf.put_i64(*price);
+            }
+            FeedMessage::DeleteOrder { order_id } => buf.put_u64(*order_id as u64),
+            FeedMessage::ReplaceOrder {
+                order_id,
+                new_order_id,
+                quantity,
+                price,
+            } => {
+                buf.put_u64(*order_id as u64);
+                buf.put_u64(*new_order_id as u64);
+                buf.put_u64(*quantity as u64);
+                buf.put_i64(*price);
+            }
+            FeedMessage::OrderExecuted {
+                order_id,
+                quantity,
+                match_number,
+            } => {
+                buf.put_u64(*order_id as u64);
+                buf.put_u64(*quantity as u64);
+                buf.put_u64(*match_number);
+            }
+            FeedMessage::Trade {
+                match_number,
+                quantity,
+                price,
+            } => {
+                buf.put_u64(*match_number);
+                buf.put_u64(*quantity as u64);
+                buf.put_i64(*price);
+            }
+            FeedMessage::TradingStatus(status) => buf.put_u8(status_code(*status)),
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        9 + body_len(self.message_type()).unwrap_or_default()
+    }
+
+    /// Decodes one message, `None` if it is truncated or of an unknown type
+    pub fn decode(mut buf: &[u8]) -> Option<FeedEvent> {
+        if buf.len() < 9 {
+            return None;
+        }
+        let message_type = buf.get_u8();
+        let timestamp = buf.get_u64();
+        let needed = body_len(message_type)?;
+        if buf.len() < needed {
+            return None;
+        }
+        let message = match message_type {
+            b'A' => FeedMessage::AddOrder {
+                order_id: buf.get_u64() as OrderId,
+                side: match buf.get_u8() {
+                    b'B' => Side::Bid,
+                    b'S' => Side::Ask,
+                    _ => return None,
+                },
+                quantity: buf.get_u64() as Quantity,
+                price: buf.get_i64(),
+            },
+            b'D' => FeedMessage::DeleteOrder {
+                order_id: buf.get_u64() as OrderId,
+            },
+            b'U' => FeedMessage::ReplaceOrder {
+                order_id: buf.get_u64() as OrderId,
+                new_order_id: buf.get_u64() as OrderId,
+                quantity: buf.get_u64() as Quantity,
+                price: buf.get_i64(),
+            },
+            b'E' => FeedMessage::OrderExecuted {
+                order_id: buf.get_u64() as OrderId,
+                quantity: buf.get_u64() as Quantity,
+                match_number: buf.get_u64(),
+            },
+            b'P' => FeedMessage::Trade {
+                match_number: buf.get_u64(),
+                quantity: buf.get_u64() as Quantity,
+                price: buf.get_i64(),
+            },
+            _ => FeedMessage::TradingStatus(match buf.get_u8() {
+                b'O' => TradingStatus::Open,
+                b'H' => TradingStatus::Halted,
+                b'A' => TradingStatus::Auction,
+                b'C' => TradingStatus::Closed,
+                _ => return None,
+            }),
+        };
+        Some(FeedEvent { timestamp, message })
+    }
+}
+
+/// Sequenced messages sent together
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub session: [u8; 10],
+    /// Sequence number of the first message
+    pub sequence: u64,
+    pub events: Vec<FeedEvent>,
+}
+
+/// Session name padded with spaces or cut to 10 bytes
+pub fn session_name(name: &str) -> [u8; 10] {
+    let mut session = [b' '; 10];
+    for (byte, name_byte) in session.iter_mut().zip(name.bytes()) {
+        *byte = name_byte;
+    }
+    session
+}
+
+impl Packet {
+    pub fn encoded_len(&self) -> usize {
+        PACKET_HEADER
+            + self
+                .events
+                .iter()
+                .map(|event| 2 + event.encoded_len())
+                .sum::<usize>()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        buf.put_slice(&self.session);
+        buf.put_u64(self.sequence);
+        buf.put_u16(self.events.len() as u16);
+        for event in &self.events {
+            buf.put_u16(event.encoded_len() as u16);
+            event.encode(&mut buf);
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Option<Packet> {
+        if buf.len() < PACKET_HEADER {
+            return None;
+        }
+        let mut session = [0; 10];
+        buf.copy_to_slice(&mut session);
+        let sequence = buf.get_u64();
+        let count = buf.get_u16();
+        let mut events = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if buf.len() < 2 {
+                return None;
+            }
+            let length = buf.get_u16() as usize;
+            if buf.len() < length {
+                return None;
+            }
+            events.push(FeedEvent::decode(&buf[..length])?);
+            buf.advance(length);
+        }
+        Some(Packet {
+            session,
+            sequence,
+            events,
+        })
+    }
+}
+
+/// Requests to the recovery service, answered with packets
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecoveryRequest {
+    /// Every resting order and the trading status in one packet whose
+    /// sequence number is the next live message to apply
+    Snapshot,
+    /// Messages from the sequence number on, at most `count`
+    Retransmit { sequence: u64, count: u16 },
+}
+
+impl RecoveryRequest {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            RecoveryRequest::Snapshot => buf.put_u8(b'S'),
+            RecoveryRequest::Retransmit { sequence, count } => {
+                buf.put_u8(b'R');
+                buf.put_u64(*sequence);
+                buf.put_u16(*count);
+            }
+        }
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Option<RecoveryRequest> {
+        match (buf.first(), buf.len()) {
+            (Some(b'S'), 1) => Some(RecoveryRequest::Snapshot),
+            (Some(b'R'), 11) => {
+                buf.advance(1);
+                Some(RecoveryRequest::Retransmit {
+                    sequence: buf.get_u64(),
+                    count: buf.get_u16(),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Resting orders rebuilt from the feed, as a feed handler would
+#[derive(Debug, Default)]
+pub struct FeedBook {
+    /// Side, price and quantity by order id, which is also time priority
+    pub orders: BTreeMap<OrderId, (Side, i64, Quantity)>,
+    pub status: Option<TradingStatus>,
+}
+
+impl FeedBook {
+    pub fn apply(&mut self, message: &FeedMessage) {
+        match message {
+            FeedMessage::AddOrder {
+                order_id,
+                side,
+                quantity,
+                price,
+            } => {
+                self.orders.insert(*order_id, (*side, *price, *quantity));
+            }
+            FeedMessage::DeleteOrder { order_id } => {
+                self.orders.remove(order_id);
+            }
+            FeedMessage::ReplaceOrder {
+                order_id,
+                new_order_id,
+                quantity,
+                price,
+            } => {
+                if let Some((side, _, _)) = self.orders.remove(order_id) {
+                    self.orders.insert(*new_order_id, (side, *price, *quantity));
+                }
+            }
+            FeedMessage::OrderExecuted {
+                order_id, quantity, ..
+            } => {
+                if let Some((_, _, remaining)) = self.orders.get_mut(order_id) {
+                    *remaining = remaining.saturating_sub(*quantity);
+                    if *remaining == 0 {
+                        self.orders.remove(order_id);
+                    }
+                }
+            }
+            FeedMessage::Trade { .. } => {}
+            FeedMessage::TradingStatus(status) => self.status = Some(*status),
+        }
+    }
+
+    /// Messages that rebuild this book from scratch, orders in time priority
+    pub fn snapshot(&self, timestamp: u64) -> Vec<FeedEvent> {
+        let status = self.status.map(|status| FeedEvent {
+            timestamp,
+            message: FeedMessage::TradingStatus(status),
+        });
+        status
+            .into_iter()
+            .chain(
+                self.orders
+                    .iter()
+                    .map(|(order_id, (side, price, quantity))| FeedEvent {
+                        timestamp,
+                        message: FeedMessage::AddOrder {
+                            order_id: *order_id,
+                            side: *side,
+                            quantity: *quantity,
+                            price: *price,
+                        },
+                    }),
+            )
+            .collect()
+    }
+}
+
+/// Splits sequenced events into packets below `MAX_PACKET`, all events of a
+/// snapshot share the sequence number of the next live message
+pub fn packets(
+    session: [u8; 10],
+    mut sequence: u64,
+    events: impl IntoIterator<Item = FeedEvent>,
+    sequenced: bool,
+) -> Vec<Packet> {
+    let mut packets = Vec::new();
+    let mut packet = Packet {
+        session,
+        sequence,
+        events: vec![],
+    };
+    for event in events {
+        if packet.encoded_len() + 2 + event.encoded_len() > MAX_PACKET {
+            if sequenced {
+                sequence += packet.events.len() as u64;
+            }
+            let next = Packet {
+                session,
+                sequence,
+                events: vec![],
+            };
+            packets.push(std::mem::replace(&mut packet, next));
+        }
+        packet.events.push(event);
+    }
+    if !packet.events.is_empty() {
+        packets.push(packet);
+    }
+    packets
+}
+
+/// Sequence numbers, recent messages and the book of the live feed
+pub struct FeedState {
+    pub session: [u8; 10],
+    /// Sequence number of the next message
+    pub next_sequence: u64,
+    history: VecDeque<FeedEvent>,
+    history_capacity: usize,
+    book: FeedBook,
+}
+
+impl FeedState {
+    pub fn new(session: [u8; 10], history_capacity: usize) -> Self {
+        FeedState {
+            session,
+            next_sequence: 1,
+            history: VecDeque::new(),
+            history_capacity,
+            book: FeedBook::default(),
+        }
+    }
+
+    /// Assigns the next sequence number to the event and returns it
+    pub fn record(&mut self, event: &FeedEvent) -> u64 {
+        self.book.apply(&event.message);
+        self.history.push_back(event.clone());
+        if self.history.len() > self.history_capacity {
+            self.history.pop_front();
+        }
+        self.next_sequence += 1;
+        self.next_sequence - 1
+    }
+
+    /// Packets of the recorded messages from `sequence` on, starting later if
+    /// the older ones are no longer kept
+    pub fn retransmit(&self, sequence: u64, count: u16) -> Vec<Packet> {
+        let first_kept = self.next_sequence - self.history.len() as u64;
+        let sequence = sequence.max(first_kept);
+        let events = self
+            .history
+            .iter()
+            .skip((sequence - first_kept) as usize)
+            .take(count as usize)
+            .cloned();
+        packets(self.session, sequence, events, true)
+    }
+
+    pub fn snapshot(&self, timestamp: u64) -> Vec<Packet> {
+        let events = self.book.snapshot(timestamp);
+        packets(self.session, self.next_sequence, events, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn prices_have_eight_decimals() {
+        let price = BigDecimal::from_str("99.125").unwrap();
+        assert_eq!(price_to_feed(&price), Some(9_912_500_000));
+        assert_eq!(price_from_feed(9_912_500_000), price);
+        let price = BigDecimal::from_str("99.123456789").unwrap();
+        assert_eq!(price_to_feed(&price), None);
+        let price = BigDecimal::from_str("100000000000000").unwrap();
+        assert_eq!(price_to_feed(&price), None);
+    }
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = Packet {
+            session: session_name("LOB"),
+            sequence: 7,
+            events: vec![
+                FeedEvent {
+                    timestamp: 1,
+                    message: FeedMessage::AddOrder {
+                        order_id: 3,
+                        side: Side::Ask,
+                        quantity: 10,
+                        price: 100,
+                    },
+                },
+                FeedEvent {
+                    timestamp: 2,
+                    message: FeedMessage::OrderExecuted {
+                        order_id: 3,
+                        quantity: 4,
+                        match_number: 1,
+                    },
+                },
+                FeedEvent {
+                    timestamp: 3,
+                    message: FeedMessage::TradingStatus(TradingStatus::Halted),
+                },
+            ],
+        };
+        let encoded = packet.encode();
+        assert_eq!(encoded.len(), packet.encoded_len());
+        assert_eq!(&encoded[..10], b"LOB       ");
+        assert_eq!(Packet::decode(&encoded), Some(packet));
+        assert_eq!(Packet::decode(&encoded[..encoded.len() - 1]), None);
+    }
+
+    #[test]
+    fn recovery_requests() {
+        let retransmit = RecoveryRequest::Retransmit {
+            sequence: 42,
+            count: 5,
+        };
+        assert_eq!(
+            RecoveryRequest::decode(&retransmit.encode()),
+            Some(retransmit)
+        );
+        assert_eq!(
+            RecoveryRequest::decode(&RecoveryRequest::Snapshot.encode()),
+            Some(RecoveryRequest::Snapshot)
+        );
+    }
+
+    #[test]
+    fn retransmit_kept_history() {
+        let mut state = FeedState::new(session_name("LOB"), 3);
+        for order_id in 0..5 {
+            state.record(&FeedEvent {
+                timestamp: 0,
+                message: FeedMessage::DeleteOrder { order_id },
+            });
+        }
+        let packets = state.retransmit(1, 10);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].sequence, 3);
+        assert_eq!(packets[0].events.len(), 3);
+        assert_eq!(state.retransmit(4, 1)[0].events.len(), 1);
+        assert!(state.retransmit(6, 1).is_empty());
+    }
+
+    #[test]
+    fn large_snapshots_are_split() {
+        let mut state = FeedState::new(session_name("LOB"), 10);
+        for order_id in 0..100 {
+            state.record(&FeedEvent {
+                timestamp: 0,
+                message: FeedMessage::AddOrder {
+                    order_id,
+                    side: Side::Bid,
+                    quantity: 1,
+                    price: 1,
+                },
+            });
+        }
+        let packets = state.snapshot(0);
+        assert!(packets.len() > 1);
+        assert!(packets
+            .iter()
+            .all(|packet| packet.sequence == 101 && packet.encoded_len() <= MAX_PACKET));
+        assert_eq!(
+            packets
+                .iter()
+                .map(|packet| packet.events.len())
+                .sum::<usize>(),
+            100
+        );
+    }
+
+    #[test]
+    fn feed_book_follows_messages() {
+        let mut book = FeedBook::default();
+        book.apply(&FeedMessage::AddOrder {
+            order_id: 1,
+            side: Side::Bid,
+            quantity: 5,
+            price: 10,
+        });
+        book.apply(&FeedMessage::ReplaceOrder {
+            order_id: 1,
+            new_order_id: 2,
+            quantity: 6,
+            price: 11,
+        });
+        book.apply(&FeedMessage::OrderExecuted {
+            order_id: 2,
+            quantity: 2,
+            match_number: 1,
+        });
+        assert_eq!(
+            book.orders.into_iter().collect::<Vec<_>>(),
+            vec![(2, (Side::Bid, 11, 4))]
+        );
+    }
+}
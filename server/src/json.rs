@@ -0,0 +1,502 @@
+//! JSON encoding of the protocol for browser clients on the WebSocket port
+//!
+//! Every message is an object with a `type` field, prices are decimal strings
+//! and sides are `"Bid"` or `"Ask"`. Orders rest until cancelled unless their
+//! `time_in_force` is `day`, `gtt:<milliseconds since the epoch>` or
+//! `gtd:<YYYY-MM-DD>`. Clients send
+//! ```json
+//! {"type": "subscribe", "channels": ["book", "trades", "status", "bars"]}
+//! {"type": "unsubscribe", "channels": ["trades"]}
+//! {"type": "place_order", "side": "Bid", "price": "99.5", "quantity": 10}
+//! {"type": "place_order", "side": "Ask", "price": "101", "quantity": 5, "time_in_force": "gtd:2026-10-18"}
+//! {"type": "get_book_depth", "side": "Ask"}
+//! {"type": "get_top_of_book", "side": "Bid"}
+//! {"type": "get_size_for_price_level", "side": "Bid", "price": "99.5"}
+//! {"type": "login", "account": "desk-1", "password": "secret"}
+//! {"type": "cancel_order", "order_id": 7}
+//! {"type": "replace_order", "order_id": 7, "price": "99.75", "quantity": 5}
+//! {"type": "request_snapshot"}
+//! {"type": "get_analytics", "levels": 5, "bps": "10"}
+//! {"type": "get_queue_positions"}
+//! {"type": "get_bars", "kind": "1m", "limit": 100}
+//! ```
+//! and receive replies to their requests as well as messages of the channels
+//! they subscribed to
+//! ```json
+//! {"type": "connected", "client_id": 3}
+//! {"type": "subscribed", "channels": ["book"]}
+//! {"type": "snapshot", "bids": [{"price": "99.5", "quantity": 10}], "asks": []}
+//! {"type": "depth", "side": "Bid", "price": "99.5", "quantity": 0}
+//! {"type": "checksum", "checksum": 3822410155}
+//! {"type": "trade", "price": "100", "quantity": 5}
+//! {"type": "trading_status", "status": "Auction"}
+//! {"type": "indicative_uncross", "uncross": {"price": "100", "quantity": 20}}
+//! {"type": "book_depth", "side": "Ask", "depth": 4}
+//! {"type": "top_of_book", "side": "Ask", "price": "100.5"}
+//! {"type": "size_for_price_level", "side": "Bid", "quantity": 10}
+//! {"type": "analytics", "spread": "1", "mid_price": "100", "microprice": "100.2", "imbalance": "0.4",
+//!  "bid_depth": 30, "ask_depth": 12, "bid_average_price": "99.1", "ask_average_price": "101.3"}
+//! {"type": "queue_positions", "positions": [{"order_id": 7, "side": "Bid", "price": "99.5", "quantity": 10,
+//!  "ahead": 25, "level_size": 40, "time_to_fill": 12.5}]}
+//! {"type": "bars", "kind": "1m", "bars": [{"number": 0, "time": 1700000040000, "open": "100", "high": "101",
+//!  "low": "99.5", "close": "100.5", "volume": 42, "vwap": "100.2", "trades": 7}]}
+//! {"type": "bar", "kind": "1m", "bar": {"number": 1, "time": 1700000100000, ...}}
+//! {"type": "rejected", "reason": "OutsidePriceBand"}
+//! {"type": "order_accepted", "order_id": 7}
+//! {"type": "fill", "order_id": 7, "price": "99.5", "quantity": 2}
+//! {"type": "order_cancelled", "order_id": 7}
+//! {"type": "order_replaced", "order_id": 7, "new_order_id": 8}
+//! {"type": "order_expired", "order_id": 7}
+//! {"type": "error", "message": "..."}
+//! ```
+
+use crate::{
+    ClientId, Levels, OrderId, Price, Quantity, RejectReason, ToClient, ToServer, TradingStatus,
+};
+use bigdecimal::BigDecimal;
+use engine::{
+    analytics::Analytics,
+    bars::{Bar, BarKind},
+    queue::QueuePosition,
+    Side, TimeInForce,
+};
+use serde::{Deserialize, Serialize};
+
+/// Groups of messages a client can subscribe to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    /// Snapshots and depth updates
+    Book,
+    Trades,
+    /// Trading status and indicative uncross
+    Status,
+    /// Updates of the bars the server aggregates
+    Bars,
+}
+
+/// Messages a WebSocket client can send
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonRequest {
+    Subscribe {
+        channels: Vec<Channel>,
+    },
+    Unsubscribe {
+        channels: Vec<Channel>,
+    },
+    PlaceOrder {
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        #[serde(default)]
+        time_in_force: TimeInForce,
+    },
+    GetBookDepth {
+        side: Side,
+    },
+    GetTopOfBook {
+        side: Side,
+    },
+    GetSizeForPriceLevel {
+        side: Side,
+        price: Price,
+    },
+    Login {
+        account: String,
+        #[serde(default)]
+        password: String,
+    },
+    CancelOrder {
+        order_id: OrderId,
+    },
+    ReplaceOrder {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    RequestSnapshot,
+    GetAnalytics {
+        levels: usize,
+        bps: Price,
+    },
+    GetQueuePositions,
+    GetBars {
+        kind: BarKind,
+        limit: usize,
+    },
+}
+
+impl JsonRequest {
+    /// The binary protocol message of requests that are not about subscriptions
+    pub fn into_to_server(self) -> Option<ToServer> {
+        match self {
+            JsonRequest::Subscribe { .. } | JsonRequest::Unsubscribe { .. } => None,
+            JsonRequest::PlaceOrder {
+                side,
+                price,
+                quantity,
+                time_in_force,
+            } => Some(ToServer::PlaceOrder(
+                side,
+                price.as_bigint_and_exponent(),
+                quantity,
+                time_in_force,
+            )),
+            JsonRequest::GetBookDepth { side } => Some(ToServer::GetBookDepth(side)),
+            JsonRequest::GetTopOfBook { side } => Some(ToServer::GetTopOfBook(side)),
+            JsonRequest::GetSizeForPriceLevel { side, price } => Some(
+                ToServer::GetSizeForPriceLevel(side, price.as_bigint_and_exponent()),
+            ),
+            JsonRequest::Login { account, password } => Some(ToServer::Login(account, password)),
+            JsonRequest::CancelOrder { order_id } => Some(ToServer::CancelOrder(order_id)),
+            JsonRequest::ReplaceOrder {
+                order_id,
+                price,
+                quantity,
+            } => Some(ToServer::ReplaceOrder(
+                order_id,
+                price.as_bigint_and_exponent(),
+                quantity,
+            )),
+            JsonRequest::RequestSnapshot => Some(ToServer::RequestSnapshot),
+            JsonRequest::GetAnalytics { levels, bps } => {
+                Some(ToServer::GetAnalytics(levels, bps.as_bigint_and_exponent()))
+            }
+            JsonRequest::GetQueuePositions => Some(ToServer::GetQueuePositions),
+            JsonRequest::GetBars { kind, limit } => Some(ToServer::GetBars(kind, limit)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonLevel {
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonQueuePosition {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub ahead: Quantity,
+    pub level_size: Quantity,
+    /// Seconds
+    pub time_to_fill: Option<f64>,
+}
+
+impl From<QueuePosition> for JsonQueuePosition {
+    fn from(position: QueuePosition) -> Self {
+        JsonQueuePosition {
+            order_id: position.order_id,
+            side: position.side,
+            price: position.price,
+            quantity: position.quantity,
+            ahead: position.ahead,
+            level_size: position.level_size,
+            time_to_fill: position.time_to_fill.map(|time| time.as_secs_f64()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonBar {
+    pub number: u64,
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Quantity,
+    pub vwap: Price,
+    pub trades: usize,
+}
+
+impl From<Bar> for JsonBar {
+    fn from(bar: Bar) -> Self {
+        JsonBar {
+            number: bar.number,
+            time: bar.start.as_millis() as u64,
+            open: bar.open,
+            high: bar.high,
+            low: bar.low,
+            close: bar.close,
+            volume: bar.volume,
+            vwap: bar.vwap,
+            trades: bar.trades,
+        }
+    }
+}
+
+/// Messages a WebSocket client can receive
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonMessage {
+    Connected {
+        client_id: ClientId,
+    },
+    Subscribed {
+        channels: Vec<Channel>,
+    },
+    Snapshot {
+        bids: Vec<JsonLevel>,
+        asks: Vec<JsonLevel>,
+    },
+    Depth {
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+    },
+    Checksum {
+        checksum: u32,
+    },
+    Trade {
+        price: Price,
+        quantity: Quantity,
+    },
+    TradingStatus {
+        status: TradingStatus,
+    },
+    IndicativeUncross {
+        uncross: Option<JsonLevel>,
+    },
+    BookDepth {
+        side: Side,
+        depth: usize,
+    },
+    TopOfBook {
+        side: Side,
+        price: Price,
+    },
+    SizeForPriceLevel {
+        side: Side,
+        quantity: Quantity,
+    },
+    Analytics(Box<Analytics>),
+    QueuePositions {
+        positions: Vec<JsonQueuePosition>,
+    },
+    Bars {
+        kind: BarKind,
+        bars: Vec<JsonBar>,
+    },
+    Bar {
+        kind: BarKind,
+        bar: JsonBar,
+    },
+    Rejected {
+        reason: RejectReason,
+    },
+    OrderAccepted {
+        order_id: OrderId,
+    },
+    Fill {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    OrderCancelled {
+        order_id: OrderId,
+    },
+    OrderReplaced {
+        order_id: OrderId,
+        new_order_id: OrderId,
+    },
+    OrderExpired {
+        order_id: OrderId,
+    },
+    Error {
+        message: String,
+    },
+}
+
+fn levels(levels: Levels) -> Vec<JsonLevel> {
+    levels
+        .into_iter()
+        .map(|((digits, scale), quantity)| JsonLevel {
+            price: BigDecimal::new(digits, scale),
+            quantity,
+        })
+        .collect()
+}
+
+impl JsonMessage {
+    /// Channel the message belongs to, `None` for replies to the client's own requests
+    pub fn channel(&self) -> Option<Channel> {
+        match self {
+            JsonMessage::Snapshot { .. }
+            | JsonMessage::Depth { .. }
+            | JsonMessage::Checksum { .. } => Some(Channel::Book),
+            JsonMessage::Trade { .. } => Some(Channel::Trades),
+            JsonMessage::TradingStatus { .. } | JsonMessage::IndicativeUncross { .. } => {
+                Some(Channel::Status)
+            }
+            JsonMessage::Bar { .. } => Some(Channel::Bars),
+            _ => None,
+        }
+    }
+}
+
+impl From<ToClient> for JsonMessage {
+    fn from(msg: ToClient) -> Self {
+        match msg {
+            ToClient::Connected(client_id) => JsonMessage::Connected { client_id },
+            ToClient::LatestDepth(side, quantity, (digits, scale)) => JsonMessage::Depth {
+                side,
+                price: BigDecimal::new(digits, scale),
+                quantity,
+            },
+            ToClient::BookDepth(side, depth) => JsonMessage::BookDepth { side, depth },
+            ToClient::TopOfBook(side, (digits, scale)) => JsonMessage::TopOfBook {
+                side,
+                price: BigDecimal::new(digits, scale),
+            },
+            ToClient::SizeForPriceLevel(side, quantity) => {
+                JsonMessage::SizeForPriceLevel { side, quantity }
+            }
+            ToClient::Trade((digits, scale), quantity) => JsonMessage::Trade {
+                price: BigDecimal::new(digits, scale),
+                quantity,
+            },
+            ToClient::TradingStatus(status) => JsonMessage::TradingStatus { status },
+            ToClient::IndicativeUncross(uncross) => JsonMessage::IndicativeUncross {
+                uncross: uncross.map(|((digits, scale), quantity)| JsonLevel {
+                    price: BigDecimal::new(digits, scale),
+                    quantity,
+                }),
+            },
+            ToClient::Rejected(reason) => JsonMessage::Rejected { reason },
+            ToClient::Snapshot(bids, asks) => JsonMessage::Snapshot {
+                bids: levels(bids),
+                asks: levels(asks),
+            },
+            ToClient::OrderAccepted(order_id) => JsonMessage::OrderAccepted { order_id },
+            ToClient::Fill(order_id, (digits, scale), quantity) => JsonMessage::Fill {
+                order_id,
+                price: BigDecimal::new(digits, scale),
+                quantity,
+            },
+            ToClient::OrderCancelled(order_id) => JsonMessage::OrderCancelled { order_id },
+            ToClient::OrderExpired(order_id) => JsonMessage::OrderExpired { order_id },
+            ToClient::OrderReplaced(order_id, new_order_id) => JsonMessage::OrderReplaced {
+                order_id,
+                new_order_id,
+            },
+            ToClient::BookChecksum(checksum) => JsonMessage::Checksum { checksum },
+            ToClient::Analytics(analytics) => JsonMessage::Analytics(Box::new((*analytics).into())),
+            ToClient::QueuePositions(positions) => JsonMessage::QueuePositions {
+                positions: positions
+                    .into_iter()
+                    .map(|position| QueuePosition::from(position).into())
+                    .collect(),
+            },
+            ToClient::Bars(kind, bars) => JsonMessage::Bars {
+                kind,
+                bars: bars.into_iter().map(|bar| Bar::from(bar).into()).collect(),
+            },
+            ToClient::BarUpdate(kind, bar) => JsonMessage::Bar {
+                kind,
+                bar: Bar::from(*bar).into(),
+            },
+            // WebSocket clients don't number their requests
+            ToClient::Reply(_, msg) => JsonMessage::from(*msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn parse_place_order() {
+        let request: JsonRequest = serde_json::from_str(
+            r#"{"type": "place_order", "side": "Bid", "price": "99.5", "quantity": 10}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            JsonRequest::PlaceOrder {
+                side: Side::Bid,
+                price: BigDecimal::from_str("99.5").unwrap(),
+                quantity: 10,
+                time_in_force: TimeInForce::GoodTillCancel,
+            }
+        );
+        let request: JsonRequest = serde_json::from_str(
+            r#"{"type": "place_order", "side": "Ask", "price": "1", "quantity": 1, "time_in_force": "day"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            request,
+            JsonRequest::PlaceOrder {
+                time_in_force: TimeInForce::Day,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn encode_depth() {
+        let msg: JsonMessage =
+            ToClient::LatestDepth(Side::Ask, 3, BigDecimal::from(12).as_bigint_and_exponent())
+                .into();
+        assert_eq!(msg.channel(), Some(Channel::Book));
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"type":"depth","side":"Ask","price":"12","quantity":3}"#
+        );
+    }
+
+    #[test]
+    fn encode_analytics() {
+        let analytics = Analytics {
+            spread: Some(BigDecimal::from(1)),
+            mid_price: Some(BigDecimal::from_str("99.5").unwrap()),
+            microprice: None,
+            imbalance: None,
+            bid_depth: Some(3),
+            ask_depth: None,
+            bid_average_price: None,
+            ask_average_price: None,
+        };
+        let msg: JsonMessage = ToClient::Analytics(Box::new(analytics.into())).into();
+        assert_eq!(msg.channel(), None);
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"type":"analytics","spread":"1","mid_price":"99.5","microprice":null,"imbalance":null,"bid_depth":3,"ask_depth":null,"bid_average_price":null,"ask_average_price":null}"#
+        );
+    }
+
+    #[test]
+    fn bars() {
+        let request: JsonRequest =
+            serde_json::from_str(r#"{"type": "get_bars", "kind": "5m", "limit": 2}"#).unwrap();
+        assert_eq!(
+            request.into_to_server().map(|msg| msg.name()),
+            Some("get_bars")
+        );
+        let bar = Bar {
+            number: 3,
+            start: std::time::Duration::from_secs(300),
+            open: BigDecimal::from(100),
+            high: BigDecimal::from(101),
+            low: BigDecimal::from(99),
+            close: BigDecimal::from(100),
+            volume: 4,
+            vwap: BigDecimal::from_str("100.25").unwrap(),
+            trades: 2,
+        };
+        let kind = BarKind::Time(std::time::Duration::from_secs(300));
+        let msg: JsonMessage = ToClient::BarUpdate(kind, Box::new(bar.into())).into();
+        assert_eq!(msg.channel(), Some(Channel::Bars));
+        assert_eq!(
+            serde_json::to_string(&msg).unwrap(),
+            r#"{"type":"bar","kind":"5m","bar":{"number":3,"time":300000,"open":"100","high":"101","low":"99","close":"100","volume":4,"vwap":"100.25","trades":2}}"#
+        );
+    }
+}
@@ -1,15 +1,72 @@
 use bigdecimal::BigDecimal;
-use engine::Side;
+use engine::{
+    analytics::Analytics,
+    bars::{Bar, BarKind},
+    queue::QueuePosition,
+    Side, TimeInForce,
+};
 use num_bigint::BigInt;
 use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Duration};
+
+pub mod auth;
+pub mod circuit_breaker;
+pub mod fix;
+pub mod http;
+pub mod itch;
+pub mod json;
+pub mod metrics;
+pub mod rate_limit;
+pub mod rest;
+pub mod stats;
 
 /// Protocol for which messages the server can receive
 #[derive(Debug, Serialize, Deserialize)]
 pub enum ToServer {
     GetBookDepth(engine::Side),
-    PlaceOrder(engine::Side, (BigInt, i64), usize),
+    PlaceOrder(engine::Side, (BigInt, i64), usize, TimeInForce),
     GetTopOfBook(engine::Side),
     GetSizeForPriceLevel(engine::Side, (BigInt, i64)),
+    SetSlowConsumerPolicy(SlowConsumerPolicy),
+    /// Account the session trades for and its password, rate limits are
+    /// shared per account
+    Login(String, String),
+    CancelOrder(OrderId),
+    /// Replaces a resting order with a new one at the back of the queue
+    ReplaceOrder(OrderId, (BigInt, i64), Quantity),
+    /// Asks for a snapshot of the book, e.g. after a checksum mismatch
+    RequestSnapshot,
+    /// Analytics over this many levels and with the depth within this many
+    /// basis points of the mid price
+    GetAnalytics(usize, (BigInt, i64)),
+    /// Queue positions of the resting orders of the session
+    GetQueuePositions,
+    /// The latest bars of a kind the server aggregates, at most this many
+    GetBars(BarKind, usize),
+    /// A message numbered by the client, whatever the server answers to it
+    /// comes back in a `ToClient::Reply` with the same number
+    Request(RequestId, Box<ToServer>),
+}
+
+impl ToServer {
+    /// Name of the message type used for rate limits
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToServer::GetBookDepth(_) => "get_book_depth",
+            ToServer::PlaceOrder(..) => "place_order",
+            ToServer::GetTopOfBook(_) => "get_top_of_book",
+            ToServer::GetSizeForPriceLevel(..) => "get_size_for_price_level",
+            ToServer::SetSlowConsumerPolicy(_) => "set_slow_consumer_policy",
+            ToServer::Login(..) => "login",
+            ToServer::CancelOrder(_) => "cancel_order",
+            ToServer::ReplaceOrder(..) => "replace_order",
+            ToServer::RequestSnapshot => "request_snapshot",
+            ToServer::GetAnalytics(..) => "get_analytics",
+            ToServer::GetQueuePositions => "get_queue_positions",
+            ToServer::GetBars(..) => "get_bars",
+            ToServer::Request(_, msg) => msg.name(),
+        }
+    }
 }
 
 /// Protocol for which messages the server can emit
@@ -20,9 +77,297 @@ pub enum ToClient {
     BookDepth(Side, Quantity),
     TopOfBook(Side, (BigInt, i64)),
     SizeForPriceLevel(Side, Quantity),
+    Trade((BigInt, i64), Quantity),
+    TradingStatus(TradingStatus),
+    IndicativeUncross(Option<((BigInt, i64), Quantity)>),
+    Rejected(RejectReason),
+    /// Every price level of the book, replacing what the client has seen so far
+    Snapshot(Levels, Levels),
+    /// An order of this client rests in the book, sent before any of its fills
+    OrderAccepted(OrderId),
+    /// An order of this client traded this quantity at this price
+    Fill(OrderId, (BigInt, i64), Quantity),
+    OrderCancelled(OrderId),
+    /// The order was cancelled and replaced by the new one
+    OrderReplaced(OrderId, OrderId),
+    /// The order reached the deadline of its time in force and left the book
+    OrderExpired(OrderId),
+    /// Checksum of the book after the depth updates sent before it, computed
+    /// with `engine::checksum::levels_checksum`
+    BookChecksum(u32),
+    Analytics(Box<AnalyticsMessage>),
+    QueuePositions(Vec<QueuePositionMessage>),
+    /// Bars of a kind oldest first, empty if the server does not aggregate it
+    Bars(BarKind, Vec<BarMessage>),
+    /// A bar that was started or updated by trades
+    BarUpdate(BarKind, Box<BarMessage>),
+    /// Answer to a `ToServer::Request`, fills and other messages that are not
+    /// an answer come on their own
+    Reply(RequestId, Box<ToClient>),
+}
+
+fn to_wire(value: Option<BigDecimal>) -> Option<(BigInt, i64)> {
+    value.map(|value| value.as_bigint_and_exponent())
+}
+
+fn from_wire(value: Option<(BigInt, i64)>) -> Option<BigDecimal> {
+    value.map(|(digits, scale)| BigDecimal::new(digits, scale))
+}
+
+/// [`Analytics`] with its decimals as digits and scale like prices
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AnalyticsMessage {
+    pub spread: Option<(BigInt, i64)>,
+    pub mid_price: Option<(BigInt, i64)>,
+    pub microprice: Option<(BigInt, i64)>,
+    pub imbalance: Option<(BigInt, i64)>,
+    pub bid_depth: Option<Quantity>,
+    pub ask_depth: Option<Quantity>,
+    pub bid_average_price: Option<(BigInt, i64)>,
+    pub ask_average_price: Option<(BigInt, i64)>,
+}
+
+impl From<Analytics> for AnalyticsMessage {
+    fn from(analytics: Analytics) -> Self {
+        AnalyticsMessage {
+            spread: to_wire(analytics.spread),
+            mid_price: to_wire(analytics.mid_price),
+            microprice: to_wire(analytics.microprice),
+            imbalance: to_wire(analytics.imbalance),
+            bid_depth: analytics.bid_depth,
+            ask_depth: analytics.ask_depth,
+            bid_average_price: to_wire(analytics.bid_average_price),
+            ask_average_price: to_wire(analytics.ask_average_price),
+        }
+    }
+}
+
+impl From<AnalyticsMessage> for Analytics {
+    fn from(message: AnalyticsMessage) -> Self {
+        Analytics {
+            spread: from_wire(message.spread),
+            mid_price: from_wire(message.mid_price),
+            microprice: from_wire(message.microprice),
+            imbalance: from_wire(message.imbalance),
+            bid_depth: message.bid_depth,
+            ask_depth: message.ask_depth,
+            bid_average_price: from_wire(message.bid_average_price),
+            ask_average_price: from_wire(message.ask_average_price),
+        }
+    }
+}
+
+/// [`QueuePosition`] with its price as digits and scale
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuePositionMessage {
+    pub order_id: OrderId,
+    pub side: Side,
+    pub price: (BigInt, i64),
+    pub quantity: Quantity,
+    pub ahead: Quantity,
+    pub level_size: Quantity,
+    pub time_to_fill: Option<Duration>,
+}
+
+impl From<QueuePosition> for QueuePositionMessage {
+    fn from(position: QueuePosition) -> Self {
+        QueuePositionMessage {
+            order_id: position.order_id,
+            side: position.side,
+            price: position.price.as_bigint_and_exponent(),
+            quantity: position.quantity,
+            ahead: position.ahead,
+            level_size: position.level_size,
+            time_to_fill: position.time_to_fill,
+        }
+    }
+}
+
+impl From<QueuePositionMessage> for QueuePosition {
+    fn from(message: QueuePositionMessage) -> Self {
+        let (digits, scale) = message.price;
+        QueuePosition {
+            order_id: message.order_id,
+            side: message.side,
+            price: BigDecimal::new(digits, scale),
+            quantity: message.quantity,
+            ahead: message.ahead,
+            level_size: message.level_size,
+            time_to_fill: message.time_to_fill,
+        }
+    }
+}
+
+/// [`Bar`] with its prices as digits and scale
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BarMessage {
+    pub number: u64,
+    pub start: Duration,
+    pub open: (BigInt, i64),
+    pub high: (BigInt, i64),
+    pub low: (BigInt, i64),
+    pub close: (BigInt, i64),
+    pub volume: Quantity,
+    pub vwap: (BigInt, i64),
+    pub trades: usize,
+}
+
+impl From<Bar> for BarMessage {
+    fn from(bar: Bar) -> Self {
+        BarMessage {
+            number: bar.number,
+            start: bar.start,
+            open: bar.open.as_bigint_and_exponent(),
+            high: bar.high.as_bigint_and_exponent(),
+            low: bar.low.as_bigint_and_exponent(),
+            close: bar.close.as_bigint_and_exponent(),
+            volume: bar.volume,
+            vwap: bar.vwap.as_bigint_and_exponent(),
+            trades: bar.trades,
+        }
+    }
+}
+
+impl From<BarMessage> for Bar {
+    fn from(message: BarMessage) -> Self {
+        let decimal = |(digits, scale)| BigDecimal::new(digits, scale);
+        Bar {
+            number: message.number,
+            start: message.start,
+            open: decimal(message.open),
+            high: decimal(message.high),
+            low: decimal(message.low),
+            close: decimal(message.close),
+            volume: message.volume,
+            vwap: decimal(message.vwap),
+            trades: message.trades,
+        }
+    }
+}
+
+/// Protocol for which messages the server can receive on the admin port
+#[derive(Debug, Serialize, Deserialize)]
+pub enum AdminCommand {
+    ListClients,
+    DumpBook,
+    CancelAllOrders(ClientId),
+    SetTradingStatus(TradingStatus),
+    ForceSnapshot,
+}
+
+/// Protocol for which messages the server can emit on the admin port
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ToAdmin {
+    /// Connected clients with their number of resting orders
+    Clients(Vec<(ClientId, usize)>),
+    Book(Levels, Levels),
+    OrdersCancelled(ClientId, usize),
+    TradingStatus(TradingStatus),
+    /// Number of clients the snapshot was sent to
+    SnapshotSent(usize),
+}
+
+/// Why an order or other message was not accepted
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    TradingHalted,
+    MarketClosed,
+    OutsidePriceBand,
+    /// The session or account sent more messages than its rate limit
+    Throttled,
+    /// The client has no resting order with this id
+    UnknownOrder,
+    /// The time in force of the order ended before it was placed
+    Expired,
+    /// The password does not match the account
+    LoginFailed,
+    /// The side of the book is empty or has no orders at the price
+    NoPriceLevel,
+    /// The price has more decimals or digits than the market data feed carries
+    InvalidPrice,
+    /// Orders need a quantity of at least 1
+    InvalidQuantity,
+}
+
+/// Trading state of the instrument
+///
+/// Orders are matched immediately while open, collected for a call auction
+/// while in auction and rejected while halted or closed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradingStatus {
+    Open,
+    Halted,
+    Auction,
+    Closed,
+}
+
+impl FromStr for TradingStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "o" | "open" | "continuous" => Ok(TradingStatus::Open),
+            "h" | "halt" | "halted" => Ok(TradingStatus::Halted),
+            "a" | "auction" => Ok(TradingStatus::Auction),
+            "c" | "close" | "closed" => Ok(TradingStatus::Closed),
+            _ => Err(format!("Unknown trading status {}", s)),
+        }
+    }
+}
+
+/// What the server does when a client does not read its messages fast enough
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlowConsumerPolicy {
+    /// Drop messages until the client catches up, then send a snapshot
+    Resync,
+    /// Keep only the latest depth of each price level and drop other messages
+    Conflate,
+    Disconnect,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_ref() {
+            "resync" => Ok(SlowConsumerPolicy::Resync),
+            "conflate" => Ok(SlowConsumerPolicy::Conflate),
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            _ => Err(format!("Unknown slow consumer policy {}", s)),
+        }
+    }
 }
 
 pub type ClientId = usize;
+/// Number a client gives a request to match the replies to it
+pub type RequestId = u64;
 pub type OrderId = usize;
 pub type Price = BigDecimal;
 pub type Quantity = usize;
+/// Price levels from the top of the book outwards
+pub type Levels = Vec<((BigInt, i64), Quantity)>;
+
+/// Where clients connect
+pub const CLIENT_ADDR: &str = "127.0.0.1:8080";
+/// Where the admin interface listens
+pub const ADMIN_ADDR: &str = "127.0.0.1:8081";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use engine::{Level2View, OrderBook};
+
+    #[test]
+    fn analytics_over_bincode() {
+        let mut order_book = OrderBook::default();
+        order_book.on_new_order(Side::Bid, 99.into(), 3, 1);
+        order_book.on_new_order(Side::Ask, 101.into(), 1, 2);
+        let analytics = order_book.analytics(5, &10.into());
+        let message = ToClient::Analytics(Box::new(analytics.clone().into()));
+        let bytes = bincode::serialize(&message).unwrap();
+        match bincode::deserialize(&bytes).unwrap() {
+            ToClient::Analytics(received) => assert_eq!(Analytics::from(*received), analytics),
+            message => panic!("Unexpected message {:?}", message),
+        }
+    }
+}
@@ -1,158 +1,2614 @@
 use bigdecimal::BigDecimal;
-use engine::{Level2View, OrderBook, Side};
-use server::{ClientId, OrderId, Price, Quantity, ToClient, ToServer};
+use clap::{App, Arg, ArgMatches};
+use engine::{
+    bars::{Bar, BarAggregator, BarKind},
+    lobster::{EventType, LobsterMessage, LobsterWriter},
+    queue::TradeRates,
+    Expired, Level2Query, Level2View, OrderBook, Side, TimeInForce, Trade,
+};
+use futures::{SinkExt, StreamExt};
+use num_bigint::BigInt;
+use server::{
+    auth::{Accounts, Login},
+    circuit_breaker::{PriceBand, VolatilityGuard},
+    fix::{Action, FixCodec, FixSession},
+    http,
+    itch::{self, FeedEvent, FeedMessage, FeedState, RecoveryRequest},
+    json::{Channel, JsonLevel, JsonMessage, JsonRequest},
+    metrics::{Encoder, Metrics},
+    rate_limit::{Limits, RateLimiter, SessionLimits, Verdict},
+    rest::{self, NewOrder, RestReply, RestRequest},
+    stats::{BookState, SessionStats},
+    AdminCommand, ClientId, Levels, OrderId, Price, Quantity, RejectReason, RequestId,
+    SlowConsumerPolicy, ToAdmin, ToClient, ToServer, TradingStatus, ADMIN_ADDR, CLIENT_ADDR,
+};
 use std::{
-    collections::HashMap,
-    io,
-    io::{stdout, Write},
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Instant, SystemTime, UNIX_EPOCH},
 };
 use tokio::{
-    io::AsyncWriteExt,
-    net::{TcpListener, TcpStream},
-    sync::mpsc::{self, UnboundedSender},
+    io::{AsyncBufReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::{
+        mpsc::{self, error::TrySendError, Receiver, Sender},
+        oneshot,
+    },
     task,
     time::Duration,
 };
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+/// Settings shared by the FIX sessions
+struct FixConfig {
+    comp_id: String,
+    symbol: String,
+    /// Directory of the sequence numbers and sent messages of every counterparty
+    store_dir: PathBuf,
+}
+
+/// Owner of the orders entered over the REST API, which outlive connections
+const REST_CLIENT: ClientId = ClientId::MAX;
+const REST_ACCOUNT: &str = "rest";
+/// Trades kept for the REST API
+const RECENT_TRADES: usize = 1000;
+/// Period over which the pace of trading at a price is measured for queue
+/// position estimates
+const TRADE_RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Completed bars kept of each kind
+const BAR_HISTORY: usize = 1000;
 
 enum ToOrderManager {
-    ClientConnected(UnboundedSender<ToClient>),
+    /// A session connected, trading for this account
+    ClientConnected(Sender<ToClient>, String),
+    Login(ClientId, String),
     ClientDisconnected(ClientId),
-    PlaceOrder(ClientId, Side, Price, Quantity),
+    PlaceOrder(ClientId, Side, Price, Quantity, TimeInForce),
     GetOrderDepth(ClientId, Side),
     GetTopOfBook(ClientId, Side),
     GetSizeForPriceLevel(ClientId, Side, Price),
+    CancelOrder(ClientId, OrderId),
+    ReplaceOrder(ClientId, OrderId, Price, Quantity),
+    SetSlowConsumerPolicy(ClientId, SlowConsumerPolicy),
+    GetSnapshot(ClientId),
+    GetAnalytics(ClientId, usize, Price),
+    GetQueuePositions(ClientId),
+    GetBars(ClientId, BarKind, usize),
+    /// A message of a client numbered by the client
+    Request(RequestId, Box<ToOrderManager>),
+    SetTradingStatus(TradingStatus),
+    Admin(AdminCommand, oneshot::Sender<ToAdmin>),
+    GetMetrics(oneshot::Sender<String>),
+    Rest(RestRequest, oneshot::Sender<RestReply>),
 }
-async fn server_loop(mut events: mpsc::UnboundedReceiver<ToOrderManager>) {
-    let mut order_book = OrderBook::default();
-    let mut order_counter: OrderId = 0;
-    let mut client_counter: ClientId = 0;
-    let mut clients: HashMap<ClientId, UnboundedSender<ToClient>> = HashMap::new();
-    let mut client_orders: HashMap<ClientId, Vec<OrderId>> = HashMap::new();
 
-    let mut heartbeat = tokio::time::interval(Duration::from_secs(1));
+impl ToOrderManager {
+    /// Name of the message type used as metrics label
+    fn name(&self) -> &'static str {
+        match self {
+            ToOrderManager::ClientConnected(..) => "client_connected",
+            ToOrderManager::Login(..) => "login",
+            ToOrderManager::ClientDisconnected(_) => "client_disconnected",
+            ToOrderManager::PlaceOrder(..) => "place_order",
+            ToOrderManager::GetOrderDepth(..) => "get_order_depth",
+            ToOrderManager::GetTopOfBook(..) => "get_top_of_book",
+            ToOrderManager::GetSizeForPriceLevel(..) => "get_size_for_price_level",
+            ToOrderManager::CancelOrder(..) => "cancel_order",
+            ToOrderManager::ReplaceOrder(..) => "replace_order",
+            ToOrderManager::SetSlowConsumerPolicy(..) => "set_slow_consumer_policy",
+            ToOrderManager::GetSnapshot(_) => "get_snapshot",
+            ToOrderManager::GetAnalytics(..) => "get_analytics",
+            ToOrderManager::GetQueuePositions(_) => "get_queue_positions",
+            ToOrderManager::GetBars(..) => "get_bars",
+            ToOrderManager::Request(_, msg) => msg.name(),
+            ToOrderManager::SetTradingStatus(_) => "set_trading_status",
+            ToOrderManager::Admin(..) => "admin",
+            ToOrderManager::GetMetrics(_) => "get_metrics",
+            ToOrderManager::Rest(..) => "rest",
+        }
+    }
+}
+
+/// Outcome of queueing a message for a client
+enum Delivery {
+    Sent,
+    /// Kept until the queue of the client has room again
+    Held,
+    Dropped,
+    Conflated,
+    Disconnect,
+}
+
+/// Market data that a snapshot or a later update makes up for, everything
+/// else answers or concerns the client and is never dropped
+fn is_market_data(msg: &ToClient) -> bool {
+    matches!(
+        msg,
+        ToClient::LatestDepth(..)
+            | ToClient::Trade(..)
+            | ToClient::IndicativeUncross(_)
+            | ToClient::BookChecksum(_)
+            | ToClient::BarUpdate(..)
+    )
+}
+
+/// Bounded outbound queue of a connected client
+struct ClientSession {
+    to_client: Sender<ToClient>,
+    policy: SlowConsumerPolicy,
+    /// Messages were dropped and the client is owed a snapshot
+    needs_snapshot: bool,
+    /// Latest depth of the price levels that did not fit in the queue
+    pending_depth: HashMap<(Side, (BigInt, i64)), Quantity>,
+    /// Replies that did not fit in the queue, oldest first
+    held: VecDeque<ToClient>,
+}
+
+impl ClientSession {
+    fn new(to_client: Sender<ToClient>, policy: SlowConsumerPolicy) -> Self {
+        ClientSession {
+            to_client,
+            policy,
+            needs_snapshot: false,
+            pending_depth: HashMap::new(),
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Conflated depth can't be carried over to another policy, so the
+    /// client is resynced instead
+    fn set_policy(&mut self, policy: SlowConsumerPolicy) {
+        if !self.pending_depth.is_empty() {
+            self.pending_depth.clear();
+            self.needs_snapshot = true;
+        }
+        self.policy = policy;
+    }
+
+    fn deliver(&mut self, msg: ToClient) -> Delivery {
+        if !is_market_data(&msg) {
+            return self.reply(msg);
+        }
+        if self.needs_snapshot {
+            return Delivery::Dropped;
+        }
+        // Depth updates queue up behind held back ones to keep their order
+        let msg = match msg {
+            ToClient::LatestDepth(side, quantity, price) if !self.pending_depth.is_empty() => {
+                self.pending_depth.insert((side, price), quantity);
+                return Delivery::Conflated;
+            }
+            // The client can't match a checksum until it has the held back depth
+            ToClient::BookChecksum(_) if !self.pending_depth.is_empty() => {
+                return Delivery::Dropped;
+            }
+            msg => msg,
+        };
+        // Market data doesn't overtake held replies
+        let sent = match self.held.is_empty() {
+            true => self.to_client.try_send(msg),
+            false => Err(TrySendError::Full(msg)),
+        };
+        match sent {
+            // The client loop has ended and will report the disconnect
+            Ok(()) | Err(TrySendError::Closed(_)) => Delivery::Sent,
+            Err(TrySendError::Full(msg)) => match (self.policy, msg) {
+                (SlowConsumerPolicy::Resync, _) => {
+                    self.needs_snapshot = true;
+                    self.pending_depth.clear();
+                    Delivery::Dropped
+                }
+                (SlowConsumerPolicy::Conflate, ToClient::LatestDepth(side, quantity, price)) => {
+                    self.pending_depth.insert((side, price), quantity);
+                    Delivery::Conflated
+                }
+                (SlowConsumerPolicy::Conflate, _) => Delivery::Dropped,
+                (SlowConsumerPolicy::Disconnect, _) => Delivery::Disconnect,
+            },
+        }
+    }
+
+    /// Queues a reply behind those already held, a client that lets as many
+    /// replies pile up as its queue holds is disconnected
+    fn reply(&mut self, msg: ToClient) -> Delivery {
+        let msg = match self.held.is_empty() {
+            true => match self.to_client.try_send(msg) {
+                Ok(()) | Err(TrySendError::Closed(_)) => return Delivery::Sent,
+                Err(TrySendError::Full(msg)) => msg,
+            },
+            false => msg,
+        };
+        if self.policy == SlowConsumerPolicy::Disconnect
+            || self.held.len() >= self.to_client.max_capacity()
+        {
+            return Delivery::Disconnect;
+        }
+        self.held.push_back(msg);
+        Delivery::Held
+    }
+
+    /// Sends the held replies and then the last messages of a disconnected
+    /// client, waiting for room in the queue in a task of their own
+    fn close(mut self, last: impl Iterator<Item = ToClient>) {
+        self.held.extend(last);
+        task::spawn(async move {
+            for msg in self.held {
+                if self.to_client.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Sends what was held back as far as the queue allows, returns true if
+    /// the client was sent a snapshot
+    fn flush(&mut self, snapshot: &mut dyn FnMut() -> ToClient) -> bool {
+        while !self.held.is_empty() {
+            match self.to_client.try_reserve() {
+                Ok(permit) => permit.send(self.held.pop_front().unwrap()),
+                Err(_) => return false,
+            }
+        }
+        let mut resynced = false;
+        if self.needs_snapshot && self.to_client.try_send(snapshot()).is_ok() {
+            self.needs_snapshot = false;
+            resynced = true;
+        }
+        let pending: Vec<_> = self.pending_depth.keys().cloned().collect();
+        for (side, price) in pending {
+            let quantity = self.pending_depth[&(side, price.clone())];
+            let permit = match self.to_client.try_reserve() {
+                Ok(permit) => permit,
+                Err(_) => break,
+            };
+            self.pending_depth.remove(&(side, price.clone()));
+            permit.send(ToClient::LatestDepth(side, quantity, price));
+        }
+        resynced
+    }
+}
+
+/// Where the order manager publishes book events
+struct Feed {
+    state: Arc<Mutex<FeedState>>,
+    /// Recorded events and their sequence numbers for `feed_loop` to send
+    events: Sender<(u64, FeedEvent)>,
+}
+
+/// Owns the order book and the connected clients, driven by `server_loop`
+struct OrderManager {
+    order_book: OrderBook,
+    order_counter: OrderId,
+    client_counter: ClientId,
+    clients: HashMap<ClientId, ClientSession>,
+    default_policy: SlowConsumerPolicy,
+    client_orders: HashMap<ClientId, Vec<OrderId>>,
+    order_owners: HashMap<OrderId, ClientId>,
+    client_accounts: HashMap<ClientId, String>,
+    /// Number of the request being handled, which its replies carry back
+    request: Option<RequestId>,
+    status: TradingStatus,
+    price_band: Option<PriceBand>,
+    volatility_guard: Option<VolatilityGuard>,
+    halt_duration: Duration,
+    /// End of an automatic volatility halt and the status to resume to
+    halted_until: Option<(Instant, TradingStatus)>,
+    metrics: Metrics,
+    rate_limiter: Arc<RateLimiter>,
+    /// Latest trades, newest first
+    recent_trades: VecDeque<Trade>,
+    trade_rates: TradeRates,
+    bars: Vec<BarAggregator>,
+    stats: SessionStats,
+    /// Resting bid and ask quantity per account
+    resting: HashMap<String, (Quantity, Quantity)>,
+    /// Quantity an account must show on each side to count as quoting
+    quote_min_size: Quantity,
+    /// Where the session report is written when trading closes
+    session_report: Option<PathBuf>,
+    feed: Feed,
+    /// Number of the last trade published on the feed
+    match_counter: u64,
+    /// Export of the session as LOBSTER message and orderbook files
+    lobster: Option<LobsterWriter<BufWriter<File>>>,
+    /// Events of the book written one JSON object per line
+    journal: Option<BufWriter<File>>,
+}
+
+impl OrderManager {
+    fn new(
+        price_band: Option<PriceBand>,
+        volatility_guard: Option<VolatilityGuard>,
+        halt_duration: Duration,
+        default_policy: SlowConsumerPolicy,
+        rate_limiter: Arc<RateLimiter>,
+        feed: Feed,
+        lobster: Option<LobsterWriter<BufWriter<File>>>,
+    ) -> Self {
+        let mut manager = OrderManager {
+            order_book: OrderBook::default(),
+            order_counter: 0,
+            client_counter: 0,
+            clients: HashMap::new(),
+            default_policy,
+            client_orders: HashMap::new(),
+            order_owners: HashMap::new(),
+            client_accounts: vec![(REST_CLIENT, REST_ACCOUNT.to_string())]
+                .into_iter()
+                .collect(),
+            request: None,
+            status: TradingStatus::Open,
+            price_band,
+            volatility_guard,
+            halt_duration,
+            halted_until: None,
+            metrics: Metrics::default(),
+            rate_limiter,
+            recent_trades: VecDeque::new(),
+            trade_rates: TradeRates::new(TRADE_RATE_WINDOW),
+            bars: vec![],
+            stats: SessionStats::new(Instant::now(), true),
+            resting: HashMap::new(),
+            quote_min_size: 1,
+            session_report: None,
+            feed,
+            match_counter: 0,
+            lobster,
+            journal: None,
+        };
+        // Feed handlers learn the status without waiting for it to change
+        manager.publish(FeedMessage::TradingStatus(manager.status));
+        manager
+    }
+
+    /// Aggregates trades into bars of these kinds
+    fn with_bars(mut self, kinds: &[BarKind]) -> Self {
+        self.bars = kinds
+            .iter()
+            .map(|kind| BarAggregator::new(*kind, BAR_HISTORY))
+            .collect();
+        self
+    }
+
+    /// Writes the session report to `path` whenever trading closes, counting
+    /// accounts with at least `quote_min_size` on both sides as quoting
+    fn with_session_report(mut self, path: Option<PathBuf>, quote_min_size: Quantity) -> Self {
+        self.session_report = path;
+        self.quote_min_size = quote_min_size;
+        self
+    }
+
+    /// Records the events of the book to `writer`, e.g. for backtests
+    fn with_journal(mut self, writer: Option<BufWriter<File>>) -> Self {
+        if writer.is_some() {
+            self.order_book = std::mem::take(&mut self.order_book).with_journal();
+        }
+        self.journal = writer;
+        self
+    }
+
+    fn write_journal(&mut self) {
+        if let Some(writer) = &mut self.journal {
+            let result = self
+                .order_book
+                .drain_journal()
+                .iter()
+                .try_for_each(|event| {
+                    serde_json::to_writer(&mut *writer, event)?;
+                    writer.write_all(b"\n")
+                });
+            if let Err(e) = result.and_then(|_| writer.flush()) {
+                println!("Could not write journal; err = {:?}", e);
+                self.journal = None;
+                self.order_book.disable_journal();
+            }
+        }
+    }
+
+    fn account(&self, client_id: ClientId) -> String {
+        self.client_accounts
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Changes the resting quantity of the account of a client on one side
+    fn update_resting(
+        &mut self,
+        client_id: ClientId,
+        side: Side,
+        added: Quantity,
+        removed: Quantity,
+    ) {
+        let account = self.account(client_id);
+        let sizes = self.resting.entry(account.clone()).or_default();
+        let size = match side {
+            Side::Bid => &mut sizes.0,
+            Side::Ask => &mut sizes.1,
+        };
+        *size = (*size + added).saturating_sub(removed);
+        if *sizes == (0, 0) {
+            self.resting.remove(&account);
+        }
+    }
+
+    /// Passes the state of the book after a change to the session statistics
+    fn observe_book(&mut self) {
+        let top_size = |side| self.order_book.levels(side).next().map_or(0, |(_, q)| *q);
+        let book = BookState {
+            spread: self.order_book.spread(),
+            bid_size: top_size(Side::Bid),
+            ask_size: top_size(Side::Ask),
+            quoting: self
+                .resting
+                .iter()
+                .filter(|(_, (bid, ask))| {
+                    *bid >= self.quote_min_size && *ask >= self.quote_min_size
+                })
+                .map(|(account, _)| account.clone())
+                .collect(),
+        };
+        self.stats.on_book(book, Instant::now());
+    }
+
+    fn write_session_report(&mut self) {
+        if let Some(path) = &self.session_report {
+            let report = self.stats.report(Instant::now());
+            if let Err(e) = std::fs::write(path, report) {
+                println!("Could not write session report; err = {:?}", e);
+            }
+        }
+    }
+
+    fn record_delivery(&mut self, client_id: ClientId, delivery: Delivery) {
+        match delivery {
+            Delivery::Sent | Delivery::Held => {}
+            Delivery::Dropped => self.metrics.dropped_messages += 1,
+            Delivery::Conflated => self.metrics.conflated_messages += 1,
+            Delivery::Disconnect => {
+                self.metrics.slow_client_disconnects += 1;
+                self.disconnect(client_id);
+            }
+        }
+    }
+
+    /// Records a book event for the market data feed and queues it to be
+    /// sent. Receivers recover the events that did not fit in the queue from
+    /// the retransmission service once they see the gap.
+    fn publish(&mut self, message: FeedMessage) {
+        let timestamp = self.order_book.now().as_nanos() as u64;
+        let event = FeedEvent { timestamp, message };
+        let sequence = self.feed.state.lock().unwrap().record(&event);
+        if let Err(TrySendError::Full(_)) = self.feed.events.try_send((sequence, event)) {
+            self.metrics.feed_overflows += 1;
+        }
+    }
+
+    /// Appends a book event to the LOBSTER export, which stops on the first
+    /// write error
+    fn export(
+        &mut self,
+        event: EventType,
+        order_id: OrderId,
+        size: Quantity,
+        price: &Price,
+        side: Side,
+    ) {
+        if let Some(writer) = &mut self.lobster {
+            let time = self.order_book.now().as_nanos() % (24 * 3600 * 1_000_000_000);
+            let message = LobsterMessage {
+                time: time as u64,
+                event,
+                order_id,
+                size,
+                price: price.clone(),
+                side,
+            };
+            if let Err(e) = writer.write(&message) {
+                println!("Could not write LOBSTER files; err = {:?}", e);
+                self.lobster = None;
+            }
+        }
+    }
+
+    fn send(&mut self, client_id: ClientId, msg: ToClient) {
+        if let Some(session) = self.clients.get_mut(&client_id) {
+            let delivery = session.deliver(msg);
+            self.record_delivery(client_id, delivery);
+        }
+    }
+
+    /// Sends an answer to the request being handled, numbered like the request
+    fn reply(&mut self, client_id: ClientId, msg: ToClient) {
+        let msg = match self.request {
+            Some(request) => ToClient::Reply(request, Box::new(msg)),
+            None => msg,
+        };
+        self.send(client_id, msg);
+    }
+
+    fn broadcast(&mut self, msg: ToClient) {
+        let deliveries: Vec<_> = self
+            .clients
+            .iter_mut()
+            .map(|(client_id, session)| (*client_id, session.deliver(msg.clone())))
+            .collect();
+        for (client_id, delivery) in deliveries {
+            self.record_delivery(client_id, delivery);
+        }
+    }
+
+    /// Sends the trades followed by the bars they went into, the latest depth
+    /// of every touched price level and the checksum of the book
+    fn broadcast_trades(&mut self, trades: &[Trade], levels: HashSet<(Side, Price)>) {
+        for trade in trades {
+            self.broadcast(ToClient::Trade(
+                trade.price.as_bigint_and_exponent(),
+                trade.quantity,
+            ));
+        }
+        for update in self.record_bars(trades) {
+            self.broadcast(update);
+        }
+        let changed = !levels.is_empty();
+        for (side, price) in levels {
+            let quantity = self.order_book.level_size(side, &price);
+            self.broadcast(ToClient::LatestDepth(
+                side,
+                quantity,
+                price.as_bigint_and_exponent(),
+            ));
+        }
+        if changed {
+            self.broadcast(ToClient::BookChecksum(self.order_book.checksum()));
+            self.observe_book();
+        }
+        if self.status == TradingStatus::Auction {
+            let uncross = self
+                .order_book
+                .indicative_uncross()
+                .map(|uncross| (uncross.price.as_bigint_and_exponent(), uncross.volume));
+            self.broadcast(ToClient::IndicativeUncross(uncross));
+        }
+    }
+
+    /// Adds trades to the bars, returns an update of every bar they changed
+    fn record_bars(&mut self, trades: &[Trade]) -> Vec<ToClient> {
+        let mut updates = vec![];
+        for aggregator in &mut self.bars {
+            let mut bars: Vec<Bar> = vec![];
+            for trade in trades {
+                let bar = match aggregator.record(&trade.price, trade.quantity, trade.time) {
+                    Some(bar) => bar,
+                    None => continue,
+                };
+                match bars.last_mut() {
+                    Some(last) if last.number == bar.number => *last = bar,
+                    _ => bars.push(bar),
+                }
+            }
+            let kind = aggregator.kind();
+            updates.extend(
+                bars.into_iter()
+                    .map(|bar| ToClient::BarUpdate(kind, Box::new(bar.into()))),
+            );
+        }
+        updates
+    }
+
+    /// Catches up slow clients whose queues have room again
+    fn flush_slow_clients(&mut self) {
+        let order_book = &self.order_book;
+        let mut snapshot = || {
+            let levels = |side| {
+                order_book
+                    .levels(side)
+                    .map(|(price, quantity)| (price.as_bigint_and_exponent(), *quantity))
+                    .collect()
+            };
+            ToClient::Snapshot(levels(Side::Bid), levels(Side::Ask))
+        };
+        for session in self.clients.values_mut() {
+            if session.flush(&mut snapshot) {
+                self.metrics.slow_client_resyncs += 1;
+            }
+        }
+    }
+
+    /// Records trades, all or none of them if a price doesn't fit the feed
+    fn record_trades(
+        &mut self,
+        trades: &[Trade],
+        aggressor: Option<OrderId>,
+    ) -> Result<(), RejectReason> {
+        let prices = trades
+            .iter()
+            .map(|trade| itch::price_to_feed(&trade.price))
+            .collect::<Option<Vec<_>>>()
+            .ok_or(RejectReason::InvalidPrice)?;
+        self.metrics.trades += trades.len() as u64;
+        self.metrics.traded_quantity += trades
+            .iter()
+            .map(|trade| trade.quantity as u64)
+            .sum::<u64>();
+        for (trade, price) in trades.iter().zip(prices) {
+            self.recent_trades.push_front(trade.clone());
+            self.trade_rates
+                .record(trade.price.clone(), trade.quantity, trade.time);
+            self.match_counter += 1;
+            self.publish(FeedMessage::Trade {
+                match_number: self.match_counter,
+                quantity: trade.quantity,
+                price,
+            });
+            for order_id in &[trade.bid_order_id, trade.ask_order_id] {
+                self.publish(FeedMessage::OrderExecuted {
+                    order_id: *order_id,
+                    quantity: trade.quantity,
+                    match_number: self.match_counter,
+                });
+            }
+            // The incoming order of a match is exported once it rests in the book
+            for &(order_id, side) in &[
+                (trade.bid_order_id, Side::Bid),
+                (trade.ask_order_id, Side::Ask),
+            ] {
+                if Some(order_id) != aggressor {
+                    self.export(
+                        EventType::Execution,
+                        order_id,
+                        trade.quantity,
+                        &trade.price,
+                        side,
+                    );
+                }
+            }
+            for &(order_id, side) in &[
+                (trade.bid_order_id, Side::Bid),
+                (trade.ask_order_id, Side::Ask),
+            ] {
+                // The incoming order only rests with what is left after matching
+                if Some(order_id) == aggressor {
+                    continue;
+                }
+                if let Some(owner) = self.order_owners.get(&order_id).copied() {
+                    self.update_resting(owner, side, 0, trade.quantity);
+                }
+            }
+            let accounts: Vec<_> = [trade.bid_order_id, trade.ask_order_id]
+                .iter()
+                .filter_map(|order_id| self.order_owners.get(order_id))
+                .map(|owner| self.account(*owner))
+                .collect();
+            let accounts: Vec<_> = accounts.iter().map(String::as_str).collect();
+            self.stats.on_trade(trade.quantity, &accounts);
+            let price = trade.price.as_bigint_and_exponent();
+            for order_id in &[trade.bid_order_id, trade.ask_order_id] {
+                if let Some(owner) = self.order_owners.get(order_id).copied() {
+                    let fill = ToClient::Fill(*order_id, price.clone(), trade.quantity);
+                    self.send(owner, fill);
+                }
+            }
+        }
+        self.recent_trades.truncate(RECENT_TRADES);
+        Ok(())
+    }
+
+    /// Forgets the orders of the trades that have been completely filled
+    fn remove_filled_orders(&mut self, trades: &[Trade]) {
+        for trade in trades {
+            for order_id in &[trade.bid_order_id, trade.ask_order_id] {
+                if self.order_book.get_order(*order_id).is_some() {
+                    continue;
+                }
+                if let Some(owner) = self.order_owners.remove(order_id) {
+                    if let Some(orders) = self.client_orders.get_mut(&owner) {
+                        orders.retain(|other| other != order_id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Rejects orders while trading is halted or closed, outside the price band,
+    /// with prices the feed can't carry and without quantity
+    fn check_order(&mut self, price: &Price, quantity: Quantity) -> Result<(), RejectReason> {
+        let rejected = match self.status {
+            TradingStatus::Halted => Some(RejectReason::TradingHalted),
+            TradingStatus::Closed => Some(RejectReason::MarketClosed),
+            _ if quantity == 0 => Some(RejectReason::InvalidQuantity),
+            _ if itch::price_to_feed(price).is_none() => Some(RejectReason::InvalidPrice),
+            _ => match &self.price_band {
+                Some(band) if !band.allows(price) => Some(RejectReason::OutsidePriceBand),
+                _ => None,
+            },
+        };
+        match rejected {
+            Some(reason) => {
+                *self
+                    .metrics
+                    .orders_rejected
+                    .entry(format!("{:?}", reason))
+                    .or_default() += 1;
+                Err(reason)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the id of the accepted order and the trades it executed
+    fn place_order(
+        &mut self,
+        client_id: ClientId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        time_in_force: TimeInForce,
+    ) -> Result<(OrderId, Vec<Trade>), RejectReason> {
+        self.stats.on_message(&self.account(client_id));
+        self.check_order(&price, quantity)?;
+        let now = self.order_book.now();
+        if matches!(time_in_force.deadline(now), Some(deadline) if deadline <= now) {
+            return Err(RejectReason::Expired);
+        }
+        self.add_order(client_id, side, price, quantity, time_in_force, None)
+    }
+
+    /// Adds an order to the book, acknowledges it to the client and matches it
+    fn add_order(
+        &mut self,
+        client_id: ClientId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        time_in_force: TimeInForce,
+        replaced: Option<OrderId>,
+    ) -> Result<(OrderId, Vec<Trade>), RejectReason> {
+        // Prices that don't fit the feed are rejected before the order
+        // changes anything
+        let price_to_feed = itch::price_to_feed(&price).ok_or(RejectReason::InvalidPrice)?;
+        let order_id = self.order_counter;
+        self.order_counter += 1;
+        self.metrics.orders_placed += 1;
+        self.order_book
+            .on_new_order(side, price.clone(), quantity, order_id);
+        self.order_book.set_time_in_force(order_id, time_in_force);
+        self.client_orders
+            .entry(client_id)
+            .or_default()
+            .push(order_id);
+        self.order_owners.insert(order_id, client_id);
+        let (ack, published) = match replaced {
+            Some(replaced) => (
+                ToClient::OrderReplaced(replaced, order_id),
+                FeedMessage::ReplaceOrder {
+                    order_id: replaced,
+                    new_order_id: order_id,
+                    quantity,
+                    price: price_to_feed,
+                },
+            ),
+            None => (
+                ToClient::OrderAccepted(order_id),
+                FeedMessage::AddOrder {
+                    order_id,
+                    side,
+                    quantity,
+                    price: price_to_feed,
+                },
+            ),
+        };
+        self.reply(client_id, ack);
+        self.publish(published);
+
+        let trades = match self.status {
+            TradingStatus::Open => self.order_book.match_order(order_id),
+            _ => vec![],
+        };
+        let opposite = match side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let mut levels: HashSet<_> = trades
+            .iter()
+            .map(|trade| (opposite, trade.price.clone()))
+            .collect();
+        levels.insert((side, price.clone()));
+        self.record_trades(&trades, Some(order_id))?;
+        if let Some(&(_, _, remaining)) = self.order_book.get_order(order_id) {
+            self.export(EventType::Submission, order_id, remaining, &price, side);
+            self.update_resting(client_id, side, remaining, 0);
+        }
+        self.remove_filled_orders(&trades);
+        self.broadcast_trades(&trades, levels);
+
+        if let Some(guard) = &mut self.volatility_guard {
+            let now = Instant::now();
+            let mut breached = false;
+            for trade in &trades {
+                breached |= guard.on_trade(now, trade.price.clone());
+            }
+            if breached {
+                self.halted_until = Some((now + self.halt_duration, self.status));
+                self.set_status(TradingStatus::Halted);
+            }
+        }
+        Ok((order_id, trades))
+    }
+
+    /// Cancels a resting order and places a new one with the same side,
+    /// returning the id of the new order
+    fn replace_order(
+        &mut self,
+        client_id: ClientId,
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    ) -> Result<OrderId, RejectReason> {
+        self.stats.on_message(&self.account(client_id));
+        let side = match (
+            self.order_owners.get(&order_id),
+            self.order_book.get_order(order_id),
+        ) {
+            (Some(owner), Some((side, _, _))) if *owner == client_id => *side,
+            _ => return Err(RejectReason::UnknownOrder),
+        };
+        self.check_order(&price, quantity)?;
+        // The replacement keeps the deadline of the order
+        let time_in_force = self
+            .order_book
+            .expiry(order_id)
+            .map_or(TimeInForce::GoodTillCancel, TimeInForce::GoodTillTime);
+        self.remove_order(client_id, order_id);
+        self.add_order(
+            client_id,
+            side,
+            price,
+            quantity,
+            time_in_force,
+            Some(order_id),
+        )
+        .map(|(order_id, _)| order_id)
+    }
+
+    /// Cancels a resting order of a client, returns false if it has no such order
+    fn cancel_order(&mut self, client_id: ClientId, order_id: OrderId) -> bool {
+        self.stats.on_message(&self.account(client_id));
+        let removed = self.remove_order(client_id, order_id);
+        if removed {
+            self.publish(FeedMessage::DeleteOrder { order_id });
+            self.metrics.orders_cancelled += 1;
+        }
+        removed
+    }
+
+    /// Removes the orders that reached the deadline of their time in force and
+    /// tells their owners
+    fn expire_orders(&mut self) {
+        let expired = self.order_book.expire_orders();
+        if expired.is_empty() {
+            return;
+        }
+        let mut levels = HashSet::new();
+        for Expired {
+            order_id,
+            side,
+            price,
+            quantity,
+        } in expired
+        {
+            if let Some(client_id) = self.order_owners.remove(&order_id) {
+                if let Some(orders) = self.client_orders.get_mut(&client_id) {
+                    orders.retain(|order| *order != order_id);
+                }
+                self.update_resting(client_id, side, 0, quantity);
+                self.send(client_id, ToClient::OrderExpired(order_id));
+            }
+            self.export(EventType::Deletion, order_id, quantity, &price, side);
+            self.publish(FeedMessage::DeleteOrder { order_id });
+            self.metrics.orders_expired += 1;
+            levels.insert((side, price));
+        }
+        self.broadcast_trades(&[], levels);
+    }
+
+    /// Takes a resting order of a client out of the book
+    fn remove_order(&mut self, client_id: ClientId, order_id: OrderId) -> bool {
+        let orders = self.client_orders.entry(client_id).or_default();
+        let position = match orders.iter().position(|order| *order == order_id) {
+            Some(position) => position,
+            None => return false,
+        };
+        orders.remove(position);
+        self.order_owners.remove(&order_id);
+        let mut levels = HashSet::new();
+        if let Some((side, price, quantity)) = self.order_book.get_order(order_id).cloned() {
+            self.export(EventType::Deletion, order_id, quantity, &price, side);
+            self.update_resting(client_id, side, 0, quantity);
+            levels.insert((side, price));
+        }
+        self.order_book.on_cancel_order(order_id);
+        self.broadcast_trades(&[], levels);
+        true
+    }
+
+    /// Cancels every resting order of a client and returns how many there were
+    fn cancel_all_orders(&mut self, client_id: ClientId) -> usize {
+        let mut levels = HashSet::new();
+        let client_orders = self.client_orders.remove(&client_id).unwrap_or_default();
+        for cancel_order in &client_orders {
+            if let Some((side, price, quantity)) = self.order_book.get_order(*cancel_order).cloned()
+            {
+                self.export(EventType::Deletion, *cancel_order, quantity, &price, side);
+                self.update_resting(client_id, side, 0, quantity);
+                levels.insert((side, price));
+            }
+            self.order_book.on_cancel_order(*cancel_order);
+            self.order_owners.remove(cancel_order);
+            self.send(client_id, ToClient::OrderCancelled(*cancel_order));
+            self.publish(FeedMessage::DeleteOrder {
+                order_id: *cancel_order,
+            });
+        }
+        self.broadcast_trades(&[], levels);
+        self.metrics.orders_cancelled += client_orders.len() as u64;
+        client_orders.len()
+    }
+
+    fn disconnect(&mut self, client_id: ClientId) {
+        let session = self.clients.remove(&client_id);
+        let orders = self
+            .client_orders
+            .get(&client_id)
+            .cloned()
+            .unwrap_or_default();
+        self.cancel_all_orders(client_id);
+        // The FIX gateway stores the cancels for the counterparty to ask for
+        // after reconnecting
+        if let Some(session) = session {
+            session.close(orders.into_iter().map(ToClient::OrderCancelled));
+        }
+        self.client_accounts.remove(&client_id);
+    }
+
+    fn levels(&self, side: Side) -> Levels {
+        self.order_book
+            .levels(side)
+            .map(|(price, quantity)| (price.as_bigint_and_exponent(), *quantity))
+            .collect()
+    }
+
+    fn snapshot(&self) -> ToClient {
+        ToClient::Snapshot(self.levels(Side::Bid), self.levels(Side::Ask))
+    }
+
+    fn json_levels(&self, side: Side, depth: Option<usize>) -> Vec<JsonLevel> {
+        self.order_book
+            .levels(side)
+            .take(depth.unwrap_or(usize::MAX))
+            .map(|(price, quantity)| JsonLevel {
+                price: price.clone(),
+                quantity: *quantity,
+            })
+            .collect()
+    }
+
+    fn handle_rest(&mut self, request: RestRequest) -> RestReply {
+        let trade_levels = |trades: &mut dyn Iterator<Item = &Trade>| {
+            trades
+                .map(|trade| JsonLevel {
+                    price: trade.price.clone(),
+                    quantity: trade.quantity,
+                })
+                .collect()
+        };
+        match request {
+            RestRequest::Book { depth } => RestReply::Book {
+                bid_levels: self.order_book.get_book_depth(Side::Bid),
+                ask_levels: self.order_book.get_book_depth(Side::Ask),
+                bids: self.json_levels(Side::Bid, depth),
+                asks: self.json_levels(Side::Ask, depth),
+            },
+            RestRequest::Top => RestReply::Top {
+                bid: self.json_levels(Side::Bid, Some(1)).pop(),
+                ask: self.json_levels(Side::Ask, Some(1)).pop(),
+            },
+            RestRequest::PlaceOrder(NewOrder {
+                side,
+                price,
+                quantity,
+                time_in_force,
+            }) => match self.place_order(REST_CLIENT, side, price, quantity, time_in_force) {
+                Ok((order_id, trades)) => RestReply::OrderPlaced {
+                    order_id,
+                    trades: trade_levels(&mut trades.iter()),
+                },
+                Err(reason) => RestReply::OrderRejected { reason },
+            },
+            RestRequest::CancelOrder(order_id) => {
+                if self.cancel_order(REST_CLIENT, order_id) {
+                    RestReply::OrderCancelled { order_id }
+                } else {
+                    RestReply::NotFound {
+                        error: format!("No resting order {}", order_id),
+                    }
+                }
+            }
+            RestRequest::Trades { limit } => RestReply::Trades {
+                trades: trade_levels(&mut self.recent_trades.iter().take(limit)),
+            },
+        }
+    }
+
+    fn handle_admin(&mut self, command: AdminCommand) -> ToAdmin {
+        match command {
+            AdminCommand::ListClients => {
+                let mut clients: Vec<_> = self
+                    .clients
+                    .keys()
+                    .map(|client_id| {
+                        let orders = self.client_orders.get(client_id).map_or(0, Vec::len);
+                        (*client_id, orders)
+                    })
+                    .collect();
+                clients.sort_unstable();
+                ToAdmin::Clients(clients)
+            }
+            AdminCommand::DumpBook => ToAdmin::Book(self.levels(Side::Bid), self.levels(Side::Ask)),
+            AdminCommand::CancelAllOrders(client_id) => {
+                ToAdmin::OrdersCancelled(client_id, self.cancel_all_orders(client_id))
+            }
+            AdminCommand::SetTradingStatus(status) => {
+                self.set_status(status);
+                ToAdmin::TradingStatus(self.status)
+            }
+            AdminCommand::ForceSnapshot => {
+                self.broadcast(self.snapshot());
+                ToAdmin::SnapshotSent(self.clients.len())
+            }
+        }
+    }
+
+    /// Executes a crossed book at its clearing price which becomes the new
+    /// reference price of the price band
+    fn uncross(&mut self) -> Result<(), RejectReason> {
+        let uncross = match self.order_book.indicative_uncross() {
+            Some(uncross) => uncross,
+            None => return Ok(()),
+        };
+        itch::price_to_feed(&uncross.price).ok_or(RejectReason::InvalidPrice)?;
+        // Levels of every order that can take part in the uncross
+        let order_book = &self.order_book;
+        let levels = self
+            .client_orders
+            .values()
+            .flatten()
+            .filter_map(|order_id| order_book.get_order(*order_id))
+            .filter(|(side, price, _)| match side {
+                Side::Bid => *price >= uncross.price,
+                Side::Ask => *price <= uncross.price,
+            })
+            .map(|(side, price, _)| (*side, price.clone()))
+            .collect();
+        let trades = self.order_book.uncross();
+        self.record_trades(&trades, None)?;
+        self.remove_filled_orders(&trades);
+        self.broadcast_trades(&trades, levels);
+        if let Some(band) = &mut self.price_band {
+            band.reference = Some(uncross.price);
+        }
+        Ok(())
+    }
+
+    fn set_status(&mut self, status: TradingStatus) {
+        if status == self.status {
+            return;
+        }
+        if self.status == TradingStatus::Halted {
+            self.halted_until = None;
+            if let Some(guard) = &mut self.volatility_guard {
+                guard.reset();
+            }
+        }
+        self.status = status;
+        let trading = matches!(status, TradingStatus::Open | TradingStatus::Auction);
+        self.stats.on_status(trading, Instant::now());
+        self.broadcast(ToClient::TradingStatus(status));
+        self.publish(FeedMessage::TradingStatus(status));
+        // Orders collected in an auction are executed before trading continues or closes
+        if matches!(status, TradingStatus::Open | TradingStatus::Closed) {
+            if let Err(reason) = self.uncross() {
+                println!("Could not uncross the book; reason = {:?}", reason);
+            }
+        }
+        match status {
+            TradingStatus::Open => {}
+            TradingStatus::Closed => {
+                self.write_session_report();
+                // Each session gets a report of its own
+                self.stats = SessionStats::new(Instant::now(), false);
+                self.observe_book();
+            }
+            TradingStatus::Auction => self.broadcast_trades(&[], HashSet::new()),
+            TradingStatus::Halted => {}
+        }
+    }
+
+    fn render_metrics(&self) -> String {
+        let metrics = &self.metrics;
+        let mut encoder = Encoder::default();
+        encoder.counter(
+            "orderbook_orders_placed_total",
+            "Orders accepted into the book",
+            metrics.orders_placed,
+        );
+        encoder.counter(
+            "orderbook_orders_cancelled_total",
+            "Orders cancelled",
+            metrics.orders_cancelled,
+        );
+        encoder.counter(
+            "orderbook_orders_expired_total",
+            "Orders removed at the deadline of their time in force",
+            metrics.orders_expired,
+        );
+        encoder.labelled_counter(
+            "orderbook_orders_rejected_total",
+            "Orders rejected per reason",
+            "reason",
+            &metrics.orders_rejected,
+        );
+        encoder.counter("orderbook_trades_total", "Trades executed", metrics.trades);
+        encoder.counter(
+            "orderbook_traded_quantity_total",
+            "Quantity traded",
+            metrics.traded_quantity,
+        );
+        encoder.labelled_counter(
+            "orderbook_messages_total",
+            "Messages processed by the order manager per type",
+            "type",
+            &metrics.messages,
+        );
+        encoder.counter(
+            "orderbook_dropped_messages_total",
+            "Messages not sent to slow clients",
+            metrics.dropped_messages,
+        );
+        encoder.counter(
+            "orderbook_conflated_messages_total",
+            "Depth updates held back for slow clients",
+            metrics.conflated_messages,
+        );
+        encoder.counter(
+            "orderbook_slow_client_resyncs_total",
+            "Snapshots sent to slow clients after dropping messages",
+            metrics.slow_client_resyncs,
+        );
+        encoder.counter(
+            "orderbook_slow_client_disconnects_total",
+            "Clients disconnected for being too slow",
+            metrics.slow_client_disconnects,
+        );
+        encoder.counter(
+            "orderbook_feed_overflows_total",
+            "Feed events only available from the retransmission service",
+            metrics.feed_overflows,
+        );
+        encoder.counter(
+            "orderbook_throttled_messages_total",
+            "Messages rejected by rate limits",
+            self.rate_limiter.throttled.load(Ordering::Relaxed),
+        );
+        encoder.counter(
+            "orderbook_abuse_disconnects_total",
+            "Sessions disconnected for exceeding rate limits",
+            self.rate_limiter.abuse_disconnects.load(Ordering::Relaxed),
+        );
+        encoder.gauge(
+            "orderbook_connected_clients",
+            "Connected clients",
+            self.clients.len(),
+        );
+        encoder.labelled_gauge(
+            "orderbook_book_depth",
+            "Price levels per side of the book",
+            "side",
+            &[
+                ("bid", self.order_book.get_book_depth(Side::Bid)),
+                ("ask", self.order_book.get_book_depth(Side::Ask)),
+            ],
+        );
+        encoder.gauge(
+            "orderbook_queue_length",
+            "Messages waiting for the order manager",
+            metrics.queue_length,
+        );
+        encoder.histogram(
+            "orderbook_command_duration_seconds",
+            "Time spent processing a message in the order manager",
+            &metrics.command_latency,
+        );
+        encoder.finish()
+    }
+
+    fn handle(&mut self, mut msg: ToOrderManager) {
+        self.request = None;
+        while let ToOrderManager::Request(request, inner) = msg {
+            self.request = Some(request);
+            msg = *inner;
+        }
+        *self.metrics.messages.entry(msg.name()).or_default() += 1;
+        // Orders past their deadline don't trade even between heartbeats
+        self.expire_orders();
+        match msg {
+            ToOrderManager::PlaceOrder(client_id, side, price, quantity, time_in_force) => {
+                if let Err(reason) =
+                    self.place_order(client_id, side, price, quantity, time_in_force)
+                {
+                    self.reply(client_id, ToClient::Rejected(reason));
+                }
+            }
+            ToOrderManager::ClientConnected(to_client, account) => {
+                let client_id = self.client_counter;
+                self.client_counter += 1;
+                self.client_accounts.insert(client_id, account);
+                self.clients.insert(
+                    client_id,
+                    ClientSession::new(to_client, self.default_policy),
+                );
+                self.send(client_id, ToClient::Connected(client_id));
+                self.send(client_id, ToClient::TradingStatus(self.status));
+                self.send(client_id, self.snapshot());
+            }
+            ToOrderManager::ClientDisconnected(client_id) => self.disconnect(client_id),
+            ToOrderManager::Login(client_id, account) => {
+                self.client_accounts.insert(client_id, account);
+            }
+            ToOrderManager::GetOrderDepth(client_id, side) => self.reply(
+                client_id,
+                ToClient::BookDepth(side, self.order_book.get_book_depth(side)),
+            ),
+            ToOrderManager::GetTopOfBook(client_id, side) => {
+                let reply = match self.order_book.top_of_book(side) {
+                    Some(price) => ToClient::TopOfBook(side, price.as_bigint_and_exponent()),
+                    None => ToClient::Rejected(RejectReason::NoPriceLevel),
+                };
+                self.reply(client_id, reply)
+            }
+            ToOrderManager::GetSizeForPriceLevel(client_id, side, price) => {
+                let reply = match self.order_book.size_for_price_level(side, &price) {
+                    Some(size) => ToClient::SizeForPriceLevel(side, size),
+                    None => ToClient::Rejected(RejectReason::NoPriceLevel),
+                };
+                self.reply(client_id, reply)
+            }
+            ToOrderManager::CancelOrder(client_id, order_id) => {
+                let reply = if self.cancel_order(client_id, order_id) {
+                    ToClient::OrderCancelled(order_id)
+                } else {
+                    ToClient::Rejected(RejectReason::UnknownOrder)
+                };
+                self.reply(client_id, reply);
+            }
+            ToOrderManager::ReplaceOrder(client_id, order_id, price, quantity) => {
+                if let Err(reason) = self.replace_order(client_id, order_id, price, quantity) {
+                    self.reply(client_id, ToClient::Rejected(reason));
+                }
+            }
+            ToOrderManager::SetSlowConsumerPolicy(client_id, policy) => {
+                if let Some(session) = self.clients.get_mut(&client_id) {
+                    session.set_policy(policy);
+                }
+            }
+            ToOrderManager::GetSnapshot(client_id) => self.reply(client_id, self.snapshot()),
+            ToOrderManager::GetAnalytics(client_id, levels, bps) => {
+                let analytics = self.order_book.analytics(levels, &bps);
+                self.reply(client_id, ToClient::Analytics(Box::new(analytics.into())))
+            }
+            ToOrderManager::GetQueuePositions(client_id) => {
+                let now = self.order_book.now();
+                let positions = self
+                    .client_orders
+                    .get(&client_id)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|order_id| {
+                        self.order_book
+                            .queue_position(*order_id, &self.trade_rates, now)
+                    })
+                    .map(Into::into)
+                    .collect();
+                self.reply(client_id, ToClient::QueuePositions(positions))
+            }
+            ToOrderManager::GetBars(client_id, kind, limit) => {
+                let bars = self
+                    .bars
+                    .iter()
+                    .find(|aggregator| aggregator.kind() == kind)
+                    .map_or(vec![], |aggregator| aggregator.history(limit));
+                let bars = bars.into_iter().map(Into::into).collect();
+                self.reply(client_id, ToClient::Bars(kind, bars))
+            }
+            // Unwrapped above
+            ToOrderManager::Request(..) => {}
+            ToOrderManager::SetTradingStatus(status) => self.set_status(status),
+            ToOrderManager::Admin(command, reply) => {
+                let _ = reply.send(self.handle_admin(command));
+            }
+            ToOrderManager::GetMetrics(reply) => {
+                let _ = reply.send(self.render_metrics());
+            }
+            ToOrderManager::Rest(request, reply) => {
+                let _ = reply.send(self.handle_rest(request));
+            }
+        }
+    }
+
+    fn on_heartbeat(&mut self) {
+        self.expire_orders();
+        self.flush_slow_clients();
+        if let Some(writer) = &mut self.lobster {
+            let _ = writer.flush();
+        }
+        self.write_journal();
+        if let Some((until, resume)) = self.halted_until {
+            if Instant::now() >= until {
+                self.set_status(resume);
+            }
+        }
+    }
+}
+
+async fn server_loop(mut events: mpsc::Receiver<ToOrderManager>, mut manager: OrderManager) {
+    let mut heartbeat = tokio::time::interval(Duration::from_millis(100));
     loop {
         tokio::select! {
             Some(msg) = events.recv() => {
+                let start = Instant::now();
+                manager.handle(msg);
+                manager.metrics.command_latency.observe(start.elapsed());
+                manager.metrics.queue_length = events.len();
+            }
+            _ = heartbeat.tick() => manager.on_heartbeat(),
+        }
+    }
+}
+/// What the sessions of all clients share
+struct SessionConfig {
+    queue_capacity: usize,
+    rate_limiter: Arc<RateLimiter>,
+    accounts: Arc<Accounts>,
+}
+
+/// Connection of a client to the order manager, shared by the TCP and WebSocket loops
+struct Session {
+    to_server: Sender<ToOrderManager>,
+    client_id: Option<ClientId>,
+    address: String,
+    /// Sessions trade for the address they connect from until they log in
+    account: String,
+    limits: SessionLimits,
+    config: Arc<SessionConfig>,
+}
+
+impl Session {
+    /// Registers with the order manager and returns the queue of messages for the client
+    async fn connect(
+        to_server: Sender<ToOrderManager>,
+        socket: &TcpStream,
+        config: Arc<SessionConfig>,
+    ) -> (Session, mpsc::Receiver<ToClient>) {
+        let (client_tx, client_rx) = mpsc::channel(config.queue_capacity);
+        let address = socket
+            .peer_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default();
+        let connect_msg = ToOrderManager::ClientConnected(client_tx, address.clone());
+        if to_server.send(connect_msg).await.is_err() {
+            println!("Could not connect to server");
+        }
+        let session = Session {
+            to_server,
+            client_id: None,
+            account: address.clone(),
+            address,
+            limits: SessionLimits::default(),
+            config,
+        };
+        (session, client_rx)
+    }
+
+    /// Remembers the client id once the order manager has assigned it
+    fn on_to_client(&mut self, msg: &ToClient) {
+        if let ToClient::Connected(client_id) = msg {
+            self.client_id = Some(*client_id);
+        }
+    }
+
+    /// Forwards a message to the order manager after checking the rate limits.
+    /// Returns a reply to send to the client right away, or an error if the
+    /// session should be closed.
+    async fn handle(&mut self, mut msg: ToServer) -> Result<Option<ToClient>, String> {
+        let mut request = None;
+        while let ToServer::Request(id, inner) = msg {
+            request = Some(id);
+            msg = *inner;
+        }
+        let reply = |msg| match request {
+            Some(id) => Some(ToClient::Reply(id, Box::new(msg))),
+            None => Some(msg),
+        };
+        match self.config.rate_limiter.check(
+            &mut self.limits,
+            &self.account,
+            msg.name(),
+            Instant::now(),
+        ) {
+            Verdict::Allow => {}
+            Verdict::Throttle => return Ok(reply(ToClient::Rejected(RejectReason::Throttled))),
+            Verdict::Disconnect => {
+                return Err(format!(
+                    "Disconnecting client {:?} of account {} for sending too many messages",
+                    self.client_id, self.account
+                ))
+            }
+        }
+        let to_order_manager = match (msg, self.client_id) {
+            (ToServer::Login(account, password), client_id) => {
+                // Without passwords anyone can claim an account, so its rate
+                // limits are only shared with sessions from the same address
+                self.account = match self.config.accounts.login(&account, &password) {
+                    Login::Verified => account,
+                    Login::Unverified => format!("{}@{}", account, self.address),
+                    Login::Refused => {
+                        return Ok(reply(ToClient::Rejected(RejectReason::LoginFailed)))
+                    }
+                };
+                match client_id {
+                    Some(client_id) => ToOrderManager::Login(client_id, self.account.clone()),
+                    None => return Ok(None),
+                }
+            }
+            // Requests before the order manager assigned an id are dropped
+            (_, None) => return Ok(None),
+            (ToServer::GetBookDepth(side), Some(client_id)) => {
+                ToOrderManager::GetOrderDepth(client_id, side)
+            }
+            (
+                ToServer::PlaceOrder(side, (digits, scale), quantity, time_in_force),
+                Some(client_id),
+            ) => {
+                let price = BigDecimal::new(digits, scale);
+                ToOrderManager::PlaceOrder(client_id, side, price, quantity, time_in_force)
+            }
+            (ToServer::GetTopOfBook(side), Some(client_id)) => {
+                ToOrderManager::GetTopOfBook(client_id, side)
+            }
+            (ToServer::GetSizeForPriceLevel(side, (digits, scale)), Some(client_id)) => {
+                let price = BigDecimal::new(digits, scale);
+                ToOrderManager::GetSizeForPriceLevel(client_id, side, price)
+            }
+            (ToServer::SetSlowConsumerPolicy(policy), Some(client_id)) => {
+                ToOrderManager::SetSlowConsumerPolicy(client_id, policy)
+            }
+            (ToServer::CancelOrder(order_id), Some(client_id)) => {
+                ToOrderManager::CancelOrder(client_id, order_id)
+            }
+            (ToServer::ReplaceOrder(order_id, (digits, scale), quantity), Some(client_id)) => {
+                let price = BigDecimal::new(digits, scale);
+                ToOrderManager::ReplaceOrder(client_id, order_id, price, quantity)
+            }
+            (ToServer::RequestSnapshot, Some(client_id)) => ToOrderManager::GetSnapshot(client_id),
+            (ToServer::GetAnalytics(levels, (digits, scale)), Some(client_id)) => {
+                ToOrderManager::GetAnalytics(client_id, levels, BigDecimal::new(digits, scale))
+            }
+            (ToServer::GetQueuePositions, Some(client_id)) => {
+                ToOrderManager::GetQueuePositions(client_id)
+            }
+            (ToServer::GetBars(kind, limit), Some(client_id)) => {
+                ToOrderManager::GetBars(client_id, kind, limit)
+            }
+            // Unwrapped above
+            (ToServer::Request(..), Some(_)) => return Ok(None),
+        };
+        let to_order_manager = match request {
+            Some(id) => ToOrderManager::Request(id, Box::new(to_order_manager)),
+            None => to_order_manager,
+        };
+        self.to_server
+            .send(to_order_manager)
+            .await
+            .map_err(|_| "Order manager has stopped".to_string())?;
+        Ok(None)
+    }
+
+    async fn disconnect(self) {
+        if let Some(client_id) = self.client_id {
+            let _ = self
+                .to_server
+                .send(ToOrderManager::ClientDisconnected(client_id))
+                .await;
+        }
+    }
+}
+
+async fn client_loop(
+    to_server: Sender<ToOrderManager>,
+    socket: TcpStream,
+    config: Arc<SessionConfig>,
+) {
+    // Acknowledgements are small and latency matters more than batching them
+    let _ = socket.set_nodelay(true);
+    let (mut session, mut client_rx) = Session::connect(to_server, &socket, config).await;
+    let mut socket = Framed::new(socket, LengthDelimitedCodec::new());
+    loop {
+        let msg = tokio::select! {
+            frame = socket.next() => {
+                let frame = match frame {
+                    Some(Ok(frame)) => frame,
+                    Some(Err(e)) => {
+                        println!("Failed to read from socket; err = {:?}", e);
+                        break;
+                    }
+                    None => break,
+                };
+                let to_server_msg: ToServer = match bincode::deserialize(&frame) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        println!("Invalid message from client; err = {:?}", e);
+                        continue;
+                    }
+                };
+                match session.handle(to_server_msg).await {
+                    Ok(Some(reply)) => reply,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        println!("{}", e);
+                        break;
+                    }
+                }
+            }
+            msg = client_rx.recv() => {
+                // The order manager dropped the client for being too slow
                 match msg {
-                    ToOrderManager::PlaceOrder(client_id, side, price, quantity) => {
-                        order_book.on_new_order(side, price.clone(), quantity, order_counter);
-                        let orders = client_orders.entry(client_id).or_insert(vec![]);
-                        orders.push(order_counter);
-
-                        order_counter += 1;
-
-                        let quantity = order_book.get_size_for_price_level(side, price.clone());
-                        for (_, to_client) in &clients {
-                            if let Err(err) =
-                                to_client.send(ToClient::LatestDepth(side, quantity, price.as_bigint_and_exponent()))
-                            {
-                                println!("Could not send to client {:?}", err);
-                            }
-                        }
+                    Some(msg) => msg,
+                    None => break,
+                }
+            }
+        };
+        session.on_to_client(&msg);
+        if let Err(e) = socket.send(bincode::serialize(&msg).unwrap().into()).await {
+            println!("Could not send to client; err = {:?}", e);
+            break;
+        }
+    }
+    session.disconnect().await;
+}
+
+/// Serves a browser client speaking the JSON protocol of `server::json`
+async fn websocket_loop(
+    to_server: Sender<ToOrderManager>,
+    socket: TcpStream,
+    config: Arc<SessionConfig>,
+) {
+    let (mut session, mut client_rx) = Session::connect(to_server, &socket, config).await;
+    let mut socket = match tokio_tungstenite::accept_async(socket).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            println!("WebSocket handshake failed; err = {:?}", e);
+            session.disconnect().await;
+            return;
+        }
+    };
+    let mut channels: HashSet<Channel> = HashSet::new();
+    // A book subscription before the order manager knew the client gets its
+    // snapshot once it does
+    let mut snapshot_owed = false;
+    loop {
+        let msg = tokio::select! {
+            frame = socket.next() => {
+                let text = match frame {
+                    Some(Ok(WsMessage::Text(text))) => text,
+                    Some(Ok(WsMessage::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        println!("Failed to read from WebSocket; err = {:?}", e);
+                        break;
                     }
-                    ToOrderManager::ClientConnected(to_client) => {
-                        if let Err(err) = to_client.send(ToClient::Connected(client_counter)) {
-                            println!("Could not connect with client.. {:?}", err);
-                            continue;
+                };
+                let request: JsonRequest = match serde_json::from_str(&text) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let error = JsonMessage::Error { message: e.to_string() };
+                        if socket.send(WsMessage::Text(serde_json::to_string(&error).unwrap())).await.is_err() {
+                            break;
                         }
-                        clients.insert(client_counter, to_client);
-                        client_counter += 1;
+                        continue;
                     }
-                    ToOrderManager::ClientDisconnected(client_id) => {
-                        if let Some(client_orders) = client_orders.get(&client_id) {
-                            for cancel_order in client_orders {
-                                order_book.on_cancel_order(*cancel_order);
+                };
+                match request {
+                    JsonRequest::Subscribe { channels: subscribe } => {
+                        // A fresh snapshot lets the client build the book from scratch
+                        if subscribe.contains(&Channel::Book) {
+                            match session.client_id {
+                                Some(client_id) => {
+                                    let _ = session.to_server.send(ToOrderManager::GetSnapshot(client_id)).await;
+                                }
+                                None => snapshot_owed = true,
                             }
                         }
-                        clients.remove(&client_id);
-                        client_orders.remove(&client_id);
+                        channels.extend(subscribe);
+                        JsonMessage::Subscribed { channels: channels.iter().copied().collect() }
                     }
-                    ToOrderManager::GetOrderDepth(client_id,side) => {
-                        if let Some(to_client) = clients.get(&client_id) {
-                            to_client.send(ToClient::BookDepth(side,order_book.get_book_depth(side)));
+                    JsonRequest::Unsubscribe { channels: unsubscribe } => {
+                        channels.retain(|channel| !unsubscribe.contains(channel));
+                        JsonMessage::Subscribed { channels: channels.iter().copied().collect() }
+                    }
+                    request => match session.handle(request.into_to_server().unwrap()).await {
+                        Ok(Some(reply)) => reply.into(),
+                        Ok(None) => continue,
+                        Err(e) => {
+                            println!("{}", e);
+                            break;
                         }
+                    },
+                }
+            }
+            msg = client_rx.recv() => {
+                let msg = match msg {
+                    Some(msg) => msg,
+                    None => break,
+                };
+                session.on_to_client(&msg);
+                if let (true, ToClient::Connected(client_id)) = (snapshot_owed, &msg) {
+                    snapshot_owed = false;
+                    let _ = session.to_server.send(ToOrderManager::GetSnapshot(*client_id)).await;
+                }
+                let msg = JsonMessage::from(msg);
+                match msg.channel() {
+                    Some(channel) if !channels.contains(&channel) => continue,
+                    _ => msg,
+                }
+            }
+        };
+        if let Err(e) = socket
+            .send(WsMessage::Text(serde_json::to_string(&msg).unwrap()))
+            .await
+        {
+            println!("Could not send to WebSocket client; err = {:?}", e);
+            break;
+        }
+    }
+    session.disconnect().await;
+}
+
+/// Serves a FIX counterparty, translating its orders into commands for the
+/// order manager and the replies into execution reports
+async fn fix_loop(
+    to_server: Sender<ToOrderManager>,
+    socket: TcpStream,
+    config: Arc<SessionConfig>,
+    fix_config: Arc<FixConfig>,
+) {
+    let accounts = config.accounts.clone();
+    let (mut session, mut client_rx) = Session::connect(to_server, &socket, config).await;
+    // Requests before the order manager knows the client would be dropped
+    while session.client_id.is_none() {
+        match client_rx.recv().await {
+            Some(msg) => session.on_to_client(&msg),
+            None => return,
+        }
+    }
+    let mut fix = FixSession::new(
+        &fix_config.comp_id,
+        &fix_config.symbol,
+        &fix_config.store_dir,
+        accounts,
+        Instant::now(),
+    );
+    let mut socket = Framed::new(socket, FixCodec);
+    let mut timer = tokio::time::interval(Duration::from_secs(1));
+    'session: loop {
+        let actions = tokio::select! {
+            frame = socket.next() => match frame {
+                Some(Ok(message)) => fix.on_message(message, Instant::now()),
+                Some(Err(e)) => {
+                    println!("Invalid FIX message; err = {:?}", e);
+                    break;
+                }
+                None => break,
+            },
+            msg = client_rx.recv() => match msg {
+                Some(msg) => fix.on_to_client(msg, Instant::now()),
+                None => break,
+            },
+            _ = timer.tick() => fix.on_timer(Instant::now()),
+        };
+        let mut actions: VecDeque<Action> = match actions {
+            Ok(actions) => actions.into(),
+            Err(e) => {
+                println!("FIX session failed; err = {:?}", e);
+                break;
+            }
+        };
+        while let Some(action) = actions.pop_front() {
+            match action {
+                Action::Send(message) => {
+                    if let Err(e) = socket.send(message).await {
+                        println!("Could not send to FIX client; err = {:?}", e);
+                        break 'session;
                     }
-                    ToOrderManager::GetTopOfBook(client_id,side) => {
-                        if let Some(to_client) = clients.get(&client_id) {
-                            to_client.send(ToClient::TopOfBook(side,order_book.get_top_of_book(side).as_bigint_and_exponent()));
+                }
+                Action::Forward(msg) => {
+                    let login = matches!(msg, ToServer::Login(..));
+                    match session.handle(msg).await {
+                        // A throttled order is rejected like any other
+                        Ok(Some(reply)) if !login => {
+                            match fix.on_to_client(reply, Instant::now()) {
+                                Ok(more) => actions.extend(more),
+                                Err(e) => {
+                                    println!("FIX session failed; err = {:?}", e);
+                                    break 'session;
+                                }
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            println!("{}", e);
+                            break 'session;
                         }
                     }
-                    ToOrderManager::GetSizeForPriceLevel(client_id,side,price) => {
-                        if let Some(to_client) = clients.get(&client_id) {
-                            to_client.send(ToClient::SizeForPriceLevel(side,order_book.get_size_for_price_level(side, price)));
+                }
+                Action::Disconnect => break 'session,
+            }
+        }
+    }
+    session.disconnect().await;
+    // Stores the reports of the orders cancelled on disconnect, the
+    // counterparty asks for them with a resend request when it logs on again
+    while let Some(msg) = client_rx.recv().await {
+        if let Err(e) = fix.on_to_client(msg, Instant::now()) {
+            println!("Could not store FIX execution report; err = {:?}", e);
+            break;
+        }
+    }
+}
+
+/// Publishes book events in sequenced packets to the multicast group,
+/// with heartbeats carrying the next sequence number when idle
+async fn feed_loop(
+    mut events: Receiver<(u64, FeedEvent)>,
+    socket: UdpSocket,
+    group: SocketAddr,
+    state: Arc<Mutex<FeedState>>,
+) {
+    let session = state.lock().unwrap().session;
+    let mut heartbeat = tokio::time::interval(Duration::from_secs(1));
+    // First event of the next packet, after a gap of events that did not fit
+    // in the queue
+    let mut carried = None;
+    loop {
+        let first = match carried.take() {
+            Some(first) => Some(first),
+            None => tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => Some(event),
+                    None => break,
+                },
+                _ = heartbeat.tick() => None,
+            },
+        };
+        let packet = match first {
+            Some((sequence, event)) => {
+                let mut packet = itch::Packet {
+                    session,
+                    sequence,
+                    events: vec![event],
+                };
+                // Batch what is already waiting into the same packet
+                while packet.encoded_len() + 64 <= itch::MAX_PACKET {
+                    match events.try_recv() {
+                        Ok((next, event)) if next == sequence + packet.events.len() as u64 => {
+                            packet.events.push(event)
                         }
+                        Ok(event) => {
+                            carried = Some(event);
+                            break;
+                        }
+                        Err(_) => break,
                     }
                 }
+                packet
             }
-            _ = heartbeat.tick() => {
-                io::stdout().flush().unwrap();
-                print!("\rConnected clients: {:?}",clients.len());
+            None => itch::Packet {
+                session,
+                sequence: state.lock().unwrap().next_sequence,
+                events: vec![],
+            },
+        };
+        if let Err(e) = socket.send_to(&packet.encode(), group).await {
+            println!("Could not publish market data; err = {:?}", e);
+        }
+    }
+}
+
+/// Serves snapshots and retransmissions of the feed, every reply is a series
+/// of packets ended by an empty one
+async fn recovery_loop(socket: TcpStream, state: Arc<Mutex<FeedState>>) {
+    let mut socket = Framed::new(socket, LengthDelimitedCodec::new());
+    while let Some(Ok(frame)) = socket.next().await {
+        let (session, mut packets) = {
+            let state = state.lock().unwrap();
+            let packets = match RecoveryRequest::decode(&frame) {
+                Some(RecoveryRequest::Snapshot) => {
+                    let timestamp = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_nanos() as u64;
+                    state.snapshot(timestamp)
+                }
+                Some(RecoveryRequest::Retransmit { sequence, count }) => {
+                    state.retransmit(sequence, count)
+                }
+                None => {
+                    println!("Invalid recovery request");
+                    break;
+                }
+            };
+            ((state.session, state.next_sequence), packets)
+        };
+        packets.push(itch::Packet {
+            session: session.0,
+            sequence: session.1,
+            events: vec![],
+        });
+        for packet in packets {
+            if socket.send(packet.encode().into()).await.is_err() {
+                return;
             }
         }
     }
 }
-async fn client_loop(to_server: UnboundedSender<ToOrderManager>, mut socket: TcpStream) {
-    let (client_tx, mut client_rx) = mpsc::unbounded_channel();
-    let connect_msg = ToOrderManager::ClientConnected(client_tx);
-    if let Err(_) = to_server.send(connect_msg) {
-        println!("Could not connect to server");
+
+/// UDP socket sending multicast through the given interface, such as loopback
+fn multicast_socket(interface: Ipv4Addr) -> io::Result<UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::IPV4,
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+    socket.set_multicast_if_v4(&interface)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.bind(&SocketAddr::from((interface, 0)).into())?;
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Serves one admin connection, answering every command in turn
+async fn admin_loop(to_server: Sender<ToOrderManager>, socket: TcpStream) {
+    let mut socket = Framed::new(socket, LengthDelimitedCodec::new());
+    while let Some(Ok(frame)) = socket.next().await {
+        let command: AdminCommand = match bincode::deserialize(&frame) {
+            Ok(command) => command,
+            Err(e) => {
+                println!("Invalid admin command; err = {:?}", e);
+                continue;
+            }
+        };
+        let (reply_tx, reply_rx) = oneshot::channel();
+        if to_server
+            .send(ToOrderManager::Admin(command, reply_tx))
+            .await
+            .is_err()
+        {
+            break;
+        }
+        let reply = match reply_rx.await {
+            Ok(reply) => reply,
+            Err(_) => break,
+        };
+        if socket
+            .send(bincode::serialize(&reply).unwrap().into())
+            .await
+            .is_err()
+        {
+            break;
+        }
     }
-    let mut client_id: Option<ClientId> = None;
+}
+
+/// Serves the metrics in the Prometheus text format over HTTP
+async fn metrics_loop(to_server: Sender<ToOrderManager>, socket: TcpStream) {
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
     loop {
-        tokio::select! {
-            _ = socket.readable()=> {
-                let mut buf = [0; 1024];
-                let n = match socket.try_read(&mut buf){
-                    Ok(n) if n == 0 => break,
-                    Ok(n) => n,
-                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        continue;
-                    }
-                    Err(e) => {
-                        println!("Failed to read from socket; err = {:?}", e);
-                        break;
+        let request = match http::read_request(&mut reader).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = http::write_error(&mut writer, &e).await;
+                break;
+            }
+        };
+        let response = match (request.method.as_ref(), request.path.as_ref()) {
+            ("GET", "/metrics") => {
+                let (reply_tx, reply_rx) = oneshot::channel();
+                if to_server
+                    .send(ToOrderManager::GetMetrics(reply_tx))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                match reply_rx.await {
+                    Ok(metrics) => {
+                        http::write_response(
+                            &mut writer,
+                            "200 OK",
+                            "text/plain; version=0.0.4",
+                            metrics.as_bytes(),
+                        )
+                        .await
                     }
-                };
-                let to_server_msg: ToServer = bincode::deserialize_from(&buf[0..n]).unwrap();
-                match (to_server_msg,client_id) {
-                    (ToServer::GetBookDepth(side),Some(client_id)) => {
-                        to_server.send(ToOrderManager::GetOrderDepth(client_id,side));
-                    },
-                    (ToServer::PlaceOrder(side, (digits, scale), quantity),Some(client_id)) => {
-                        let price = BigDecimal::new(digits, scale);
-                        to_server.send(ToOrderManager::PlaceOrder(client_id, side, price, quantity));
+                    Err(_) => break,
+                }
+            }
+            _ => {
+                http::write_response(&mut writer, "404 Not Found", "text/plain", b"Not found").await
+            }
+        };
+        if response.is_err() {
+            break;
+        }
+    }
+}
 
-                    },
-                    (ToServer::GetTopOfBook(side),Some(client_id)) => {
-                        to_server.send(ToOrderManager::GetTopOfBook(client_id,side));
-                    },
-                    (ToServer::GetSizeForPriceLevel(side,(digits,scale)),Some(client_id)) => {
-                        to_server.send(ToOrderManager::GetSizeForPriceLevel(client_id,side,BigDecimal::new(digits,scale)));
-                    }
-                    _ => ()
-                };
+/// Serves the REST API of `server::rest` for the book of `symbol`
+///
+/// Every connection has the limits of a session and all of them share those
+/// of the account REST orders are placed for.
+async fn rest_loop(
+    to_server: Sender<ToOrderManager>,
+    socket: TcpStream,
+    symbol: String,
+    config: Arc<SessionConfig>,
+) {
+    let mut limits = SessionLimits::default();
+    let (reader, mut writer) = socket.into_split();
+    let mut reader = BufReader::new(reader);
+    loop {
+        let request = match http::read_request(&mut reader).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                let _ = http::write_error(&mut writer, &e).await;
+                break;
             }
-            Some(msg) = client_rx.recv() => {
-                match msg {
-                    ToClient::Connected(our_client_id) => client_id = Some(our_client_id),
-                    _ => ()
+        };
+        let reply = match rest::route(&request, &symbol) {
+            Ok(request) => {
+                let verdict = config.rate_limiter.check(
+                    &mut limits,
+                    REST_ACCOUNT,
+                    request.name(),
+                    Instant::now(),
+                );
+                if verdict != Verdict::Allow {
+                    let reply = RestReply::OrderRejected {
+                        reason: RejectReason::Throttled,
+                    };
+                    let body = serde_json::to_vec(&reply).unwrap();
+                    let _ = http::write_response(
+                        &mut writer,
+                        reply.status(),
+                        "application/json",
+                        &body,
+                    )
+                    .await;
+                    match verdict {
+                        Verdict::Disconnect => break,
+                        _ => continue,
+                    }
+                }
+                let (reply_tx, reply_rx) = oneshot::channel();
+                if to_server
+                    .send(ToOrderManager::Rest(request, reply_tx))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                match reply_rx.await {
+                    Ok(reply) => reply,
+                    Err(_) => break,
                 }
-                socket.write(&bincode::serialize(&msg).unwrap()).await.expect("Could not send to client");
             }
+            Err(reply) => reply,
+        };
+        let body = serde_json::to_vec(&reply).unwrap();
+        if http::write_response(&mut writer, reply.status(), "application/json", &body)
+            .await
+            .is_err()
+        {
+            break;
         }
     }
-    if let Some(client_id) = client_id {
-        to_server.send(ToOrderManager::ClientDisconnected(client_id));
+}
+
+/// Reads trading status commands typed into the server terminal
+async fn console_loop(to_server: Sender<ToOrderManager>) {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match line.trim().parse::<TradingStatus>() {
+            Ok(status) => {
+                let _ = to_server
+                    .send(ToOrderManager::SetTradingStatus(status))
+                    .await;
+            }
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+/// Walks through the scheduled statuses, the last one lasts until changed from the console
+async fn schedule_loop(
+    to_server: Sender<ToOrderManager>,
+    schedule: Vec<(TradingStatus, Option<Duration>)>,
+) {
+    for (status, duration) in schedule {
+        let _ = to_server
+            .send(ToOrderManager::SetTradingStatus(status))
+            .await;
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => break,
+        }
     }
 }
 
+/// Parses a schedule such as `auction:30,open:600,auction:60,closed`
+/// where the number is the duration of the status in seconds
+fn parse_schedule(schedule: &str) -> Result<Vec<(TradingStatus, Option<Duration>)>, String> {
+    schedule
+        .split(',')
+        .map(|entry| {
+            let mut parts = entry.trim().splitn(2, ':');
+            let status = parts.next().unwrap_or_default().parse::<TradingStatus>()?;
+            let duration = parts
+                .next()
+                .map(|secs| secs.parse::<u64>().map(Duration::from_secs))
+                .transpose()
+                .map_err(|err| format!("Invalid status duration in {}: {}", entry, err))?;
+            Ok((status, duration))
+        })
+        .collect()
+}
+
+fn parse_arg<T: FromStr>(args: &ArgMatches, name: &str) -> io::Result<Option<T>> {
+    args.value_of(name)
+        .map(|value| {
+            value.parse::<T>().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid value {} for {}", value, name),
+                )
+            })
+        })
+        .transpose()
+}
+
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:8080").await?;
-    let (server_tx, server_rx) = mpsc::unbounded_channel::<ToOrderManager>();
-    task::spawn(server_loop(server_rx));
+    let args = App::new("server")
+        .arg(
+            Arg::new("schedule")
+                .long("schedule")
+                .takes_value(true)
+                .help("Trading statuses with durations in seconds, e.g. auction:30,open"),
+        )
+        .arg(
+            Arg::new("price-band")
+                .long("price-band")
+                .takes_value(true)
+                .help("Reject orders more than this percentage from the reference price"),
+        )
+        .arg(
+            Arg::new("reference-price")
+                .long("reference-price")
+                .takes_value(true)
+                .help("Initial reference price of the price band, later set by auctions"),
+        )
+        .arg(
+            Arg::new("volatility-halt")
+                .long("volatility-halt")
+                .takes_value(true)
+                .help("Halt trading when trades move more than this percentage"),
+        )
+        .arg(
+            Arg::new("volatility-window")
+                .long("volatility-window")
+                .takes_value(true)
+                .default_value("60")
+                .help("Seconds of trades considered by the volatility halt"),
+        )
+        .arg(
+            Arg::new("halt-duration")
+                .long("halt-duration")
+                .takes_value(true)
+                .default_value("60")
+                .help("Seconds a volatility halt lasts"),
+        )
+        .arg(
+            Arg::new("websocket-addr")
+                .long("websocket-addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:8082")
+                .help("Address of the WebSocket gateway speaking JSON"),
+        )
+        .arg(
+            Arg::new("rest-addr")
+                .long("rest-addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:8083")
+                .help("Address of the REST API"),
+        )
+        .arg(
+            Arg::new("fix-addr")
+                .long("fix-addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:8084")
+                .help("Address of the FIX 4.4 order entry gateway"),
+        )
+        .arg(
+            Arg::new("fix-comp-id")
+                .long("fix-comp-id")
+                .takes_value(true)
+                .default_value("LOB")
+                .help("SenderCompID of the server, counterparties must send it as TargetCompID"),
+        )
+        .arg(
+            Arg::new("fix-store")
+                .long("fix-store")
+                .takes_value(true)
+                .default_value("fix_store")
+                .help("Directory keeping sequence numbers and sent messages of FIX sessions"),
+        )
+        .arg(
+            Arg::new("feed-group")
+                .long("feed-group")
+                .takes_value(true)
+                .default_value("239.1.1.1:30001")
+                .help("Multicast group and port of the market data feed"),
+        )
+        .arg(
+            Arg::new("feed-interface")
+                .long("feed-interface")
+                .takes_value(true)
+                .default_value("127.0.0.1")
+                .help("Address of the interface the market data feed is sent on"),
+        )
+        .arg(
+            Arg::new("feed-history")
+                .long("feed-history")
+                .takes_value(true)
+                .default_value("100000")
+                .help("Market data messages kept for retransmission"),
+        )
+        .arg(
+            Arg::new("feed-queue")
+                .long("feed-queue")
+                .takes_value(true)
+                .default_value("10000")
+                .help("Market data messages waiting to be sent, receivers recover the rest"),
+        )
+        .arg(
+            Arg::new("recovery-addr")
+                .long("recovery-addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:8085")
+                .help("Address of the market data snapshot and retransmission service"),
+        )
+        .arg(
+            Arg::new("symbol")
+                .long("symbol")
+                .takes_value(true)
+                .default_value("LOB")
+                .help("Symbol of the book in REST paths"),
+        )
+        .arg(
+            Arg::new("lobster")
+                .long("lobster")
+                .takes_value(true)
+                .help("Exports the session to {prefix}_message.csv and {prefix}_orderbook.csv"),
+        )
+        .arg(
+            Arg::new("lobster-levels")
+                .long("lobster-levels")
+                .takes_value(true)
+                .default_value("10")
+                .help("Levels of each side in the exported LOBSTER orderbook file"),
+        )
+        .arg(
+            Arg::new("journal")
+                .long("journal")
+                .takes_value(true)
+                .help("Writes every event of the book to this file, one JSON object per line"),
+        )
+        .arg(
+            Arg::new("bars")
+                .long("bars")
+                .takes_value(true)
+                .default_value("1s,1m,5m")
+                .help("Bars aggregated from trades, e.g. 1s,1m,5m,100t,500v for time, tick and volume bars"),
+        )
+        .arg(
+            Arg::new("session-report")
+                .long("session-report")
+                .takes_value(true)
+                .help("Writes statistics of the session to this file when trading closes"),
+        )
+        .arg(
+            Arg::new("quote-min-size")
+                .long("quote-min-size")
+                .takes_value(true)
+                .default_value("1")
+                .help("Quantity on each side an account needs to count as quoting"),
+        )
+        .arg(
+            Arg::new("metrics-addr")
+                .long("metrics-addr")
+                .takes_value(true)
+                .default_value("127.0.0.1:9100")
+                .help("Address of the Prometheus metrics endpoint"),
+        )
+        .arg(
+            Arg::new("channel-capacity")
+                .long("channel-capacity")
+                .takes_value(true)
+                .default_value("10000")
+                .help("Messages queued for the order manager before sessions wait"),
+        )
+        .arg(
+            Arg::new("client-queue")
+                .long("client-queue")
+                .takes_value(true)
+                .default_value("1000")
+                .help("Messages queued for each client before it counts as slow"),
+        )
+        .arg(
+            Arg::new("slow-consumer-policy")
+                .long("slow-consumer-policy")
+                .takes_value(true)
+                .default_value("resync")
+                .help("Default handling of slow clients: resync, conflate or disconnect"),
+        )
+        .arg(
+            Arg::new("session-rate-limit")
+                .long("session-rate-limit")
+                .takes_value(true)
+                .help("Messages per second of each session, e.g. place_order=50,default=200"),
+        )
+        .arg(
+            Arg::new("account-rate-limit")
+                .long("account-rate-limit")
+                .takes_value(true)
+                .help("Messages per second of all sessions of an account, e.g. place_order=100"),
+        )
+        .arg(
+            Arg::new("accounts")
+                .long("accounts")
+                .takes_value(true)
+                .help("File of account and password pairs, one per line, checked on login"),
+        )
+        .arg(
+            Arg::new("abuse-threshold")
+                .long("abuse-threshold")
+                .takes_value(true)
+                .default_value("100")
+                .help("Throttled messages within the abuse window that disconnect a session"),
+        )
+        .arg(
+            Arg::new("abuse-window")
+                .long("abuse-window")
+                .takes_value(true)
+                .default_value("10")
+                .help("Seconds of throttled messages counted towards the abuse threshold"),
+        )
+        .get_matches();
+    let schedule = args
+        .value_of("schedule")
+        .map(parse_schedule)
+        .transpose()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let reference = parse_arg::<BigDecimal>(&args, "reference-price")?;
+    let price_band = parse_arg::<BigDecimal>(&args, "price-band")?
+        .map(|percent| PriceBand { percent, reference });
+    let volatility_window = parse_arg::<u64>(&args, "volatility-window")?.unwrap_or_default();
+    let volatility_guard = parse_arg::<BigDecimal>(&args, "volatility-halt")?
+        .map(|percent| VolatilityGuard::new(percent, Duration::from_secs(volatility_window)));
+    let halt_duration = parse_arg::<u64>(&args, "halt-duration")?.unwrap_or_default();
+    let policy = parse_arg::<SlowConsumerPolicy>(&args, "slow-consumer-policy")?
+        .unwrap_or(SlowConsumerPolicy::Resync);
+    let client_queue = parse_arg::<usize>(&args, "client-queue")?.unwrap_or_default();
+    let channel_capacity = parse_arg::<usize>(&args, "channel-capacity")?.unwrap_or_default();
+    if client_queue == 0 || channel_capacity == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Queue capacities must be at least 1",
+        ));
+    }
+    let rate_limiter = Arc::new(RateLimiter::new(
+        parse_arg::<Limits>(&args, "session-rate-limit")?.unwrap_or_default(),
+        parse_arg::<Limits>(&args, "account-rate-limit")?.unwrap_or_default(),
+        parse_arg::<usize>(&args, "abuse-threshold")?.unwrap_or_default(),
+        Duration::from_secs(parse_arg::<u64>(&args, "abuse-window")?.unwrap_or_default()),
+    ));
+    let session_config = Arc::new(SessionConfig {
+        queue_capacity: client_queue,
+        rate_limiter: rate_limiter.clone(),
+        accounts: Arc::new(match args.value_of("accounts") {
+            Some(path) => Accounts::load(Path::new(path))?,
+            None => Accounts::default(),
+        }),
+    });
+    let feed_queue = parse_arg::<usize>(&args, "feed-queue")?.unwrap_or_default();
+    if feed_queue == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Feed queue must hold at least 1 event",
+        ));
+    }
+    let (feed_tx, feed_rx) = mpsc::channel(feed_queue);
+    let feed_state = Arc::new(Mutex::new(FeedState::new(
+        itch::session_name(args.value_of("symbol").unwrap()),
+        parse_arg::<usize>(&args, "feed-history")?.unwrap_or_default(),
+    )));
+    let lobster_levels = parse_arg::<usize>(&args, "lobster-levels")?.unwrap_or_default();
+    let lobster = match args.value_of("lobster") {
+        Some(prefix) => Some(LobsterWriter::new(
+            BufWriter::new(File::create(format!("{}_message.csv", prefix))?),
+            BufWriter::new(File::create(format!("{}_orderbook.csv", prefix))?),
+            lobster_levels,
+        )),
+        None => None,
+    };
+    let journal = match args.value_of("journal") {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let bar_kinds = args
+        .value_of("bars")
+        .unwrap()
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<BarKind>, _>>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let manager = OrderManager::new(
+        price_band,
+        volatility_guard,
+        Duration::from_secs(halt_duration),
+        policy,
+        rate_limiter.clone(),
+        Feed {
+            state: feed_state.clone(),
+            events: feed_tx,
+        },
+        lobster,
+    )
+    .with_bars(&bar_kinds)
+    .with_journal(journal)
+    .with_session_report(
+        args.value_of("session-report").map(PathBuf::from),
+        parse_arg::<Quantity>(&args, "quote-min-size")?.unwrap_or_default(),
+    );
+
+    let listener = TcpListener::bind(CLIENT_ADDR).await?;
+    let admin_listener = TcpListener::bind(ADMIN_ADDR).await?;
+    let metrics_listener = TcpListener::bind(args.value_of("metrics-addr").unwrap()).await?;
+    let websocket_listener = TcpListener::bind(args.value_of("websocket-addr").unwrap()).await?;
+    let rest_listener = TcpListener::bind(args.value_of("rest-addr").unwrap()).await?;
+    let symbol = args.value_of("symbol").unwrap().to_string();
+    let fix_listener = TcpListener::bind(args.value_of("fix-addr").unwrap()).await?;
+    let recovery_listener = TcpListener::bind(args.value_of("recovery-addr").unwrap()).await?;
+    let feed_group = parse_arg::<SocketAddr>(&args, "feed-group")?.unwrap();
+    let feed_socket = multicast_socket(parse_arg::<Ipv4Addr>(&args, "feed-interface")?.unwrap())?;
+    let fix_config = Arc::new(FixConfig {
+        comp_id: args.value_of("fix-comp-id").unwrap().to_string(),
+        symbol: symbol.clone(),
+        store_dir: PathBuf::from(args.value_of("fix-store").unwrap()),
+    });
+    let (server_tx, server_rx) = mpsc::channel::<ToOrderManager>(channel_capacity);
+    task::spawn(server_loop(server_rx, manager));
+    task::spawn(feed_loop(
+        feed_rx,
+        feed_socket,
+        feed_group,
+        feed_state.clone(),
+    ));
+    task::spawn(console_loop(server_tx.clone()));
+    if let Some(schedule) = schedule {
+        task::spawn(schedule_loop(server_tx.clone(), schedule));
+    }
     loop {
-        let (socket, _) = listener.accept().await?;
-        task::spawn(client_loop(server_tx.clone(), socket));
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(client_loop(server_tx.clone(), socket, session_config.clone()));
+            }
+            accepted = admin_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(admin_loop(server_tx.clone(), socket));
+            }
+            accepted = websocket_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(websocket_loop(server_tx.clone(), socket, session_config.clone()));
+            }
+            accepted = rest_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(rest_loop(server_tx.clone(), socket, symbol.clone(), session_config.clone()));
+            }
+            accepted = fix_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(fix_loop(server_tx.clone(), socket, session_config.clone(), fix_config.clone()));
+            }
+            accepted = recovery_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(recovery_loop(socket, feed_state.clone()));
+            }
+            accepted = metrics_listener.accept() => {
+                let (socket, _) = accepted?;
+                task::spawn(metrics_loop(server_tx.clone(), socket));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc::channel;
+
+    fn depth(price: i64, quantity: Quantity) -> ToClient {
+        ToClient::LatestDepth(Side::Bid, quantity, (BigInt::from(price), 0))
+    }
+
+    fn received(from_client: &mut Receiver<ToClient>) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Ok(msg) = from_client.try_recv() {
+            messages.push(format!("{:?}", msg));
+        }
+        messages
+    }
+
+    fn snapshot() -> ToClient {
+        ToClient::Snapshot(Vec::new(), Vec::new())
+    }
+
+    #[test]
+    fn resync_after_drops() {
+        let (to_client, mut from_client) = channel(1);
+        let mut session = ClientSession::new(to_client, SlowConsumerPolicy::Resync);
+        assert!(matches!(session.deliver(depth(100, 1)), Delivery::Sent));
+        assert!(matches!(session.deliver(depth(101, 2)), Delivery::Dropped));
+        assert_eq!(received(&mut from_client).len(), 1);
+        // Nothing more is sent until the client had its snapshot
+        assert!(matches!(session.deliver(depth(102, 3)), Delivery::Dropped));
+        assert!(session.flush(&mut snapshot));
+        assert_eq!(
+            received(&mut from_client),
+            vec![format!("{:?}", snapshot())]
+        );
+        assert!(!session.flush(&mut snapshot));
+        assert!(matches!(session.deliver(depth(102, 3)), Delivery::Sent));
+    }
+
+    #[test]
+    fn conflate_depth() {
+        let (to_client, mut from_client) = channel(1);
+        let mut session = ClientSession::new(to_client, SlowConsumerPolicy::Conflate);
+        assert!(matches!(session.deliver(depth(100, 1)), Delivery::Sent));
+        assert!(matches!(
+            session.deliver(depth(101, 2)),
+            Delivery::Conflated
+        ));
+        assert!(matches!(
+            session.deliver(depth(101, 5)),
+            Delivery::Conflated
+        ));
+        assert!(matches!(
+            session.deliver(ToClient::BookChecksum(7)),
+            Delivery::Dropped
+        ));
+        assert_eq!(received(&mut from_client).len(), 1);
+        assert!(!session.flush(&mut snapshot));
+        assert_eq!(
+            received(&mut from_client),
+            vec![format!("{:?}", depth(101, 5))]
+        );
+    }
+
+    #[test]
+    fn disconnect_slow_consumer() {
+        let (to_client, _from_client) = channel(1);
+        let mut session = ClientSession::new(to_client, SlowConsumerPolicy::Disconnect);
+        assert!(matches!(session.deliver(depth(100, 1)), Delivery::Sent));
+        assert!(matches!(
+            session.deliver(depth(101, 2)),
+            Delivery::Disconnect
+        ));
+        assert!(matches!(
+            session.deliver(ToClient::OrderCancelled(1)),
+            Delivery::Disconnect
+        ));
+    }
+
+    #[test]
+    fn held_replies() {
+        let (to_client, mut from_client) = channel(2);
+        let mut session = ClientSession::new(to_client, SlowConsumerPolicy::Resync);
+        assert!(matches!(session.deliver(depth(100, 1)), Delivery::Sent));
+        assert!(matches!(
+            session.deliver(ToClient::OrderAccepted(1)),
+            Delivery::Sent
+        ));
+        assert!(matches!(
+            session.deliver(ToClient::OrderCancelled(1)),
+            Delivery::Held
+        ));
+        // Market data doesn't overtake the held reply
+        assert!(matches!(session.deliver(depth(101, 2)), Delivery::Dropped));
+        assert!(matches!(
+            session.deliver(ToClient::OrderAccepted(2)),
+            Delivery::Held
+        ));
+        // A client that lets its whole queue of replies pile up is cut off
+        assert!(matches!(
+            session.deliver(ToClient::OrderCancelled(2)),
+            Delivery::Disconnect
+        ));
+
+        assert_eq!(received(&mut from_client).len(), 2);
+        assert!(!session.flush(&mut snapshot));
+        assert_eq!(
+            received(&mut from_client),
+            vec![
+                format!("{:?}", ToClient::OrderCancelled(1)),
+                format!("{:?}", ToClient::OrderAccepted(2)),
+            ]
+        );
+        // The snapshot owed for the dropped depth follows the replies
+        assert!(session.flush(&mut snapshot));
+        assert_eq!(
+            received(&mut from_client),
+            vec![format!("{:?}", snapshot())]
+        );
+    }
+
+    #[test]
+    fn set_policy_resyncs_conflated_depth() {
+        let (to_client, mut from_client) = channel(1);
+        let mut session = ClientSession::new(to_client, SlowConsumerPolicy::Conflate);
+        assert!(matches!(session.deliver(depth(100, 1)), Delivery::Sent));
+        assert!(matches!(
+            session.deliver(depth(101, 2)),
+            Delivery::Conflated
+        ));
+        session.set_policy(SlowConsumerPolicy::Disconnect);
+        assert_eq!(received(&mut from_client).len(), 1);
+        assert!(session.flush(&mut snapshot));
+        assert_eq!(
+            received(&mut from_client),
+            vec![format!("{:?}", snapshot())]
+        );
+
+        session.set_policy(SlowConsumerPolicy::Resync);
+        assert!(!session.needs_snapshot);
     }
 }
@@ -0,0 +1,161 @@
+//! Metrics of the order manager in the Prometheus text format
+
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// Upper bounds in seconds of the latency histogram buckets
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// Cumulative histogram of durations
+#[derive(Default)]
+pub struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if seconds <= *le {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Counters and histograms updated while processing messages
+#[derive(Default)]
+pub struct Metrics {
+    pub orders_placed: u64,
+    pub orders_cancelled: u64,
+    pub orders_expired: u64,
+    /// Rejected orders per reason
+    pub orders_rejected: BTreeMap<String, u64>,
+    pub trades: u64,
+    pub traded_quantity: u64,
+    /// Processed messages per type
+    pub messages: BTreeMap<&'static str, u64>,
+    /// Messages not sent to slow clients
+    pub dropped_messages: u64,
+    /// Depth updates held back for slow clients and replaced by newer ones
+    pub conflated_messages: u64,
+    pub slow_client_resyncs: u64,
+    pub slow_client_disconnects: u64,
+    /// Feed events that did not fit in the queue of the feed
+    pub feed_overflows: u64,
+    /// Messages waiting for the order manager after the last one was processed
+    pub queue_length: usize,
+    pub command_latency: Histogram,
+}
+
+/// Writes metrics in the Prometheus text exposition format
+#[derive(Default)]
+pub struct Encoder {
+    out: String,
+}
+
+impl Encoder {
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) {
+        self.header(name, help, "counter");
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    /// Counter with one sample per value of the label
+    pub fn labelled_counter<'a, K: AsRef<str> + 'a>(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        values: impl IntoIterator<Item = (&'a K, &'a u64)>,
+    ) {
+        self.header(name, help, "counter");
+        for (key, value) in values {
+            let _ = writeln!(
+                self.out,
+                "{}{{{}=\"{}\"}} {}",
+                name,
+                label,
+                key.as_ref(),
+                value
+            );
+        }
+    }
+
+    pub fn gauge(&mut self, name: &str, help: &str, value: usize) {
+        self.header(name, help, "gauge");
+        let _ = writeln!(self.out, "{} {}", name, value);
+    }
+
+    /// Gauge with one sample per value of the label
+    pub fn labelled_gauge(
+        &mut self,
+        name: &str,
+        help: &str,
+        label: &str,
+        values: &[(&str, usize)],
+    ) {
+        self.header(name, help, "gauge");
+        for (key, value) in values {
+            let _ = writeln!(self.out, "{}{{{}=\"{}\"}} {}", name, label, key, value);
+        }
+    }
+
+    pub fn histogram(&mut self, name: &str, help: &str, histogram: &Histogram) {
+        self.header(name, help, "histogram");
+        for (bucket, le) in histogram.buckets.iter().zip(LATENCY_BUCKETS.iter()) {
+            let _ = writeln!(self.out, "{}_bucket{{le=\"{}\"}} {}", name, le, bucket);
+        }
+        let _ = writeln!(
+            self.out,
+            "{}_bucket{{le=\"+Inf\"}} {}",
+            name, histogram.count
+        );
+        let _ = writeln!(self.out, "{}_sum {}", name, histogram.sum);
+        let _ = writeln!(self.out, "{}_count {}", name, histogram.count);
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_micros(3));
+        histogram.observe(Duration::from_millis(2));
+        let mut encoder = Encoder::default();
+        encoder.histogram("latency", "Latency", &histogram);
+        let text = encoder.finish();
+        assert!(text.contains("latency_bucket{le=\"0.000001\"} 0\n"));
+        assert!(text.contains("latency_bucket{le=\"0.000005\"} 1\n"));
+        assert!(text.contains("latency_bucket{le=\"0.005\"} 2\n"));
+        assert!(text.contains("latency_bucket{le=\"+Inf\"} 2\n"));
+        assert!(text.contains("latency_count 2\n"));
+    }
+
+    #[test]
+    fn labelled_counter() {
+        let mut messages = BTreeMap::new();
+        messages.insert("place_order", 3);
+        let mut encoder = Encoder::default();
+        encoder.labelled_counter("messages_total", "Messages", "type", &messages);
+        assert!(encoder
+            .finish()
+            .ends_with("messages_total{type=\"place_order\"} 3\n"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Token bucket rate limits on the messages of client sessions
+
+use std::{
+    collections::{HashMap, VecDeque},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Accounts whose buckets are all full are forgotten this often
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Refills at `rate` tokens per second up to one second worth of tokens, but
+/// at least one token so that rates below one per second allow messages
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, now: Instant) -> Self {
+        let capacity = rate.max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Adds the tokens earned since the last refill, returns true if there
+    /// is one to take
+    fn refill(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+        self.tokens >= 1.0
+    }
+
+    pub fn try_take(&mut self, now: Instant) -> bool {
+        let ready = self.refill(now);
+        if ready {
+            self.tokens -= 1.0;
+        }
+        ready
+    }
+
+    /// A full bucket is no different from a new one
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+/// Messages per second allowed per message type, such as
+/// `place_order=50,default=200` where `default` covers the other types
+#[derive(Debug, Clone, Default)]
+pub struct Limits(HashMap<String, f64>);
+
+impl Limits {
+    fn rate(&self, message_type: &str) -> Option<f64> {
+        self.0
+            .get(message_type)
+            .or_else(|| self.0.get("default"))
+            .copied()
+    }
+}
+
+impl FromStr for Limits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|entry| {
+                let (message_type, rate) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("Expected type=rate in {}", entry))?;
+                let rate = rate
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|err| format!("Invalid rate in {}: {}", entry, err))?;
+                if !(rate.is_finite() && rate > 0.0) {
+                    return Err(format!("Rate must be above zero in {}", entry));
+                }
+                Ok((message_type.trim().to_string(), rate))
+            })
+            .collect::<Result<_, _>>()
+            .map(Limits)
+    }
+}
+
+/// What to do with a message from a client
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    Throttle,
+    Disconnect,
+}
+
+/// Buckets and recent rejections of one session
+#[derive(Default)]
+pub struct SessionLimits {
+    buckets: HashMap<&'static str, TokenBucket>,
+    rejections: VecDeque<Instant>,
+}
+
+/// Buckets of the accounts and when idle ones were last forgotten
+struct AccountBuckets {
+    buckets: HashMap<String, HashMap<&'static str, TokenBucket>>,
+    swept: Instant,
+}
+
+/// Shared by all sessions to enforce per session and per account limits
+pub struct RateLimiter {
+    session_limits: Limits,
+    account_limits: Limits,
+    accounts: Mutex<AccountBuckets>,
+    /// Throttled messages within `abuse_window` that get a session disconnected
+    abuse_threshold: usize,
+    abuse_window: Duration,
+    pub throttled: AtomicU64,
+    pub abuse_disconnects: AtomicU64,
+}
+
+/// Bucket of a message type, `None` if the type is not limited
+fn bucket<'a>(
+    buckets: &'a mut HashMap<&'static str, TokenBucket>,
+    limits: &Limits,
+    message_type: &'static str,
+    now: Instant,
+) -> Option<&'a mut TokenBucket> {
+    let rate = limits.rate(message_type)?;
+    Some(
+        buckets
+            .entry(message_type)
+            .or_insert_with(|| TokenBucket::new(rate, now)),
+    )
+}
+
+impl RateLimiter {
+    pub fn new(
+        session_limits: Limits,
+        account_limits: Limits,
+        abuse_threshold: usize,
+        abuse_window: Duration,
+    ) -> Self {
+        RateLimiter {
+            session_limits,
+            account_limits,
+            accounts: Mutex::new(AccountBuckets {
+                buckets: HashMap::new(),
+                swept: Instant::now(),
+            }),
+            abuse_threshold,
+            abuse_window,
+            throttled: AtomicU64::new(0),
+            abuse_disconnects: AtomicU64::new(0),
+        }
+    }
+
+    pub fn check(
+        &self,
+        session: &mut SessionLimits,
+        account: &str,
+        message_type: &'static str,
+        now: Instant,
+    ) -> Verdict {
+        let mut accounts = self.accounts.lock().unwrap();
+        if now.saturating_duration_since(accounts.swept) >= SWEEP_INTERVAL {
+            accounts
+                .buckets
+                .retain(|_, buckets| !buckets.values_mut().all(|bucket| bucket.is_full(now)));
+            accounts.swept = now;
+        }
+        // A message spends a token of both buckets or of none
+        let mut buckets = [
+            bucket(
+                &mut session.buckets,
+                &self.session_limits,
+                message_type,
+                now,
+            ),
+            bucket(
+                accounts.buckets.entry(account.to_string()).or_default(),
+                &self.account_limits,
+                message_type,
+                now,
+            ),
+        ];
+        let allowed = buckets
+            .iter_mut()
+            .flatten()
+            .all(|bucket| bucket.refill(now));
+        if allowed {
+            for bucket in buckets.iter_mut().flatten() {
+                bucket.try_take(now);
+            }
+            return Verdict::Allow;
+        }
+        drop(accounts);
+
+        self.throttled.fetch_add(1, Ordering::Relaxed);
+        while let Some(rejected) = session.rejections.front() {
+            if now.saturating_duration_since(*rejected) <= self.abuse_window {
+                break;
+            }
+            session.rejections.pop_front();
+        }
+        session.rejections.push_back(now);
+        if session.rejections.len() > self.abuse_threshold {
+            self.abuse_disconnects.fetch_add(1, Ordering::Relaxed);
+            Verdict::Disconnect
+        } else {
+            Verdict::Throttle
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_refills() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, start);
+        assert!(bucket.try_take(start));
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start));
+        assert!(bucket.try_take(start + Duration::from_millis(500)));
+        assert!(!bucket.try_take(start + Duration::from_millis(500)));
+
+        // Below one message per second a message is still allowed
+        let mut bucket = TokenBucket::new(0.5, start);
+        assert!(bucket.try_take(start));
+        assert!(!bucket.try_take(start + Duration::from_secs(1)));
+        assert!(bucket.try_take(start + Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn limits_per_type_with_default() {
+        let limits: Limits = "place_order=5, default=20".parse().unwrap();
+        assert_eq!(limits.rate("place_order"), Some(5.0));
+        assert_eq!(limits.rate("get_top_of_book"), Some(20.0));
+        assert_eq!(Limits::default().rate("place_order"), None);
+        assert!("place_order".parse::<Limits>().is_err());
+        for rate in &["0", "-1", "NaN", "inf"] {
+            assert!(format!("place_order={}", rate).parse::<Limits>().is_err());
+        }
+    }
+
+    #[test]
+    fn account_limit_is_shared_between_sessions() {
+        let limiter = RateLimiter::new(
+            Limits::default(),
+            "place_order=1".parse().unwrap(),
+            10,
+            Duration::from_secs(10),
+        );
+        let now = Instant::now();
+        let mut first = SessionLimits::default();
+        let mut second = SessionLimits::default();
+        assert_eq!(
+            limiter.check(&mut first, "a", "place_order", now),
+            Verdict::Allow
+        );
+        assert_eq!(
+            limiter.check(&mut second, "a", "place_order", now),
+            Verdict::Throttle
+        );
+        assert_eq!(
+            limiter.check(&mut second, "b", "place_order", now),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn throttled_message_spends_no_token() {
+        let limiter = RateLimiter::new(
+            "place_order=2".parse().unwrap(),
+            "place_order=1".parse().unwrap(),
+            10,
+            Duration::from_secs(10),
+        );
+        let now = Instant::now();
+        let mut session = SessionLimits::default();
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Allow
+        );
+        // Throttled by the account, so the session keeps its second token
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Throttle
+        );
+        assert_eq!(
+            limiter.check(&mut session, "b", "place_order", now),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn sustained_abuse_disconnects() {
+        let limiter = RateLimiter::new(
+            "default=1".parse().unwrap(),
+            Limits::default(),
+            2,
+            Duration::from_secs(10),
+        );
+        let now = Instant::now();
+        let mut session = SessionLimits::default();
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Allow
+        );
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Throttle
+        );
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Throttle
+        );
+        assert_eq!(
+            limiter.check(&mut session, "a", "place_order", now),
+            Verdict::Disconnect
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Routes of the REST API for scripts and notebooks
+//!
+//! ```text
+//! GET    /book/{symbol}?depth=N   price levels of both sides, all of them without depth
+//! GET    /top/{symbol}            best price and size of both sides
+//! POST   /orders                  {"side": "Bid", "price": "99.5", "quantity": 10}
+//! DELETE /orders/{id}             cancel an order placed over REST
+//! GET    /trades?limit=N          latest trades, newest first
+//! ```
+//! Prices are decimal strings and orders take an optional `time_in_force` like
+//! in the JSON protocol of the WebSocket port.
+
+use crate::{http::Request, json::JsonLevel, OrderId, Price, Quantity, RejectReason};
+use engine::{Side, TimeInForce};
+use serde::{Deserialize, Serialize};
+
+/// Trades returned by `GET /trades` without a limit
+pub const DEFAULT_TRADES: usize = 100;
+
+/// Body of `POST /orders`
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct NewOrder {
+    pub side: Side,
+    pub price: Price,
+    pub quantity: Quantity,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+}
+
+/// A request the order manager answers
+#[derive(Debug, PartialEq)]
+pub enum RestRequest {
+    Book { depth: Option<usize> },
+    Top,
+    PlaceOrder(NewOrder),
+    CancelOrder(OrderId),
+    Trades { limit: usize },
+}
+
+impl RestRequest {
+    /// Name of the message type used for rate limits
+    pub fn name(&self) -> &'static str {
+        match self {
+            RestRequest::Book { .. } => "get_book",
+            RestRequest::Top => "get_top_of_book",
+            RestRequest::PlaceOrder(_) => "place_order",
+            RestRequest::CancelOrder(_) => "cancel_order",
+            RestRequest::Trades { .. } => "get_trades",
+        }
+    }
+}
+
+/// JSON body of a response
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum RestReply {
+    Book {
+        bid_levels: usize,
+        ask_levels: usize,
+        bids: Vec<JsonLevel>,
+        asks: Vec<JsonLevel>,
+    },
+    Top {
+        bid: Option<JsonLevel>,
+        ask: Option<JsonLevel>,
+    },
+    /// The accepted order with the trades it executed right away
+    OrderPlaced {
+        order_id: OrderId,
+        trades: Vec<JsonLevel>,
+    },
+    OrderRejected {
+        reason: RejectReason,
+    },
+    OrderCancelled {
+        order_id: OrderId,
+    },
+    Trades {
+        trades: Vec<JsonLevel>,
+    },
+    NotFound {
+        error: String,
+    },
+    BadRequest {
+        error: String,
+    },
+}
+
+impl RestReply {
+    pub fn status(&self) -> &'static str {
+        match self {
+            RestReply::OrderPlaced { .. } => "201 Created",
+            RestReply::OrderRejected {
+                reason: RejectReason::Throttled,
+            } => "429 Too Many Requests",
+            RestReply::OrderRejected { .. } => "422 Unprocessable Entity",
+            RestReply::NotFound { .. } => "404 Not Found",
+            RestReply::BadRequest { .. } => "400 Bad Request",
+            _ => "200 OK",
+        }
+    }
+}
+
+fn not_found(path: &str) -> RestReply {
+    RestReply::NotFound {
+        error: format!("No route for {}", path),
+    }
+}
+
+fn query_number(request: &Request, name: &str) -> Result<Option<usize>, RestReply> {
+    request
+        .query
+        .get(name)
+        .map(|value| {
+            value.parse().map_err(|_| RestReply::BadRequest {
+                error: format!("Invalid {} {}", name, value),
+            })
+        })
+        .transpose()
+}
+
+/// Maps a request to what the order manager should answer, or to the error
+/// reply if the request is invalid
+pub fn route(request: &Request, symbol: &str) -> Result<RestRequest, RestReply> {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (request.method.as_ref(), segments.as_slice()) {
+        ("GET", ["book", book]) | ("GET", ["top", book]) if *book != symbol => {
+            Err(RestReply::NotFound {
+                error: format!("Unknown symbol {}", book),
+            })
+        }
+        ("GET", ["book", _]) => Ok(RestRequest::Book {
+            depth: query_number(request, "depth")?,
+        }),
+        ("GET", ["top", _]) => Ok(RestRequest::Top),
+        ("POST", ["orders"]) => serde_json::from_slice(&request.body)
+            .map(RestRequest::PlaceOrder)
+            .map_err(|e| RestReply::BadRequest {
+                error: e.to_string(),
+            }),
+        ("DELETE", ["orders", order_id]) => order_id
+            .parse()
+            .map(RestRequest::CancelOrder)
+            .map_err(|_| not_found(&request.path)),
+        ("GET", ["trades"]) => Ok(RestRequest::Trades {
+            limit: query_number(request, "limit")?.unwrap_or(DEFAULT_TRADES),
+        }),
+        _ => Err(not_found(&request.path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bigdecimal::BigDecimal;
+    use std::{collections::HashMap, str::FromStr};
+
+    fn request(method: &str, path: &str, query: &[(&str, &str)], body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn route_queries() {
+        assert_eq!(
+            route(&request("GET", "/book/LOB", &[("depth", "5")], ""), "LOB"),
+            Ok(RestRequest::Book { depth: Some(5) })
+        );
+        assert_eq!(
+            route(&request("GET", "/top/LOB", &[], ""), "LOB"),
+            Ok(RestRequest::Top)
+        );
+        assert_eq!(
+            route(&request("GET", "/trades", &[], ""), "LOB"),
+            Ok(RestRequest::Trades {
+                limit: DEFAULT_TRADES
+            })
+        );
+        assert!(matches!(
+            route(&request("GET", "/top/ABC", &[], ""), "LOB"),
+            Err(RestReply::NotFound { .. })
+        ));
+        assert!(matches!(
+            route(&request("GET", "/book/LOB", &[("depth", "x")], ""), "LOB"),
+            Err(RestReply::BadRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn route_orders() {
+        let body = r#"{"side": "Ask", "price": "101.25", "quantity": 3}"#;
+        assert_eq!(
+            route(&request("POST", "/orders", &[], body), "LOB"),
+            Ok(RestRequest::PlaceOrder(NewOrder {
+                side: Side::Ask,
+                price: BigDecimal::from_str("101.25").unwrap(),
+                quantity: 3,
+                time_in_force: TimeInForce::GoodTillCancel,
+            }))
+        );
+        assert!(matches!(
+            route(&request("POST", "/orders", &[], "{}"), "LOB"),
+            Err(RestReply::BadRequest { .. })
+        ));
+        assert_eq!(
+            route(&request("DELETE", "/orders/7", &[], ""), "LOB"),
+            Ok(RestRequest::CancelOrder(7))
+        );
+    }
+
+    #[test]
+    fn reply_body() {
+        let reply = RestReply::OrderPlaced {
+            order_id: 4,
+            trades: vec![JsonLevel {
+                price: BigDecimal::from(100),
+                quantity: 2,
+            }],
+        };
+        assert_eq!(reply.status(), "201 Created");
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"order_id":4,"trades":[{"price":"100","quantity":2}]}"#
+        );
+    }
+}
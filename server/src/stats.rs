@@ -0,0 +1,215 @@
+//! Statistics of a trading session and the report written when it closes
+//!
+//! Book statistics are weighted by how long the book stayed in each state, so
+//! a spread that lasted a minute counts sixty times as much as one that lasted
+//! a second. Time while trading is halted or closed doesn't count.
+
+use crate::Quantity;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+/// State of the book until its next change
+#[derive(Debug, Clone, Default)]
+pub struct BookState {
+    /// `None` while a side is empty
+    pub spread: Option<BigDecimal>,
+    pub bid_size: Quantity,
+    pub ask_size: Quantity,
+    /// Accounts quoting both sides with at least the obligated size
+    pub quoting: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct AccountStats {
+    /// Orders, cancels and replaces sent
+    messages: u64,
+    /// Trades the account took part in
+    trades: u64,
+    volume: Quantity,
+    quoted: Duration,
+}
+
+pub struct SessionStats {
+    last_change: Instant,
+    /// Whether the time until the next change counts
+    trading: bool,
+    /// Time spent trading
+    trading_time: Duration,
+    book: BookState,
+    /// Time both sides had orders and the spread summed over that time
+    spread_time: Duration,
+    spread_seconds: f64,
+    bid_size_seconds: f64,
+    ask_size_seconds: f64,
+    trades: u64,
+    volume: Quantity,
+    accounts: BTreeMap<String, AccountStats>,
+}
+
+impl SessionStats {
+    pub fn new(now: Instant, trading: bool) -> Self {
+        SessionStats {
+            last_change: now,
+            trading,
+            trading_time: Duration::default(),
+            book: BookState::default(),
+            spread_time: Duration::default(),
+            spread_seconds: 0.0,
+            bid_size_seconds: 0.0,
+            ask_size_seconds: 0.0,
+            trades: 0,
+            volume: 0,
+            accounts: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the time spent in the previous state of the book
+    fn elapse(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_change);
+        self.last_change = now;
+        if !self.trading {
+            return;
+        }
+        self.trading_time += elapsed;
+        let seconds = elapsed.as_secs_f64();
+        if let Some(spread) = &self.book.spread {
+            self.spread_time += elapsed;
+            self.spread_seconds += spread.to_f64().unwrap_or_default() * seconds;
+        }
+        self.bid_size_seconds += self.book.bid_size as f64 * seconds;
+        self.ask_size_seconds += self.book.ask_size as f64 * seconds;
+        for account in &self.book.quoting {
+            self.accounts.entry(account.clone()).or_default().quoted += elapsed;
+        }
+    }
+
+    /// Trading started, or stopped because it was halted or closed
+    pub fn on_status(&mut self, trading: bool, now: Instant) {
+        self.elapse(now);
+        self.trading = trading;
+    }
+
+    /// The book changed to `book`
+    pub fn on_book(&mut self, book: BookState, now: Instant) {
+        self.elapse(now);
+        self.book = book;
+    }
+
+    pub fn on_message(&mut self, account: &str) {
+        self.accounts
+            .entry(account.to_string())
+            .or_default()
+            .messages += 1;
+    }
+
+    /// A trade between orders of these accounts, which may be the same
+    pub fn on_trade(&mut self, quantity: Quantity, accounts: &[&str]) {
+        self.trades += 1;
+        self.volume += quantity;
+        let accounts: HashSet<_> = accounts.iter().collect();
+        for account in accounts {
+            let stats = self.accounts.entry(account.to_string()).or_default();
+            stats.trades += 1;
+            stats.volume += quantity;
+        }
+    }
+
+    /// Time-weighted average spread over the time both sides had orders
+    pub fn average_spread(&self) -> Option<f64> {
+        match self.spread_time.as_secs_f64() {
+            seconds if seconds > 0.0 => Some(self.spread_seconds / seconds),
+            _ => None,
+        }
+    }
+
+    /// Time-weighted average sizes of the best bid and ask, an empty side
+    /// counting as zero
+    pub fn average_top_sizes(&self) -> (f64, f64) {
+        match self.trading_time.as_secs_f64() {
+            seconds if seconds > 0.0 => (
+                self.bid_size_seconds / seconds,
+                self.ask_size_seconds / seconds,
+            ),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// Summary of the session up to `now` followed by a CSV table with a row
+    /// per account
+    pub fn report(&mut self, now: Instant) -> String {
+        self.elapse(now);
+        let duration = self.trading_time;
+        let (bid_size, ask_size) = self.average_top_sizes();
+        let mut report = String::new();
+        let _ = writeln!(report, "duration_seconds: {:.3}", duration.as_secs_f64());
+        let _ = writeln!(report, "trades: {}", self.trades);
+        let _ = writeln!(report, "volume: {}", self.volume);
+        let _ = match self.average_spread() {
+            Some(spread) => writeln!(report, "average_spread: {:.6}", spread),
+            None => writeln!(report, "average_spread: -"),
+        };
+        let _ = writeln!(report, "average_bid_size: {:.3}", bid_size);
+        let _ = writeln!(report, "average_ask_size: {:.3}", ask_size);
+        let _ = writeln!(report);
+        let _ = writeln!(
+            report,
+            "account,messages,trades,volume,message_to_trade_ratio,quoted_uptime_percent"
+        );
+        for (account, stats) in &self.accounts {
+            let ratio = match stats.trades {
+                0 => "-".to_string(),
+                trades => format!("{:.3}", stats.messages as f64 / trades as f64),
+            };
+            let uptime = match duration.as_secs_f64() {
+                seconds if seconds > 0.0 => stats.quoted.as_secs_f64() / seconds * 100.0,
+                _ => 0.0,
+            };
+            let _ = writeln!(
+                report,
+                "{},{},{},{},{},{:.2}",
+                account, stats.messages, stats.trades, stats.volume, ratio, uptime
+            );
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_weighted() {
+        let start = Instant::now();
+        let mut stats = SessionStats::new(start, true);
+        let mut book = BookState {
+            spread: Some(BigDecimal::from(1)),
+            bid_size: 10,
+            ask_size: 0,
+            quoting: vec!["mm".to_string()].into_iter().collect(),
+        };
+        stats.on_book(book.clone(), start);
+        book.spread = Some(BigDecimal::from(4));
+        book.quoting.clear();
+        stats.on_book(book.clone(), start + Duration::from_secs(3));
+        book.spread = None;
+        stats.on_book(book, start + Duration::from_secs(4));
+        // (1 * 3 + 4 * 1) / 4 while both sides had orders
+        assert_eq!(stats.average_spread(), Some(1.75));
+        assert_eq!(stats.average_top_sizes(), (10.0, 0.0));
+        // A halt doesn't count
+        stats.on_status(false, start + Duration::from_secs(5));
+        stats.on_status(true, start + Duration::from_secs(9));
+
+        stats.on_message("mm");
+        stats.on_message("mm");
+        stats.on_message("taker");
+        stats.on_trade(5, &["mm", "taker"]);
+        stats.on_trade(2, &["taker", "taker"]);
+        let report = stats.report(start + Duration::from_secs(10));
+        assert!(report.contains("trades: 2\nvolume: 7\n"));
+        assert!(report.ends_with("mm,2,1,5,2.000,50.00\ntaker,1,2,7,0.500,0.00\n"));
+    }
+}